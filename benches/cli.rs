@@ -1,10 +1,8 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use squid::{Configuration, MarkdownDocument, Website};
 use std::path::Path;
-use std::sync::Arc;
 use tempdir::TempDir;
 use tokio::runtime::Runtime;
-use tokio::task::JoinSet;
 
 const MARKDOWN_SAMPLE: &str = r#"---
 title: This is such a nice title
@@ -93,6 +91,71 @@ fn full_build(c: &mut Criterion) {
     });
 }
 
+/// a markdown folder with a `posts` collection of the given size
+fn markdown_site(documents: usize) -> TempDir {
+    let site = TempDir::new("bench_site").expect("tempdir");
+    let posts = site.path().join("posts");
+    std::fs::create_dir(&posts).expect("posts folder");
+    let mut content = String::from(MARKDOWN_SAMPLE);
+    for i in 0..5 {
+        content.push_str(&format!("\n\n## Section {}\n\n", i));
+        content.push_str("Lorem ipsum dolor sit amet, consectetur adipiscing elit. ");
+        content.push_str("```rust\nfn main() { println!(\"squid\"); }\n```\n");
+    }
+    for i in 0..documents {
+        std::fs::write(posts.join(format!("post_{i}.md")), &content).expect("markdown file");
+    }
+    site
+}
+
+fn full_build_1k(c: &mut Criterion) {
+    let rt = Runtime::new().expect("runtime");
+    let site = markdown_site(1_000);
+    let config = Configuration::from_toml("tests/config.toml").expect("config");
+
+    let mut group = c.benchmark_group("full_build_1k");
+    group.sample_size(10);
+    group.bench_function("full_build_1k", |b| {
+        b.iter(|| {
+            let tempdir = TempDir::new("bench_output").expect("tempdir");
+            let mut website = Website::new(
+                Some(config.clone()),
+                Path::new("tests/templates").to_path_buf(),
+                Some(site.path().to_path_buf()),
+            );
+
+            let mut set = rt
+                .block_on(website.build_from_scratch(tempdir.path()))
+                .expect("build failed");
+            rt.block_on(async { while set.join_next().await.is_some() {} });
+        })
+    });
+    group.finish();
+}
+
+fn markdown_collections_10k(c: &mut Criterion) {
+    let rt = Runtime::new().expect("runtime");
+    let site = markdown_site(10_000);
+    let config = Configuration::from_toml("tests/config.toml").expect("config");
+
+    let mut group = c.benchmark_group("markdown_collections_10k");
+    group.sample_size(10);
+    group.bench_function("markdown_collections_10k", |b| {
+        b.iter(|| {
+            let mut website = Website::new(
+                Some(config.clone()),
+                Path::new("tests/templates").to_path_buf(),
+                Some(site.path().to_path_buf()),
+            );
+            black_box(
+                rt.block_on(website.build_markdown_collections())
+                    .expect("markdown collections"),
+            )
+        })
+    });
+    group.finish();
+}
+
 fn full_build_without_markdown(c: &mut Criterion) {
     let rt = Runtime::new().expect("runtime");
     let template_folder = Path::new("tests/templates").to_path_buf();
@@ -123,6 +186,8 @@ criterion_group!(
     markdown_parse_single,
     markdown_parse_single_large,
     full_build,
+    full_build_1k,
+    markdown_collections_10k,
     full_build_without_markdown
 );
 criterion_main!(benches);
@@ -0,0 +1,317 @@
+//! Admonitions, also known as callouts.
+//!
+//! Both GitHub alerts (`> [!NOTE]`) and fenced containers (`:::tip Optional title`
+//! closed by `:::`) are supported. They are rendered by `admonitions/<kind>.template`
+//! (inside the template folder) when it exists, with `kind`, `title` and `content` set,
+//! and as an `<aside class="admonition <kind>">` otherwise.
+//!
+//! Containers are replaced by placeholders before the markdown is converted, so their
+//! content is still parsed as markdown. Alerts are found on the generated html.
+
+use crate::fence::CodeBlocks;
+use crate::html::escape;
+use crate::io::LazyFolderReader;
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tinylang::eval;
+use tinylang::types::{State, TinyLangType};
+
+/// folder, inside the template folder, holding the admonition templates
+pub const ADMONITIONS_FOLDER: &str = "admonitions";
+
+fn container_open_regex() -> &'static Regex {
+    static OPEN: OnceLock<Regex> = OnceLock::new();
+    OPEN.get_or_init(|| {
+        Regex::new(r"^\s*:::\s*([A-Za-z][A-Za-z0-9_\-]*)(?:\s+(.*?))?\s*$").unwrap()
+    })
+}
+
+fn container_close_regex() -> &'static Regex {
+    static CLOSE: OnceLock<Regex> = OnceLock::new();
+    CLOSE.get_or_init(|| Regex::new(r"^\s*:::\s*$").unwrap())
+}
+
+fn alert_regex() -> &'static Regex {
+    static ALERT: OnceLock<Regex> = OnceLock::new();
+    ALERT.get_or_init(|| {
+        Regex::new(r"<blockquote>\s*<p>\[!([A-Za-z][A-Za-z0-9_\-]*)\]([^\n<]*)(?:\n|</p>)").unwrap()
+    })
+}
+
+fn open_placeholder(position: usize) -> String {
+    format!("SQUIDADMONITION{position}START")
+}
+
+fn close_placeholder(position: usize) -> String {
+    format!("SQUIDADMONITION{position}END")
+}
+
+/// a `:::` container found in a document
+#[derive(Debug, Clone)]
+pub struct Container {
+    pub kind: String,
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct Admonitions {
+    /// kind -> (template path, template contents)
+    templates: HashMap<String, (PathBuf, String)>,
+    /// state shared by every admonition (website variables and functions)
+    state: State,
+}
+
+impl Admonitions {
+    /// loads every `.template` inside the folder, a missing folder means the default markup
+    pub fn new(folder: &Path, state: State) -> Result<Self> {
+        let mut templates = HashMap::new();
+
+        if folder.is_dir() {
+            let reader = LazyFolderReader::new(folder, "template")
+                .context("could not create lazy folder reader for admonitions folder")?;
+            for file in reader {
+                let file = file?;
+                let kind = file.name.trim_end_matches(".template").to_lowercase();
+                templates.insert(kind, (file.path, file.contents));
+            }
+        }
+
+        Ok(Self { templates, state })
+    }
+
+    /// replaces the `:::` containers of the given kinds by placeholders, returning the
+    /// markdown and the containers in the order of their placeholders
+    pub fn extract(&self, markdown: &str, kinds: &[String]) -> (String, Vec<Container>) {
+        let mut containers = Vec::new();
+        // containers still open, None for `:::` blocks we do not handle
+        let mut open: Vec<Option<usize>> = Vec::new();
+        let mut code = CodeBlocks::default();
+        let mut output = Vec::new();
+
+        for line in markdown.lines() {
+            // `:::` inside code blocks is code
+            if code.contains(line) {
+                output.push(line.to_string());
+                continue;
+            }
+
+            if container_close_regex().is_match(line) && !open.is_empty() {
+                match open.pop().flatten() {
+                    Some(position) => output.push(format!("\n{}\n", close_placeholder(position))),
+                    None => output.push(line.to_string()),
+                }
+                continue;
+            }
+
+            if let Some(caps) = container_open_regex().captures(line) {
+                let kind = caps[1].to_lowercase();
+                if kinds.iter().any(|k| k.eq_ignore_ascii_case(&kind)) {
+                    containers.push(Container {
+                        kind,
+                        title: caps.get(2).map(|t| escape(t.as_str())),
+                    });
+                    open.push(Some(containers.len() - 1));
+                    output.push(format!("\n{}\n", open_placeholder(containers.len() - 1)));
+                } else {
+                    open.push(None);
+                    output.push(line.to_string());
+                }
+                continue;
+            }
+
+            output.push(line.to_string());
+        }
+
+        // containers left open are closed at the end of the document
+        while let Some(position) = open.pop() {
+            if let Some(position) = position {
+                output.push(format!("\n{}\n", close_placeholder(position)));
+            }
+        }
+
+        (output.join("\n"), containers)
+    }
+
+    /// renders the containers in place of their placeholders and the alerts of the given kinds,
+    /// returning the html and the kinds used
+    pub fn render(
+        &self,
+        html: &str,
+        containers: &[Container],
+        kinds: &[String],
+    ) -> Result<(String, Vec<String>)> {
+        let mut html = html.to_string();
+        let mut used = Vec::new();
+
+        for (position, container) in containers.iter().enumerate() {
+            let open = open_placeholder(position);
+            let close = close_placeholder(position);
+            let (start, content_start) = match html.find(&format!("<p>{open}</p>")) {
+                Some(start) => (start, start + open.len() + 7),
+                None => match html.find(&open) {
+                    Some(start) => (start, start + open.len()),
+                    None => continue,
+                },
+            };
+            let (content_end, end) = match html[content_start..].find(&format!("<p>{close}</p>")) {
+                Some(end) => (content_start + end, content_start + end + close.len() + 7),
+                None => match html[content_start..].find(&close) {
+                    Some(end) => (content_start + end, content_start + end + close.len()),
+                    None => continue,
+                },
+            };
+
+            let content = html[content_start..content_end].trim().to_string();
+            let rendered =
+                self.render_admonition(&container.kind, container.title.as_deref(), &content)?;
+            html.replace_range(start..end, &rendered);
+            used.push(container.kind.clone());
+        }
+
+        let mut search_from = 0;
+        while let Some(caps) = alert_regex().captures_at(&html, search_from) {
+            let whole = caps.get(0).unwrap();
+            let (start, marker_end) = (whole.start(), whole.end());
+            // the first paragraph goes on after the marker
+            let continues_paragraph = whole.as_str().ends_with('\n');
+            let kind = caps[1].to_lowercase();
+            let title = caps[2].trim().to_string();
+
+            if !kinds.iter().any(|k| k.eq_ignore_ascii_case(&kind)) {
+                search_from = marker_end;
+                continue;
+            }
+
+            let end = closing_blockquote(&html, marker_end)
+                .ok_or(anyhow!("could not find the end of the `[!{kind}]` alert"))?;
+            let content = if continues_paragraph {
+                format!("<p>{}", &html[marker_end..end])
+            } else {
+                html[marker_end..end].to_string()
+            };
+
+            let title = Some(title.as_str()).filter(|t| !t.is_empty());
+            let rendered = self.render_admonition(&kind, title, content.trim())?;
+            html.replace_range(start..end + "</blockquote>".len(), &rendered);
+            search_from = start;
+            used.push(kind);
+        }
+
+        used.sort();
+        used.dedup();
+        Ok((html, used))
+    }
+
+    fn render_admonition(&self, kind: &str, title: Option<&str>, content: &str) -> Result<String> {
+        let title = title
+            .map(str::to_string)
+            .unwrap_or_else(|| capitalize(kind));
+
+        let (_, template) = match self.templates.get(kind) {
+            Some(t) => t,
+            None => {
+                return Ok(format!(
+                    "<aside class=\"admonition {kind}\">\n<p class=\"admonition-title\">{title}</p>\n{content}\n</aside>"
+                ))
+            }
+        };
+
+        let mut state = self.state.clone();
+        state.insert("kind".into(), kind.to_string().into());
+        state.insert("title".into(), TinyLangType::String(title));
+        state.insert("content".into(), content.to_string().into());
+
+        eval(template, state).map_err(|e| anyhow!("could not render admonition `{kind}`: {e}"))
+    }
+}
+
+/// position of the `</blockquote>` closing the blockquote whose content starts at `from`
+fn closing_blockquote(html: &str, from: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut position = from;
+    loop {
+        let next_open = html[position..].find("<blockquote>").map(|p| p + position);
+        let next_close = html[position..]
+            .find("</blockquote>")
+            .map(|p| p + position)?;
+        match next_open {
+            Some(open) if open < next_close => {
+                depth += 1;
+                position = open + "<blockquote>".len();
+            }
+            _ if depth == 0 => return Some(next_close),
+            _ => {
+                depth -= 1;
+                position = next_close + "</blockquote>".len();
+            }
+        }
+    }
+}
+
+fn capitalize(kind: &str) -> String {
+    let mut chars = kind.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AdmonitionsConfiguration;
+    use std::fs::{create_dir, File};
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn render(admonitions: &Admonitions, markdown: &str) -> String {
+        let kinds = AdmonitionsConfiguration::default().kinds;
+        let (markdown, containers) = admonitions.extract(markdown, &kinds);
+        let html = markdown::to_html(&markdown);
+        admonitions.render(&html, &containers, &kinds).unwrap().0
+    }
+
+    #[test]
+    fn test_renders_github_alerts() {
+        let html = render(
+            &Admonitions::default(),
+            "> [!WARNING] Careful\n> Hot *stuff*\n>\n> > nested quote\n\n> [!unknown]\n> kept",
+        );
+
+        assert!(html.starts_with(
+            "<aside class=\"admonition warning\">\n<p class=\"admonition-title\">Careful</p>\n<p>Hot <em>stuff</em></p>"
+        ));
+        assert!(html.contains("<blockquote>\n<p>nested quote</p>\n</blockquote>\n</aside>"));
+        assert!(html.contains("<blockquote>\n<p>[!unknown]"));
+    }
+
+    #[test]
+    fn test_renders_containers_with_templates() {
+        let tempdir = TempDir::new("admonitions").unwrap();
+        let folder = tempdir.path().join(ADMONITIONS_FOLDER);
+        create_dir(&folder).unwrap();
+        let mut file = File::create(folder.join("tip.template")).unwrap();
+        write!(
+            file,
+            "<div class=\"{{{{ kind }}}}\"><b>{{{{ title }}}}</b>{{{{ content }}}}</div>"
+        )
+        .unwrap();
+        let admonitions = Admonitions::new(&folder, State::new()).unwrap();
+
+        let html = render(
+            &admonitions,
+            ":::tip\nUse **this**\n:::\n\n```\n:::note\n```\n\n    :::tip\n    :::\n\n:::note A <title>\ntext\n:::",
+        );
+
+        assert!(
+            html.starts_with("<div class=\"tip\"><b>Tip</b><p>Use <strong>this</strong></p></div>")
+        );
+        assert!(html.contains("<pre><code>:::note\n</code></pre>"));
+        assert!(html.contains("<pre><code>:::tip\n:::\n</code></pre>"));
+        assert!(html
+            .contains("<p class=\"admonition-title\">A &lt;title&gt;</p>\n<p>text</p>\n</aside>"));
+    }
+}
@@ -1,10 +1,12 @@
 use crate::config::Configuration;
 use crate::deps::{FileChangeEvent, FileChangeType};
+use crate::diagnostic::Diagnostic;
 use crate::http;
-use crate::io::copy_dir;
+use crate::io::{copy_dir, copy_markdown_assets, OutputFile, WriteSummary};
+use crate::outputs::{self, BuildManifest};
 use crate::template::Website;
 use crate::watch::FolderWatcher;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::Path;
 use std::process::exit;
 use tokio::runtime::Handle;
@@ -14,9 +16,13 @@ use tokio::task::JoinSet;
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 pub(crate) struct Args {
-    #[arg(short, long)]
-    template_folder: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, required = true)]
+    template_folder: Option<String>,
 
     #[arg(short, long)]
     markdown_folder: Option<String>,
@@ -27,20 +33,49 @@ pub(crate) struct Args {
     #[arg(short = 'v', long)]
     template_variables: Option<String>,
 
+    #[arg(short, long, required = true)]
+    output_folder: Option<String>,
+
     #[arg(short, long)]
-    output_folder: String,
+    data_folder: Option<String>,
+
+    #[arg(short, long)]
+    gallery_folder: Option<String>,
 
     #[arg(short, long)]
     watch: bool,
 
     #[arg(short = 'p', long)]
     serve: Option<u16>,
+
+    /// build for publishing, e.g. without source maps and with minified pages
+    #[arg(long)]
+    production: bool,
+
+    /// convert and render everything, ignoring the cache of the previous builds
+    #[arg(long)]
+    no_cache: bool,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// removes the files squid generated in the output folder, leaving the others alone
+    Clean {
+        #[arg(short, long)]
+        output_folder: String,
+    },
 }
 
 pub struct App {
     args: Args,
 }
 
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl App {
     pub fn new() -> Self {
         Self {
@@ -49,15 +84,29 @@ impl App {
     }
 
     pub async fn run(&mut self) {
-        let output_folder = Path::new(&self.args.output_folder);
+        if let Some(Command::Clean { output_folder }) = &self.args.command {
+            match BuildManifest::load(Path::new(output_folder)).clean() {
+                Ok(removed) => println!("Removed {removed} files"),
+                Err(e) => {
+                    eprintln!("could not clean {output_folder}: {e:?}");
+                    exit(1);
+                }
+            }
+            return;
+        }
+
+        let output_folder = Path::new(self.output_folder());
         let website = self.build_website(output_folder).await;
         self.copy_static_files(output_folder);
+        self.copy_markdown_assets(output_folder);
+        website.compress_outputs(output_folder);
+        Self::remove_stale_outputs(output_folder);
 
         let mut async_server = None;
 
         if let Some(port) = self.args.serve.as_ref() {
             println!("Serving website at http://127.0.0.1:{port}");
-            let folder = &self.args.output_folder;
+            let folder = self.output_folder();
             async_server = Some(http::serve(*port, folder));
         }
 
@@ -78,8 +127,38 @@ impl App {
         }
     }
 
+    /// required unless running a subcommand
+    fn output_folder(&self) -> &str {
+        self.args.output_folder.as_deref().unwrap()
+    }
+
+    fn template_folder(&self) -> &str {
+        self.args.template_folder.as_deref().unwrap()
+    }
+
+    /// removes the outputs of the previous build this one did not produce
+    fn remove_stale_outputs(output_folder: &Path) {
+        let mut manifest = BuildManifest::load(output_folder);
+        let removed = manifest.replace_with_recorded();
+        for file in &removed {
+            println!("removed stale output {}", file.display());
+        }
+        if let Err(e) = manifest.save() {
+            eprintln!("{e:?}");
+        }
+    }
+
+    /// adds the outputs of an incremental build to the manifest
+    fn record_outputs(output_folder: &Path) {
+        let mut manifest = BuildManifest::load(output_folder);
+        manifest.extend_with_recorded();
+        if let Err(e) = manifest.save() {
+            eprintln!("{e:?}");
+        }
+    }
+
     async fn build_website(&self, output_folder: &Path) -> Website {
-        let template_folder = Path::new(&self.args.template_folder);
+        let template_folder = Path::new(self.template_folder());
 
         let config = self
             .args
@@ -92,21 +171,66 @@ impl App {
             .as_ref()
             .map(|f| Path::new(&f).to_path_buf());
 
-        let mut website = Website::new(config, template_folder.to_path_buf(), markdown_folder);
+        let data_folder = self
+            .args
+            .data_folder
+            .as_ref()
+            .map(|f| Path::new(&f).to_path_buf());
+
+        let static_folder = self
+            .args
+            .static_resources
+            .as_ref()
+            .map(|f| Path::new(&f).to_path_buf());
+
+        let gallery_folder = self
+            .args
+            .gallery_folder
+            .as_ref()
+            .map(|f| Path::new(&f).to_path_buf());
+
+        let mut website = Website::new(config, template_folder.to_path_buf(), markdown_folder)
+            .with_data_folder(data_folder)
+            .with_static_folder(static_folder)
+            .with_gallery_folder(gallery_folder)
+            .with_production(self.args.production)
+            .with_serving(self.args.serve.is_some())
+            .with_build_cache(!self.args.no_cache);
         let mut files_processed = website.build_from_scratch(output_folder).await.unwrap();
 
         Self::process_website_files(&mut files_processed).await;
+        if let Err(e) = website.save_cache() {
+            eprintln!("could not save the build cache: {e:?}");
+        }
+
+        // while watching, errors are shown and fixed as we go
+        let has_errors = Self::report_diagnostics(website.take_diagnostics());
+        if has_errors && !self.args.watch && self.args.serve.is_none() {
+            exit(1);
+        }
 
         website
     }
 
-    async fn process_website_files(files_processed: &mut JoinSet<String>) {
+    /// prints the diagnostics, returning whether any of them is an error
+    fn report_diagnostics(diagnostics: Vec<Diagnostic>) -> bool {
+        for diagnostic in &diagnostics {
+            eprintln!("{diagnostic}");
+        }
+        diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    async fn process_website_files(files_processed: &mut JoinSet<OutputFile>) {
         let mut failed = false;
+        let mut summary = WriteSummary::default();
 
         while let Some(res) = files_processed.join_next().await {
             match res {
                 Ok(file) => {
-                    println!("successfully processed {file}");
+                    if file.written {
+                        println!("successfully processed {}", file.name);
+                    }
+                    summary.add(file.written);
                 }
                 Err(e) => {
                     eprintln!("task failed {e:?}");
@@ -115,6 +239,8 @@ impl App {
             };
         }
 
+        println!("Pages: {summary}");
+
         if failed {
             exit(1);
         }
@@ -135,11 +261,30 @@ impl App {
                 );
                 exit(1);
             }
-            Some(_) => println!("Copied static resources"),
+            Some(Ok(summary)) => println!("Copied static resources: {summary}"),
             _ => println!("No static resources to be copied over"),
         }
     }
 
+    /// publishes the files relative links of the documents point to, e.g. their images
+    fn copy_markdown_assets(&self, output_folder: &Path) {
+        let markdown_folder = match self.args.markdown_folder.as_ref() {
+            Some(dir) => Path::new(dir),
+            None => return,
+        };
+
+        match copy_markdown_assets(markdown_folder, output_folder) {
+            Ok(summary) => println!("Copied markdown assets: {summary}"),
+            Err(e) => {
+                eprintln!(
+                    "task failed, could not copy markdown assets {:?}",
+                    e.to_string()
+                );
+                exit(1);
+            }
+        }
+    }
+
     /// watches for change in the directories selected by the user
     /// in order to re-build the website
     async fn watch_website_files(&self, mut website: Website) {
@@ -147,7 +292,7 @@ impl App {
         let mut watcher = FolderWatcher::new(Handle::current(), tx);
 
         watcher
-            .watch(&self.args.template_folder, FileChangeType::Template)
+            .watch(self.template_folder(), FileChangeType::Template)
             .unwrap();
 
         if let Some(markdown_folder) = self.args.markdown_folder.as_ref() {
@@ -160,22 +305,57 @@ impl App {
             watcher.watch(template_var, FileChangeType::Config).unwrap();
         }
 
+        if let Some(data_folder) = self.args.data_folder.as_ref() {
+            watcher.watch(data_folder, FileChangeType::Data).unwrap();
+        }
+
+        if let Some(gallery_folder) = self.args.gallery_folder.as_ref() {
+            watcher
+                .watch(gallery_folder, FileChangeType::Gallery)
+                .unwrap();
+        }
+
         if let Some(static_resources) = self.args.static_resources.as_ref() {
             watcher
                 .watch(static_resources, FileChangeType::Static)
                 .unwrap();
         }
 
-        let output_folder = Path::new(&self.args.output_folder);
+        let output_folder = Path::new(self.output_folder());
 
         while let Some(change) = rx.recv().await {
             println!("Detected changes on files, rebuilding site");
             self.handle_file_change(&mut website, &change, output_folder)
                 .await;
+            website.compress_outputs(output_folder);
+            Self::record_outputs(output_folder);
+            if let Err(e) = website.save_cache() {
+                eprintln!("could not save the build cache: {e:?}");
+            }
             println!("Site rebuilt");
         }
     }
 
+    /// removes the copies of the static files that were deleted
+    fn remove_deleted_static_files(&self, change: &FileChangeEvent, output_folder: &Path) {
+        let Some(static_folder) = self.args.static_resources.as_ref().map(Path::new) else {
+            return;
+        };
+        let canonical = static_folder.canonicalize().ok();
+        for path in change.paths.iter().filter(|p| !p.exists()) {
+            let relative = canonical
+                .as_ref()
+                .and_then(|folder| path.strip_prefix(folder).ok())
+                .or_else(|| path.strip_prefix(static_folder).ok());
+            if let Some(relative) = relative {
+                let output = output_folder.join(relative);
+                if outputs::remove(&output) {
+                    println!("removed {}", output.display());
+                }
+            }
+        }
+    }
+
     async fn handle_file_change(
         &self,
         website: &mut Website,
@@ -184,9 +364,21 @@ impl App {
     ) {
         match change.change_type {
             FileChangeType::Static => {
+                self.remove_deleted_static_files(change, output_folder);
                 self.copy_static_files(output_folder);
+                if let Err(e) = website.rebuild_stylesheets(change, output_folder).await {
+                    eprintln!("Failed to compile stylesheets: {e}");
+                }
+                match website.rebuild_assets(output_folder).await {
+                    Ok(mut files_processed) => {
+                        Self::process_website_files(&mut files_processed).await;
+                    }
+                    Err(e) => eprintln!("Failed to bundle assets: {e}"),
+                }
+                Self::report_diagnostics(website.take_diagnostics());
             }
             FileChangeType::Markdown => {
+                self.copy_markdown_assets(output_folder);
                 match website.rebuild_after_markdown_change(output_folder).await {
                     Ok(()) => match website.compile_templates().await {
                         Ok(mut files_processed) => {
@@ -210,8 +402,31 @@ impl App {
                         }
                     }
                 }
+                Self::report_diagnostics(website.take_diagnostics());
+            }
+            FileChangeType::Data => {
+                if let Err(e) = website.rebuild_after_data_change().await {
+                    eprintln!("Failed to reload data files: {e}, falling back to full rebuild");
+                    if let Ok(mut files_processed) = website.build_from_scratch(output_folder).await
+                    {
+                        Self::process_website_files(&mut files_processed).await;
+                    }
+                    Self::report_diagnostics(website.take_diagnostics());
+                    return;
+                }
+                match website.build_incremental(change, output_folder).await {
+                    Ok(Some(mut files_processed)) => {
+                        Self::process_website_files(&mut files_processed).await;
+                    }
+                    Ok(None) | Err(_) => {
+                        let mut files_processed =
+                            website.build_from_scratch(output_folder).await.unwrap();
+                        Self::process_website_files(&mut files_processed).await;
+                    }
+                }
+                Self::report_diagnostics(website.take_diagnostics());
             }
-            FileChangeType::Template | FileChangeType::Config => {
+            FileChangeType::Template | FileChangeType::Config | FileChangeType::Gallery => {
                 match website.build_incremental(change, output_folder).await {
                     Ok(Some(mut files_processed)) => {
                         Self::process_website_files(&mut files_processed).await;
@@ -0,0 +1,903 @@
+//! Bundled and minified stylesheets and scripts of the static folder.
+//!
+//! Every `.css` file, the compiled Sass stylesheets included, has its `@import` rules
+//! inlined and is minified. Every `.js` file is bundled with the local modules it
+//! imports: they are put before it in the same file, each in its own function scope
+//! returning its exports, and imports become bindings to those exports. Only named imports
+//! of relative paths are bundled, anything else is left to the browser. Scripts using
+//! default or namespace imports, or default exports, are copied without bundling.
+//!
+//! The result is written next to the copied file, `main.css` becoming `main.min.css`,
+//! with a source map unless building for production. With fingerprinting, it is named after
+//! its content hash instead, `main.min.3f9a1c2b.css`, so it can be cached forever. The other
+//! files of the static folder, images, fonts..., then get a fingerprinted copy as well.
+//! Templates get the path of the result through `asset()`, and its integrity hash through
+//! `asset_integrity()`.
+
+use crate::config::AssetsConfiguration;
+use crate::diagnostic::Diagnostic;
+use crate::io::write_if_changed;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use lightningcss::bundler::{Bundler, FileProvider};
+use lightningcss::error::ErrorLocation;
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions};
+use minify_js::{Session, TopLevelMode};
+use parcel_sourcemap::{OriginalLocation, SourceMap};
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256, Sha384};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tinylang::types::{FuncArguments, State, TinyLangType};
+
+/// key of the state holding the asset paths, functions cannot capture them
+const STATE_KEY: &str = "_assets";
+
+/// key of the state holding the integrity hashes of the assets
+const INTEGRITY_STATE_KEY: &str = "_asset_integrity";
+
+/// file, at the root of the output, listing the fingerprinted assets
+pub const MANIFEST_FILE: &str = "asset-manifest.json";
+
+/// length of the content hash in fingerprinted names
+const FINGERPRINT_LENGTH: usize = 8;
+
+static IMPORT: OnceLock<Regex> = OnceLock::new();
+static EXPORT: OnceLock<Regex> = OnceLock::new();
+
+/// `import { a, b as c } from './module.js';`, and `import './module.js';`
+fn import_regex() -> &'static Regex {
+    IMPORT.get_or_init(|| {
+        Regex::new(r#"(?m)^[ \t]*import\s+(?:([\w$*{}\s,]+?)\s*from\s*)?["']([^"']+)["'][ \t]*;?"#)
+            .unwrap()
+    })
+}
+
+/// `export` in front of a declaration, of a list of names or of the default value
+fn export_regex() -> &'static Regex {
+    EXPORT.get_or_init(|| {
+        Regex::new(
+            r#"(?m)^([ \t]*)export\s+(?:(default)\b|(\{[^}]*\}(?:\s*from\s*["'][^"']+["'])?[ \t]*;?)|((?:async\s+)?(?:function|class|const|let|var)\b\s*\*?\s*([\w$]+)?))"#,
+        )
+        .unwrap()
+    })
+}
+
+/// byte ranges of the comments, strings and template literals of a script, where `import`
+/// and `export` are only text. Regular expression literals are not told apart from divisions
+fn text_ranges(source: &str) -> Vec<Range<usize>> {
+    let bytes = source.as_bytes();
+    let mut ranges = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let start = position;
+        let end = match (bytes[start], bytes.get(start + 1)) {
+            (b'/', Some(b'/')) => source[start..]
+                .find('\n')
+                .map_or(bytes.len(), |e| start + e),
+            (b'/', Some(b'*')) => source[start + 2..]
+                .find("*/")
+                .map_or(bytes.len(), |e| start + e + 4),
+            (quote @ (b'\'' | b'"' | b'`'), _) => {
+                let mut end = start + 1;
+                while end < bytes.len() && bytes[end] != quote {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                (end + 1).min(bytes.len())
+            }
+            _ => {
+                position += 1;
+                continue;
+            }
+        };
+        ranges.push(start..end);
+        position = end;
+    }
+    ranges
+}
+
+fn is_text(ranges: &[Range<usize>], offset: usize) -> bool {
+    ranges.iter().any(|range| range.contains(&offset))
+}
+
+/// stylesheets and scripts, minified files are already processed
+pub fn is_asset(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    (name.ends_with(".css") || name.ends_with(".js")) && !name.contains(".min.")
+}
+
+pub struct Assets {
+    static_folder: PathBuf,
+    output_folder: PathBuf,
+    config: AssetsConfiguration,
+    production: bool,
+}
+
+/// result of building assets
+#[derive(Debug, Default)]
+pub struct AssetBuild {
+    /// path of the source, relative to the website root -> url of the result
+    pub manifest: HashMap<String, String>,
+    /// path of the source -> `sha384-` hash of the result
+    pub integrity: HashMap<String, String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Assets {
+    pub fn new(
+        static_folder: PathBuf,
+        output_folder: PathBuf,
+        config: AssetsConfiguration,
+        production: bool,
+    ) -> Self {
+        Self {
+            static_folder,
+            output_folder,
+            config,
+            production,
+        }
+    }
+
+    /// every stylesheet and script of the static folder, and every other file of it but
+    /// the hidden ones when fingerprinting
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let mut sources = Vec::new();
+        let mut folders = vec![self.static_folder.clone()];
+        while let Some(folder) = folders.pop() {
+            for entry in fs::read_dir(&folder).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    folders.push(path);
+                } else if is_asset(&path) || self.config.fingerprint && !is_hidden(&path) {
+                    sources.push(path);
+                }
+            }
+        }
+        sources.sort();
+        sources
+    }
+
+    /// builds the sources, either files of the static folder or generated in the output
+    pub fn build(&self, sources: &[PathBuf]) -> AssetBuild {
+        let mut build = AssetBuild::default();
+
+        for source in sources {
+            let relative = self.relative_path(source);
+            let result = match relative.extension().and_then(|e| e.to_str()) {
+                Some("css") if self.config.enabled && is_asset(source) => self.stylesheet(source),
+                Some("js") if self.config.enabled && is_asset(source) => self.script(source),
+                Some("css" | "js") if is_asset(source) => self.copy(source),
+                _ if self.config.fingerprint => {
+                    let result = self.fingerprint(source, &relative);
+                    self.record(&mut build, &relative, result);
+                    continue;
+                }
+                _ => continue,
+            };
+            let result = result.and_then(|mut bundle| {
+                build.diagnostics.append(&mut bundle.warnings);
+                self.write(source, &relative, bundle)
+            });
+            let result = result.map(|(url, code)| (url, code.into_bytes()));
+            self.record(&mut build, &relative, result);
+        }
+
+        build
+    }
+
+    fn record(
+        &self,
+        build: &mut AssetBuild,
+        relative: &Path,
+        result: Result<(String, Vec<u8>), Diagnostic>,
+    ) {
+        match result {
+            Ok((url, contents)) => {
+                let path = url_path(relative);
+                if self.config.integrity {
+                    build.integrity.insert(path.clone(), integrity(&contents));
+                }
+                build.manifest.insert(path, url);
+            }
+            Err(diagnostic) => build.diagnostics.push(diagnostic),
+        }
+    }
+
+    /// lists the fingerprinted assets at the root of the output
+    pub fn write_manifest(&self, manifest: &HashMap<String, String>) -> Result<(), Diagnostic> {
+        if !self.config.fingerprint {
+            return Ok(());
+        }
+        let output = self.output_folder.join(MANIFEST_FILE);
+        let manifest: BTreeMap<_, _> = manifest.iter().collect();
+        serde_json::to_string_pretty(&manifest)
+            .map_err(|e| e.to_string())
+            .and_then(|json| write_if_changed(&output, json).map_err(|e| e.to_string()))
+            .map(|_| ())
+            .map_err(|e| Diagnostic::error(&output, format!("could not write the manifest: {e}")))
+    }
+
+    /// the source as it is, to be fingerprinted
+    fn copy(&self, source: &Path) -> Result<Bundle, Diagnostic> {
+        let code = fs::read_to_string(source)
+            .map_err(|e| Diagnostic::error(source, format!("could not read the asset: {e}")))?;
+        Ok(Bundle {
+            code,
+            source_map: None,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// publishes a file that is neither a stylesheet nor a script under its fingerprinted
+    /// name, returning its url and contents
+    fn fingerprint(&self, source: &Path, relative: &Path) -> Result<(String, Vec<u8>), Diagnostic> {
+        let contents = fs::read(source)
+            .map_err(|e| Diagnostic::error(source, format!("could not read the asset: {e}")))?;
+        let relative = fingerprinted(relative, &contents);
+        let output = self.output_folder.join(&relative);
+        output
+            .parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| write_if_changed(&output, &contents))
+            .map_err(|e| Diagnostic::error(source, format!("could not write {:?}: {e}", output)))?;
+        Ok((format!("/{}", url_path(&relative)), contents))
+    }
+
+    /// where the source is published, relative to the output
+    fn relative_path(&self, source: &Path) -> PathBuf {
+        source
+            .strip_prefix(&self.static_folder)
+            .or_else(|_| source.strip_prefix(&self.output_folder))
+            .unwrap_or(source)
+            .to_path_buf()
+    }
+
+    fn source_map(&self) -> Option<SourceMap> {
+        (!self.production).then(|| SourceMap::new(&self.static_folder.to_string_lossy()))
+    }
+
+    fn stylesheet(&self, source: &Path) -> Result<Bundle, Diagnostic> {
+        let provider = FileProvider::new();
+        let mut source_map = self.source_map();
+        let mut stylesheet = Bundler::new(&provider, source_map.as_mut(), ParserOptions::default())
+            .bundle(source)
+            .map_err(|e| css_diagnostic(source, e.kind.to_string(), e.loc))?;
+
+        if self.config.minify {
+            stylesheet
+                .minify(MinifyOptions::default())
+                .map_err(|e| css_diagnostic(source, e.kind.to_string(), e.loc))?;
+        }
+        let css = stylesheet
+            .to_css(PrinterOptions {
+                minify: self.config.minify,
+                source_map: source_map.as_mut(),
+                ..Default::default()
+            })
+            .map_err(|e| css_diagnostic(source, e.kind.to_string(), e.loc))?;
+
+        Ok(Bundle {
+            code: css.code,
+            source_map,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// scripts with syntax the bundler does not support are copied, with a warning
+    fn script(&self, source: &Path) -> Result<Bundle, Diagnostic> {
+        let mut modules = Modules::default();
+        if let Err(mut diagnostic) = modules.add(source) {
+            if diagnostic.is_error() {
+                return Err(diagnostic);
+            }
+            diagnostic.message = format!(
+                "{}, {:?} is copied without bundling",
+                diagnostic.message,
+                self.relative_path(source)
+            );
+            let mut bundle = self.copy(source)?;
+            bundle.warnings.push(diagnostic);
+            return Ok(bundle);
+        }
+        let code = modules.code();
+
+        // minifying would throw away the lines the source map points at
+        if self.production && self.config.minify {
+            let session = Session::new();
+            let mut minified = Vec::new();
+            minify_js::minify(
+                &session,
+                TopLevelMode::Module,
+                code.as_bytes(),
+                &mut minified,
+            )
+            .map_err(|e| Diagnostic::error(source, format!("{e:?}")))?;
+            return Ok(Bundle {
+                code: String::from_utf8_lossy(&minified).to_string(),
+                source_map: None,
+                warnings: Vec::new(),
+            });
+        }
+
+        let source_map = self.source_map().map(|mut map| {
+            modules.map_lines(&mut map);
+            map
+        });
+        Ok(Bundle {
+            code,
+            source_map,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// writes the bundle next to the copied source, returning its url and contents
+    fn write(
+        &self,
+        source: &Path,
+        relative: &Path,
+        bundle: Bundle,
+    ) -> Result<(String, String), Diagnostic> {
+        let extension = relative.extension().unwrap_or_default().to_string_lossy();
+        let mut relative = match self.config.enabled {
+            true => relative.with_extension(format!("min.{extension}")),
+            false => relative.to_path_buf(),
+        };
+        let mut code = bundle.code;
+        if self.config.enabled {
+            code = code.trim_end().to_string();
+        }
+        // the hash covers the source map comment too, both depend on the contents
+        if self.config.fingerprint {
+            relative = fingerprinted(&relative, code.as_bytes());
+        }
+        let output = self.output_folder.join(&relative);
+        let name = output
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let mut written = output.parent().map(fs::create_dir_all).unwrap_or(Ok(()));
+        if let Some(mut source_map) = bundle.source_map {
+            let json = source_map
+                .to_json(None)
+                .map_err(|e| Diagnostic::error(source, format!("{e:?}")))?;
+            code.push_str(&match extension.as_ref() {
+                "css" => format!("\n/*# sourceMappingURL={name}.map */"),
+                _ => format!("\n//# sourceMappingURL={name}.map"),
+            });
+            written = written.and_then(|_| {
+                write_if_changed(&output.with_file_name(format!("{name}.map")), json).map(|_| ())
+            });
+        }
+        if self.config.enabled {
+            code.push('\n');
+        }
+        written
+            .and_then(|_| write_if_changed(&output, &code))
+            .map_err(|e| Diagnostic::error(source, format!("could not write {:?}: {e}", output)))?;
+
+        Ok((format!("/{}", url_path(&relative)), code))
+    }
+}
+
+struct Bundle {
+    code: String,
+    source_map: Option<SourceMap>,
+    /// reported without failing the asset
+    warnings: Vec<Diagnostic>,
+}
+
+/// subresource integrity hash, for the `integrity` attribute
+fn integrity(contents: &[u8]) -> String {
+    format!("sha384-{}", STANDARD.encode(Sha384::digest(contents)))
+}
+
+/// `logo.png` -> `logo.3f9a1c2b.png`, named after the content hash
+fn fingerprinted(path: &Path, contents: &[u8]) -> PathBuf {
+    let hash = format!("{:x}", Sha256::digest(contents));
+    let hash = &hash[..FINGERPRINT_LENGTH];
+    match path.extension() {
+        Some(extension) => path.with_extension(format!("{hash}.{}", extension.to_string_lossy())),
+        None => path.with_extension(hash),
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+fn css_diagnostic(source: &Path, message: String, location: Option<ErrorLocation>) -> Diagnostic {
+    match location {
+        Some(loc) => {
+            Diagnostic::error(loc.filename, message).at(loc.line as usize + 1, loc.column as usize)
+        }
+        None => Diagnostic::error(source, message),
+    }
+}
+
+/// `css/main.css`, whatever the platform
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// modules of a script, each one after the modules it imports
+#[derive(Default)]
+struct Modules {
+    /// path, source and bundled code, with as many lines as the source plus the closing
+    /// line of the scope of imported modules
+    modules: Vec<(PathBuf, String, String)>,
+    /// modules being added, importing one of them again is a cycle
+    visiting: HashSet<PathBuf>,
+    /// imported modules -> the name of the object holding their exports
+    names: HashMap<PathBuf, String>,
+    /// modules -> their exported names and the local names they export
+    exports: HashMap<PathBuf, Vec<(String, String)>>,
+}
+
+impl Modules {
+    fn add(&mut self, path: &Path) -> Result<(), Diagnostic> {
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+        if self.modules.iter().any(|(p, _, _)| *p == path) {
+            return Ok(());
+        }
+        // the script itself is not imported, it keeps the top level scope
+        let imported = !self.visiting.is_empty();
+        if !self.visiting.insert(path.clone()) {
+            return Err(Diagnostic::error(&path, "the module imports itself"));
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|e| Diagnostic::error(&path, format!("could not read the module: {e}")))?;
+        let folder = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let line = |offset: usize| source[..offset].matches('\n').count() + 1;
+
+        // unsupported syntax is a warning, the script is then copied as it is
+        let mut unsupported = None;
+        // module, names it imports from it and line of the import
+        let mut dependencies = Vec::new();
+        let names = &mut self.names;
+        let text = text_ranges(&source);
+        let code = import_regex().replace_all(&source, |captures: &Captures| {
+            let specifier = &captures[2];
+            let statement = captures.get(0).unwrap();
+            if !specifier.starts_with("./") && !specifier.starts_with("../")
+                || is_text(&text, statement.start())
+            {
+                return captures[0].to_string();
+            }
+            let Some(imports) = imported_names(captures.get(1).map(|c| c.as_str())) else {
+                unsupported.get_or_insert(
+                    Diagnostic::warning(&path, "only named imports of local modules are bundled")
+                        .at(line(statement.start()), 1),
+                );
+                return String::new();
+            };
+            let dependency = folder.join(specifier);
+            let dependency = dependency.canonicalize().unwrap_or(dependency);
+            let count = names.len();
+            let name = names
+                .entry(dependency.clone())
+                .or_insert_with(|| format!("__squid_module_{count}"));
+            let bindings = match imports.is_empty() {
+                true => String::new(),
+                false => format!("const {{ {} }} = {name};", properties(&imports)),
+            };
+            dependencies.push((dependency, imports, line(statement.start())));
+            bindings + &"\n".repeat(statement.as_str().matches('\n').count())
+        });
+
+        let mut exports = Vec::new();
+        // the imports changed the offsets, not the lines
+        let text = text_ranges(&code);
+        let code = export_regex().replace_all(&code, |captures: &Captures| {
+            let statement = captures.get(0).unwrap();
+            if is_text(&text, statement.start()) {
+                return captures[0].to_string();
+            }
+            if captures.get(2).is_some() || captures[0].contains("from") {
+                unsupported.get_or_insert(
+                    Diagnostic::warning(&path, "only named exports are bundled")
+                        .at(code[..statement.start()].matches('\n').count() + 1, 1),
+                );
+            }
+            if let Some(list) = captures.get(3) {
+                let list = list.as_str().trim_end_matches([' ', '\t', ';']);
+                let names = imported_names(Some(list)).unwrap_or_default();
+                // `export { a as b }` exports `a` as `b`
+                exports.extend(names.into_iter().map(|(local, name)| (name, local)));
+            }
+            if let Some(name) = captures.get(5) {
+                exports.push((name.as_str().to_string(), name.as_str().to_string()));
+            }
+            match captures.get(4) {
+                Some(declaration) => format!("{}{}", &captures[1], declaration.as_str()),
+                None => "\n".repeat(statement.as_str().matches('\n').count()),
+            }
+        });
+        if let Some(unsupported) = unsupported {
+            return Err(unsupported);
+        }
+
+        for (dependency, imports, line) in &dependencies {
+            if !dependency.is_file() {
+                return Err(Diagnostic::error(
+                    &path,
+                    format!("cannot find the module {:?}", dependency),
+                )
+                .at(*line, 1));
+            }
+            self.add(dependency)?;
+            let exported = &self.exports[dependency];
+            if let Some((name, _)) = imports
+                .iter()
+                .find(|(name, _)| !exported.iter().any(|(export, _)| export == name))
+            {
+                return Err(Diagnostic::error(
+                    &path,
+                    format!("{:?} does not export {name}", dependency),
+                )
+                .at(*line, 1));
+            }
+        }
+
+        // imported modules get their own scope, only their exports leave it
+        let code = match imported {
+            true => format!(
+                "const {} = (() => {{ {}\nreturn {{ {} }}; }})();",
+                self.names[&path],
+                code.trim_end_matches('\n'),
+                properties(&exports)
+            ),
+            false => code.to_string(),
+        };
+        self.visiting.remove(&path);
+        self.exports.insert(path.clone(), exports);
+        self.modules.push((path, source, code));
+        Ok(())
+    }
+
+    fn code(&self) -> String {
+        self.modules
+            .iter()
+            .map(|(_, _, code)| code.trim_end_matches('\n'))
+            .collect::<Vec<&str>>()
+            .join("\n")
+            + "\n"
+    }
+
+    /// maps each line of the bundle to the line of its module
+    fn map_lines(&self, map: &mut SourceMap) {
+        let mut generated_line = 0;
+        for (path, source, code) in &self.modules {
+            let index = map.add_source(&path.to_string_lossy());
+            let _ = map.set_source_content(index as usize, source);
+            // the line closing the scope belongs to the last line of the module
+            let last_line = source.trim_end_matches('\n').lines().count().max(1) as u32 - 1;
+            for line in 0..code.trim_end_matches('\n').lines().count() as u32 {
+                map.add_mapping(
+                    generated_line,
+                    0,
+                    Some(OriginalLocation::new(line.min(last_line), 0, index, None)),
+                );
+                generated_line += 1;
+            }
+        }
+    }
+}
+
+/// names and local names of `{ a, b as c }`, empty without names and None for default and
+/// namespace imports
+fn imported_names(clause: Option<&str>) -> Option<Vec<(String, String)>> {
+    let clause = match clause {
+        Some(clause) => clause.trim(),
+        None => return Some(Vec::new()),
+    };
+    let names = clause.strip_prefix('{')?.strip_suffix('}')?;
+
+    let names = names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match name.split_once(" as ") {
+            Some((name, alias)) => (name.trim().to_string(), alias.trim().to_string()),
+            None => (name.to_string(), name.to_string()),
+        })
+        .collect();
+    Some(names)
+}
+
+/// `a, b: c` destructuring or building an object from the names
+fn properties(names: &[(String, String)]) -> String {
+    names
+        .iter()
+        .map(|(name, local)| match name == local {
+            true => name.clone(),
+            false => format!("{name}: {local}"),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn insert_into_state(
+    manifest: &HashMap<String, String>,
+    integrity: &HashMap<String, String>,
+    state: &mut State,
+) {
+    let object = |map: &HashMap<String, String>| {
+        TinyLangType::Object(
+            map.iter()
+                .map(|(path, value)| (path.clone(), value.clone().into()))
+                .collect(),
+        )
+    };
+    state.insert(STATE_KEY.into(), object(manifest));
+    state.insert(INTEGRITY_STATE_KEY.into(), object(integrity));
+}
+
+fn lookup(key: &str, arguments: FuncArguments, state: &State) -> Option<TinyLangType> {
+    let path = match arguments.first() {
+        Some(TinyLangType::String(path)) => path,
+        _ => return None,
+    };
+    match state.get(key) {
+        Some(TinyLangType::Object(values)) => values.get(path.trim_start_matches('/')).cloned(),
+        _ => None,
+    }
+}
+
+/// `asset(path)` returns the url of the bundled file, or the path itself when there is
+/// none, e.g. `asset('/css/main.css')` is `/css/main.min.css`
+pub fn asset(arguments: FuncArguments, state: &State) -> TinyLangType {
+    let path = match arguments.first() {
+        Some(TinyLangType::String(path)) => path.clone(),
+        _ => return TinyLangType::Nil,
+    };
+    lookup(STATE_KEY, arguments, state).unwrap_or(TinyLangType::String(path))
+}
+
+/// `asset_integrity(path)` returns the `sha384-` hash of the asset, for the `integrity`
+/// attribute, or nil when `[assets] integrity` is off
+pub fn asset_integrity(arguments: FuncArguments, state: &State) -> TinyLangType {
+    lookup(INTEGRITY_STATE_KEY, arguments, state).unwrap_or(TinyLangType::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn assets(tempdir: &TempDir, production: bool) -> Assets {
+        let folder = tempdir.path().join("static");
+        fs::create_dir_all(folder.join("css")).unwrap();
+        fs::create_dir_all(folder.join("js")).unwrap();
+        fs::write(folder.join("css/base.css"), "a {\n  color: #ff0000;\n}\n").unwrap();
+        fs::write(
+            folder.join("css/main.css"),
+            "@import 'base.css';\n\np {\n  margin: 0px;\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            folder.join("js/math.js"),
+            "export function add(a, b) {\n  return a + b;\n}\nexport const zero = 0;\n",
+        )
+        .unwrap();
+        fs::write(
+            folder.join("js/main.js"),
+            "import { add, zero as nothing } from './math.js';\nconsole.log(add(nothing, 1));\n",
+        )
+        .unwrap();
+
+        Assets::new(
+            folder,
+            tempdir.path().join("output"),
+            AssetsConfiguration {
+                enabled: true,
+                ..Default::default()
+            },
+            production,
+        )
+    }
+
+    #[test]
+    fn test_bundles_stylesheets_and_scripts() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let assets = assets(&tempdir, false);
+
+        let build = assets.build(&assets.sources());
+
+        assert!(build.diagnostics.is_empty(), "{:?}", build.diagnostics);
+        assert_eq!("/css/main.min.css", build.manifest["css/main.css"]);
+        let output = tempdir.path().join("output");
+        let css = fs::read_to_string(output.join("css/main.min.css")).unwrap();
+        assert_eq!(
+            "a{color:red}p{margin:0}\n/*# sourceMappingURL=main.min.css.map */\n",
+            css
+        );
+        assert!(output.join("css/main.min.css.map").exists());
+
+        let js = fs::read_to_string(output.join("js/main.min.js")).unwrap();
+        assert_eq!(
+            "const __squid_module_0 = (() => { function add(a, b) {\n  return a + b;\n}\nconst zero = 0;\nreturn { add, zero }; })();\nconst { add, zero: nothing } = __squid_module_0;\nconsole.log(add(nothing, 1));\n//# sourceMappingURL=main.min.js.map\n",
+            js
+        );
+        assert!(output.join("js/main.min.js.map").exists());
+    }
+
+    #[test]
+    fn test_production_has_no_source_maps() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let assets = assets(&tempdir, true);
+
+        let build = assets.build(&assets.sources());
+
+        assert!(build.diagnostics.is_empty(), "{:?}", build.diagnostics);
+        let output = tempdir.path().join("output");
+        let js = fs::read_to_string(output.join("js/main.min.js")).unwrap();
+        assert!(!js.contains("sourceMappingURL"));
+        assert_eq!(1, js.lines().count(), "{js}");
+        assert!(!output.join("js/main.min.js.map").exists());
+        assert!(!output.join("css/main.min.css.map").exists());
+    }
+
+    #[test]
+    fn test_unsupported_syntax_is_copied_with_a_warning() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let assets = assets(&tempdir, false);
+        let js = tempdir.path().join("static/js");
+        fs::write(
+            js.join("main.js"),
+            "// app\nimport math from './math.js';\n",
+        )
+        .unwrap();
+        fs::write(js.join("other.js"), "import { add } from './math.js';\n").unwrap();
+        fs::write(js.join("math.js"), "export default function add() {}\n").unwrap();
+
+        let build = assets.build(&[js.join("main.js"), js.join("other.js")]);
+
+        assert!(build.diagnostics.iter().all(|d| !d.is_error()));
+        assert_eq!(Some((2, 1)), build.diagnostics[0].location);
+        assert_eq!(Some((1, 1)), build.diagnostics[1].location);
+        assert!(build.diagnostics[1].path.ends_with("math.js"));
+        assert_eq!("/js/main.min.js", build.manifest["js/main.js"]);
+        let copied = fs::read_to_string(tempdir.path().join("output/js/other.min.js")).unwrap();
+        assert_eq!("import { add } from './math.js';\n", copied);
+    }
+
+    #[test]
+    fn test_imports_in_comments_and_strings_are_text() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let assets = assets(&tempdir, false);
+        let main = tempdir.path().join("static/js/main.js");
+        fs::write(
+            &main,
+            "/*\nimport x from './math.js';\n*/\nconst help = `\nexport default 1;\n`;\n",
+        )
+        .unwrap();
+
+        let build = assets.build(std::slice::from_ref(&main));
+
+        assert!(build.diagnostics.is_empty(), "{:?}", build.diagnostics);
+        let bundle = fs::read_to_string(tempdir.path().join("output/js/main.min.js")).unwrap();
+        assert!(bundle.starts_with(&fs::read_to_string(&main).unwrap()));
+    }
+
+    #[test]
+    fn test_modules_keep_their_private_names() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let assets = assets(&tempdir, false);
+        let js = tempdir.path().join("static/js");
+        fs::write(
+            js.join("a.js"),
+            "const helper = 'a';\nfunction a() { return helper; }\nexport { a as first };\n",
+        )
+        .unwrap();
+        fs::write(
+            js.join("b.js"),
+            "const helper = 'b';\nexport function second() { return helper; }\n",
+        )
+        .unwrap();
+        fs::write(
+            js.join("main.js"),
+            "import { first } from './a.js';\nimport { second } from './b.js';\nconsole.log(first(), second());\n",
+        )
+        .unwrap();
+
+        let build = assets.build(&[js.join("main.js")]);
+
+        assert!(build.diagnostics.is_empty(), "{:?}", build.diagnostics);
+        let bundle = fs::read_to_string(tempdir.path().join("output/js/main.min.js")).unwrap();
+        assert_eq!(
+            "const __squid_module_0 = (() => { const helper = 'a';\nfunction a() { return helper; }\nreturn { first: a }; })();\n\
+            const __squid_module_1 = (() => { const helper = 'b';\nfunction second() { return helper; }\nreturn { second }; })();\n\
+            const { first } = __squid_module_0;\nconst { second } = __squid_module_1;\nconsole.log(first(), second());\n",
+            bundle.split("//#").next().unwrap()
+        );
+
+        fs::write(js.join("main.js"), "import { helper } from './b.js';\n").unwrap();
+        let build = assets.build(&[js.join("main.js")]);
+        assert!(build.diagnostics[0]
+            .message
+            .contains("does not export helper"));
+    }
+
+    #[test]
+    fn test_asset_function() {
+        let mut state = State::new();
+        let manifest =
+            HashMap::from([("css/main.css".to_string(), "/css/main.min.css".to_string())]);
+        insert_into_state(&manifest, &HashMap::new(), &mut state);
+
+        let url = asset(vec!["/css/main.css".to_string().into()], &state);
+        assert_eq!("/css/main.min.css", url.to_string());
+        let missing = asset(vec!["/css/print.css".to_string().into()], &state);
+        assert_eq!("/css/print.css", missing.to_string());
+        let integrity = asset_integrity(vec!["/css/main.css".to_string().into()], &state);
+        assert!(matches!(integrity, TinyLangType::Nil));
+    }
+
+    #[test]
+    fn test_fingerprints_assets() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let mut assets = assets(&tempdir, true);
+        assets.config = AssetsConfiguration {
+            enabled: false,
+            fingerprint: true,
+            integrity: true,
+            ..Default::default()
+        };
+
+        let build = assets.build(&assets.sources());
+        assets.write_manifest(&build.manifest).unwrap();
+
+        let output = tempdir.path().join("output");
+        // sha256 of the contents of base.css
+        assert_eq!("/css/base.7cccf97d.css", build.manifest["css/base.css"]);
+        let copy = fs::read_to_string(output.join("css/base.7cccf97d.css")).unwrap();
+        assert_eq!("a {\n  color: #ff0000;\n}\n", copy);
+        assert!(build.integrity["css/base.css"].starts_with("sha384-"));
+        let manifest: HashMap<String, String> =
+            serde_json::from_str(&fs::read_to_string(output.join(MANIFEST_FILE)).unwrap()).unwrap();
+        assert_eq!(build.manifest, manifest);
+    }
+
+    #[test]
+    fn test_fingerprints_every_static_file() {
+        let tempdir = TempDir::new("assets").unwrap();
+        let mut assets = assets(&tempdir, true);
+        assets.config.fingerprint = true;
+        let folder = tempdir.path().join("static");
+        fs::create_dir_all(folder.join("img")).unwrap();
+        fs::write(folder.join("img/logo.png"), [0x89, b'P', b'N', b'G', 0xff]).unwrap();
+        fs::write(folder.join("CNAME"), "example.com").unwrap();
+        fs::write(folder.join(".htaccess"), "").unwrap();
+
+        let build = assets.build(&assets.sources());
+
+        assert!(build.diagnostics.is_empty(), "{:?}", build.diagnostics);
+        let logo = &build.manifest["img/logo.png"];
+        assert!(
+            logo.starts_with("/img/logo.") && logo.ends_with(".png"),
+            "{logo}"
+        );
+        let output = tempdir.path().join("output");
+        assert_eq!(
+            vec![0x89, b'P', b'N', b'G', 0xff],
+            fs::read(output.join(&logo[1..])).unwrap()
+        );
+        assert!(build.manifest["CNAME"].starts_with("/CNAME."));
+        assert!(!build.manifest.contains_key(".htaccess"));
+        assert!(build.manifest["js/main.js"].starts_with("/js/main.min."));
+    }
+}
@@ -0,0 +1,446 @@
+//! Build cache kept in the cache folder across runs.
+//!
+//! Each website has its own cache folder next to its output folder, `site/public` is
+//! cached in `site/.squid-cache/public`.
+//!
+//! Converting markdown and rendering templates is most of a build, while most builds
+//! change little. Converted documents are cached keyed on their contents and on what the
+//! conversion reads (configuration, shortcodes...). Pages are cached keyed on their
+//! template, the templates it renders, and the part of the state the template reads, so
+//! a page is only rendered again when something it could show changed, or when its
+//! output is not the file squid wrote.
+
+use crate::md::MarkdownDocument;
+use crate::outputs;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tinylang::types::{State, TinyLangType};
+
+/// folder, next to the output folder, keeping what builds reuse
+pub const CACHE_FOLDER: &str = ".squid-cache";
+
+/// files, inside the cache folder, holding the converted documents and rendered pages
+const DOCUMENTS_FILE: &str = "documents.json";
+const PAGES_FILE: &str = "pages.json";
+
+/// hash of everything a cached value depends on, squid itself included
+pub struct CacheKey(Sha256);
+
+impl CacheKey {
+    pub fn new() -> Self {
+        Self(Sha256::new()).with(env!("CARGO_PKG_VERSION"))
+    }
+
+    pub fn with(mut self, bytes: impl AsRef<[u8]>) -> Self {
+        let bytes = bytes.as_ref();
+        // the length keeps ("ab", "c") and ("a", "bc") apart
+        self.0.update((bytes.len() as u64).to_le_bytes());
+        self.0.update(bytes);
+        self
+    }
+
+    /// maps are hashed with sorted keys, their iteration order changes between runs
+    pub fn with_json(self, value: &impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or_default();
+        self.with(value.to_string())
+    }
+
+    pub fn with_state(mut self, state: &State) -> Self {
+        hash_state(&mut self.0, state);
+        self
+    }
+
+    pub fn with_value(mut self, value: &TinyLangType) -> Self {
+        hash_value(&mut self.0, value);
+        self
+    }
+
+    /// the files of the folder, a missing folder has none
+    pub fn with_folder(mut self, folder: &Path) -> Self {
+        let mut files = Vec::new();
+        let mut folders = vec![folder.to_path_buf()];
+        while let Some(folder) = folders.pop() {
+            for entry in fs::read_dir(&folder).into_iter().flatten().flatten() {
+                match entry.path() {
+                    path if path.is_dir() => folders.push(path),
+                    path => files.push(path),
+                }
+            }
+        }
+        files.sort();
+        for file in files {
+            self = self
+                .with(file.to_string_lossy().as_bytes())
+                .with(fs::read(&file).unwrap_or_default());
+        }
+        self
+    }
+
+    pub fn finish(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+impl Default for CacheKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hash_state(hasher: &mut Sha256, state: &State) {
+    let mut keys: Vec<&String> = state.keys().collect();
+    keys.sort();
+    for key in keys {
+        hasher.update(key.as_bytes());
+        hash_value(hasher, &state[key]);
+    }
+}
+
+fn hash_value(hasher: &mut Sha256, value: &TinyLangType) {
+    match value {
+        TinyLangType::String(s) => {
+            hasher.update([0]);
+            hasher.update((s.len() as u64).to_le_bytes());
+            hasher.update(s.as_bytes());
+        }
+        TinyLangType::Numeric(n) => {
+            hasher.update([1]);
+            hasher.update(n.to_bits().to_le_bytes());
+        }
+        TinyLangType::Bool(b) => hasher.update([2, *b as u8]),
+        // functions are part of squid, hashed with its version
+        TinyLangType::Function(_) => hasher.update([3]),
+        TinyLangType::Vec(values) => {
+            hasher.update([4]);
+            hasher.update((values.len() as u64).to_le_bytes());
+            values.iter().for_each(|v| hash_value(hasher, v));
+        }
+        TinyLangType::Object(state) => {
+            hasher.update([5]);
+            hasher.update((state.len() as u64).to_le_bytes());
+            hash_state(hasher, state);
+        }
+        TinyLangType::Nil => hasher.update([6]),
+    }
+}
+
+/// cache folder of the website built in this output folder
+pub fn cache_folder(output_folder: &Path) -> PathBuf {
+    let output = match output_folder.file_name() {
+        Some(_) => output_folder.to_path_buf(),
+        // `.` or `..`
+        None => output_folder.canonicalize().unwrap_or_default(),
+    };
+    let name = output.file_name().unwrap_or_default();
+    output
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(CACHE_FOLDER)
+        .join(name)
+}
+
+fn content_hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct DocumentEntry {
+    key: String,
+    document: MarkdownDocument,
+    /// files written while converting it, e.g. images resized by shortcodes
+    outputs: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct PageEntry {
+    key: String,
+    /// hash of the page as it was written
+    hash: String,
+    /// files written while rendering it, e.g. resized images
+    outputs: Vec<PathBuf>,
+}
+
+/// entries of the previous run, and the ones used by this one
+struct Entries<T> {
+    previous: HashMap<PathBuf, T>,
+    /// sorted, so an unchanged cache is written the same
+    current: Mutex<BTreeMap<PathBuf, T>>,
+}
+
+impl<T: Clone + Serialize + for<'de> Deserialize<'de>> Entries<T> {
+    fn load(file: &Path) -> Self {
+        Self {
+            previous: Self::read(file).into_iter().collect(),
+            current: Default::default(),
+        }
+    }
+
+    fn read(file: &Path) -> BTreeMap<PathBuf, T> {
+        fs::read_to_string(file)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    /// the entry of the previous run, kept for the next one
+    fn reuse(&self, path: &Path, is_valid: impl FnOnce(&T) -> bool) -> Option<T> {
+        let entry = self.previous.get(path).filter(|e| is_valid(e))?;
+        self.insert(path.to_path_buf(), entry.clone());
+        Some(entry.clone())
+    }
+
+    fn insert(&self, path: PathBuf, entry: T) {
+        self.current.lock().unwrap().insert(path, entry);
+    }
+
+    /// Adds the entries used by this run to the saved ones, which another build may
+    /// have written since they were loaded. Entries of files that are gone are dropped.
+    fn save(&self, file: &Path) -> Result<()> {
+        let mut entries = Self::read(file);
+        entries.extend(self.current.lock().unwrap().clone());
+        entries.retain(|path, _| path.exists());
+        let json = serde_json::to_string(&entries)?;
+        crate::io::write_atomically(file, json)
+            .with_context(|| format!("could not write {:?}", file))
+    }
+}
+
+pub struct BuildCache {
+    folder: PathBuf,
+    documents: Entries<DocumentEntry>,
+    pages: Entries<PageEntry>,
+}
+
+impl BuildCache {
+    /// a missing or unreadable cache only means building everything
+    pub fn load(cache_folder: &Path) -> Self {
+        Self {
+            folder: cache_folder.to_path_buf(),
+            documents: Entries::load(&cache_folder.join(DOCUMENTS_FILE)),
+            pages: Entries::load(&cache_folder.join(PAGES_FILE)),
+        }
+    }
+
+    /// the document converted from this file with the same key, its outputs are recorded
+    /// again as if it was converted
+    pub fn document(&self, path: &Path, key: &str) -> Option<MarkdownDocument> {
+        let entry = self.documents.reuse(path, |e| {
+            e.key == key && e.outputs.iter().all(|output| output.exists())
+        })?;
+        entry
+            .outputs
+            .iter()
+            .for_each(|output| outputs::record(output));
+        Some(entry.document)
+    }
+
+    pub fn insert_document(
+        &self,
+        path: PathBuf,
+        key: String,
+        document: MarkdownDocument,
+        outputs: Vec<PathBuf>,
+    ) {
+        let entry = DocumentEntry {
+            key,
+            document,
+            outputs,
+        };
+        self.documents.insert(path, entry);
+    }
+
+    /// pages rendered with this key, see `PageCache::for_template`
+    pub fn pages(self: &Arc<Self>, key: String) -> PageCache {
+        PageCache {
+            cache: self.clone(),
+            key,
+        }
+    }
+
+    /// writes the entries used since it was loaded, along with the saved ones of files
+    /// that still exist
+    pub fn save(&self) -> Result<()> {
+        fs::create_dir_all(&self.folder)?;
+        self.documents.save(&self.folder.join(DOCUMENTS_FILE))?;
+        self.pages.save(&self.folder.join(PAGES_FILE))
+    }
+}
+
+#[derive(Clone)]
+pub struct PageCache {
+    cache: Arc<BuildCache>,
+    key: String,
+}
+
+impl PageCache {
+    /// pages of the template rendered with this state, which is all it can read. None
+    /// when the templates it renders are not known, its pages are then not cached.
+    pub fn for_template(&self, template: &str, state: &State) -> Option<PageCache> {
+        let mut key = CacheKey::new()
+            .with(&self.key)
+            .with(template)
+            .with_state(state);
+        for (path, contents) in crate::tinylang::rendered_templates(template)? {
+            key = key.with(path).with(contents);
+        }
+        Some(PageCache {
+            cache: self.cache.clone(),
+            key: key.finish(),
+        })
+    }
+
+    /// the page of a partial template showing this `content`
+    pub fn for_content(&self, content: &TinyLangType) -> PageCache {
+        PageCache {
+            cache: self.cache.clone(),
+            key: CacheKey::new().with(&self.key).with_value(content).finish(),
+        }
+    }
+
+    /// whether the output is still the page rendered with this key, its outputs are
+    /// recorded again as if it was rendered
+    pub fn is_fresh(&self, output: &Path) -> bool {
+        let entry = self.cache.pages.reuse(output, |e| {
+            e.key == self.key
+                && e.outputs.iter().all(|output| output.exists())
+                && fs::read(output).is_ok_and(|c| content_hash(&c) == e.hash)
+        });
+        match entry {
+            Some(entry) => {
+                outputs::record(output);
+                entry
+                    .outputs
+                    .iter()
+                    .for_each(|output| outputs::record(output));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, output: PathBuf, contents: &[u8], outputs: Vec<PathBuf>) {
+        let entry = PageEntry {
+            key: self.key.clone(),
+            hash: content_hash(contents),
+            outputs,
+        };
+        self.cache.pages.insert(output, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_state_keys_do_not_depend_on_order() {
+        let mut state = State::new();
+        for i in 0..20 {
+            state.insert(format!("key{i}"), TinyLangType::Numeric(i as f64));
+        }
+        // every map gets its own random order
+        let mut entries: Vec<_> = state.clone().into_iter().collect();
+        entries.reverse();
+        let reordered: State = entries.into_iter().collect();
+        let key = |s: &State| CacheKey::new().with_state(s).finish();
+        assert_eq!(key(&state), key(&reordered));
+
+        state.insert("key0".into(), "0".into());
+        assert_ne!(key(&state), key(&reordered));
+    }
+
+    #[test]
+    fn test_pages_are_fresh_until_their_output_or_key_changes() {
+        let tempdir = TempDir::new("cache").unwrap();
+        let cache_folder = tempdir.path().join("cache");
+        let page = tempdir.path().join("index.html");
+        fs::write(&page, "<p>squid</p>").unwrap();
+
+        let cache = Arc::new(BuildCache::load(&cache_folder));
+        let pages = cache.pages("key".into());
+        assert!(!pages.is_fresh(&page));
+        pages.insert(page.clone(), b"<p>squid</p>", Vec::new());
+        cache.save().unwrap();
+
+        let cache = Arc::new(BuildCache::load(&cache_folder));
+        assert!(cache.pages("key".into()).is_fresh(&page));
+        assert!(!cache.pages("other key".into()).is_fresh(&page));
+        fs::write(&page, "<p>edited</p>").unwrap();
+        assert!(!cache.pages("key".into()).is_fresh(&page));
+    }
+
+    #[test]
+    fn test_saving_keeps_the_entries_of_other_builds() {
+        let tempdir = TempDir::new("cache").unwrap();
+        let cache_folder = tempdir.path().join("cache");
+        let first = tempdir.path().join("first.html");
+        let second = tempdir.path().join("second.html");
+        fs::write(&first, "first").unwrap();
+        fs::write(&second, "second").unwrap();
+
+        let cache = Arc::new(BuildCache::load(&cache_folder));
+        let other = Arc::new(BuildCache::load(&cache_folder));
+        cache
+            .pages("key".into())
+            .insert(first.clone(), b"first", Vec::new());
+        other
+            .pages("key".into())
+            .insert(second.clone(), b"second", Vec::new());
+        cache.save().unwrap();
+        other.save().unwrap();
+
+        let cache = Arc::new(BuildCache::load(&cache_folder));
+        assert!(cache.pages("key".into()).is_fresh(&first));
+        assert!(cache.pages("key".into()).is_fresh(&second));
+
+        fs::remove_file(&first).unwrap();
+        cache.save().unwrap();
+        let saved = fs::read_to_string(cache_folder.join(PAGES_FILE)).unwrap();
+        assert!(!saved.contains("first.html"));
+        assert!(saved.contains("second.html"));
+    }
+
+    #[test]
+    fn test_cache_folder_is_next_to_the_output() {
+        assert_eq!(
+            Path::new("site/.squid-cache/public"),
+            cache_folder(Path::new("site/public/"))
+        );
+        assert_eq!(
+            Path::new(".squid-cache/out"),
+            cache_folder(Path::new("out"))
+        );
+    }
+
+    #[test]
+    fn test_page_keys_only_depend_on_what_the_template_reads() {
+        let tempdir = TempDir::new("cache").unwrap();
+        let cache = Arc::new(BuildCache::load(&tempdir.path().join("cache")));
+        let pages = cache.pages("key".into());
+        let key = |state: &State| {
+            let state = crate::tinylang::state_for_template("{{ title }}", state);
+            pages.for_template("{{ title }}", &state).unwrap().key
+        };
+
+        let mut state = State::new();
+        state.insert("title".into(), "squid".into());
+        let mut other = state.clone();
+        other.insert("posts".into(), TinyLangType::Vec(Vec::new()));
+        assert_eq!(key(&state), key(&other));
+        assert!(pages.for_template("{{ render(page) }}", &state).is_none());
+
+        let template = pages.for_template("{{ content.title }}", &state).unwrap();
+        let content = |title: &str| {
+            let mut content = State::new();
+            content.insert("title".into(), title.into());
+            template.for_content(&TinyLangType::Object(content)).key
+        };
+        assert_ne!(content("a"), content("b"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Precompressed gzip and brotli variants of the generated files.
+//!
+//! `index.html` gets `index.html.gz` and `index.html.br` next to it, which web servers
+//! (e.g. nginx with `gzip_static`) send as they are instead of compressing every response.
+//! Variants are only compressed again when the file changed after them.
+
+use crate::config::CompressionConfiguration;
+use crate::outputs;
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// extensions of the files worth compressing, images and fonts already are
+pub const COMPRESSIBLE_EXTENSIONS: &[&str] = &["html", "css", "js", "xml", "json"];
+
+/// brotli settings, compressing once means we can afford the best ratio
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+
+pub struct Compressor {
+    config: CompressionConfiguration,
+}
+
+impl Compressor {
+    pub fn new(config: CompressionConfiguration) -> Self {
+        Self { config }
+    }
+
+    /// compresses the files of the folder, returning how many were compressed again
+    pub fn compress_folder(&self, folder: &Path) -> usize {
+        let mut compressed = 0;
+        let mut folders = vec![folder.to_path_buf()];
+        while let Some(folder) = folders.pop() {
+            for entry in fs::read_dir(&folder).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    folders.push(path);
+                } else if is_compressible(&path) {
+                    match self.compress_file(&path) {
+                        Ok(true) => compressed += 1,
+                        Ok(false) => {}
+                        Err(e) => eprintln!("{e:?}"),
+                    }
+                }
+            }
+        }
+        compressed
+    }
+
+    /// writes the variants of the file that are out of date, returns whether it did
+    fn compress_file(&self, path: &Path) -> Result<bool> {
+        let variants = [
+            (
+                self.config.gzip,
+                variant_path(path, "gz"),
+                gzip as fn(&[u8]) -> _,
+            ),
+            (self.config.brotli, variant_path(path, "br"), brotli),
+        ];
+        let size = fs::metadata(path)?.len();
+
+        let mut contents = None;
+        let mut compressed = false;
+        for (enabled, variant, compress) in variants {
+            // a variant left from a bigger version of the file would be served instead
+            if !enabled || size < self.config.threshold {
+                if variant.exists() {
+                    fs::remove_file(&variant)?;
+                }
+                continue;
+            }
+            outputs::record(&variant);
+            if !is_stale(path, &variant) {
+                continue;
+            }
+
+            if contents.is_none() {
+                contents =
+                    Some(fs::read(path).with_context(|| format!("could not read {:?}", path))?);
+            }
+            let bytes = compress(contents.as_ref().unwrap())
+                .with_context(|| format!("could not compress {:?}", path))?;
+            fs::write(&variant, bytes).with_context(|| format!("could not write {:?}", variant))?;
+            compressed = true;
+        }
+        Ok(compressed)
+    }
+}
+
+fn is_compressible(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| COMPRESSIBLE_EXTENSIONS.iter().any(|c| e.eq(*c)))
+}
+
+/// `index.html` -> `index.html.gz`
+fn variant_path(path: &Path, extension: &str) -> PathBuf {
+    let mut variant = path.as_os_str().to_owned();
+    variant.push(".");
+    variant.push(extension);
+    PathBuf::from(variant)
+}
+
+/// the variant is missing or older than the file
+fn is_stale(path: &Path, variant: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(path), modified(variant)) {
+        (Some(file), Some(variant)) => variant < file,
+        _ => true,
+    }
+}
+
+fn gzip(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(contents)?;
+    Ok(encoder.finish()?)
+}
+
+fn brotli(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    {
+        let mut writer =
+            brotli::CompressorWriter::new(&mut output, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        writer.write_all(contents)?;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_compresses_big_enough_files() {
+        let tempdir = TempDir::new("compress").unwrap();
+        let page = tempdir.path().join("index.html");
+        let html = "<p>squid</p>".repeat(200);
+        fs::write(&page, &html).unwrap();
+        fs::write(tempdir.path().join("small.css"), "p{margin:0}").unwrap();
+        fs::write(tempdir.path().join("photo.jpg"), html.as_bytes()).unwrap();
+        let compressor = Compressor::new(CompressionConfiguration {
+            enabled: true,
+            ..Default::default()
+        });
+
+        assert_eq!(1, compressor.compress_folder(tempdir.path()));
+
+        let mut decoded = String::new();
+        GzDecoder::new(fs::File::open(tempdir.path().join("index.html.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(html, decoded);
+        let mut decoded = String::new();
+        brotli::Decompressor::new(
+            fs::File::open(tempdir.path().join("index.html.br")).unwrap(),
+            4096,
+        )
+        .read_to_string(&mut decoded)
+        .unwrap();
+        assert_eq!(html, decoded);
+        assert!(!tempdir.path().join("small.css.gz").exists());
+        assert!(!tempdir.path().join("photo.jpg.gz").exists());
+
+        // nothing changed since
+        assert_eq!(0, compressor.compress_folder(tempdir.path()));
+    }
+}
@@ -9,6 +9,277 @@ pub struct Configuration {
     pub website_name: String,
     pub uri: String,
     pub custom_keys: HashMap<String, String>,
+    #[serde(default)]
+    pub data: DataConfiguration,
+    #[serde(default)]
+    pub markdown: MarkdownConfiguration,
+    #[serde(default)]
+    pub images: ImageProcessingConfiguration,
+    #[serde(default)]
+    pub gallery: GalleryConfiguration,
+    #[serde(default)]
+    pub assets: AssetsConfiguration,
+    #[serde(default)]
+    pub html: HtmlConfiguration,
+    #[serde(default)]
+    pub compression: CompressionConfiguration,
+}
+
+/// `[markdown]` section, selects the markdown dialect used for every document.
+///
+/// Everything is disabled by default, which is plain CommonMark. `gfm = true` turns on
+/// tables, footnotes, strikethrough, autolinks and task lists at once, each of them
+/// can still be set individually. Documents can override it with a `markdown` key in
+/// their front matter, e.g. `markdown: math, -tables`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct MarkdownConfiguration {
+    pub gfm: bool,
+    pub tables: Option<bool>,
+    pub footnotes: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub autolinks: Option<bool>,
+    pub task_lists: Option<bool>,
+    /// `$inline$` and `$$` display math, rendered as MathML
+    pub math: bool,
+    /// parse a second front matter block left in the body (the first one is always
+    /// removed before the document is converted)
+    pub frontmatter: bool,
+    /// keep raw html present in the markdown instead of escaping it
+    pub allow_dangerous_html: bool,
+    pub highlight: HighlightConfiguration,
+    pub headings: HeadingsConfiguration,
+    pub admonitions: AdmonitionsConfiguration,
+    pub images: ImagesConfiguration,
+}
+
+/// `[markdown.admonitions]` section, `> [!NOTE]` alerts and `:::tip` containers, disabled
+/// by default
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AdmonitionsConfiguration {
+    pub enabled: bool,
+    /// kinds rendered as admonitions, anything else is left as it is
+    pub kinds: Vec<String>,
+}
+
+impl Default for AdmonitionsConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kinds: ["note", "tip", "important", "warning", "caution"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+/// `[markdown.images]` section, post-processing of the images of the documents, all
+/// disabled by default
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ImagesConfiguration {
+    /// add the `width` and `height` of local images, which then must exist
+    pub dimensions: bool,
+    /// add `loading="lazy"` and `decoding="async"`
+    pub lazy_loading: bool,
+    /// turn images alone in their paragraph with a title into `<figure>`
+    pub figures: bool,
+}
+
+/// `[images]` section, resized variants and placeholders of the local images, both
+/// disabled by default
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ImageProcessingConfiguration {
+    pub enabled: bool,
+    /// widths of the variants, images are never enlarged
+    pub widths: Vec<u32>,
+    /// formats of the variants: avif, jpeg, png or webp, which is always lossless
+    pub formats: Vec<String>,
+    /// quality of lossy formats, from 1 to 100
+    pub quality: u8,
+    /// `sizes` attribute of the markdown images
+    pub sizes: String,
+    /// folder of the output holding the variants
+    pub folder: String,
+    /// compute a blurhash and the dominant color of every image
+    pub placeholders: bool,
+}
+
+impl Default for ImageProcessingConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            widths: vec![480, 960, 1440],
+            formats: vec!["avif".to_string()],
+            quality: 80,
+            sizes: "100vw".to_string(),
+            folder: "_images".to_string(),
+            placeholders: false,
+        }
+    }
+}
+
+/// `[gallery]` section, photos of the gallery folder
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GalleryConfiguration {
+    /// widths of the thumbnails
+    pub thumbnails: Vec<u32>,
+    /// format of the thumbnails, webp is always lossless
+    pub format: String,
+    /// expose where photos were taken
+    pub gps: bool,
+    /// publish the photos without their EXIF data
+    pub strip_exif: bool,
+}
+
+impl Default for GalleryConfiguration {
+    fn default() -> Self {
+        Self {
+            thumbnails: vec![320, 640],
+            format: "avif".to_string(),
+            gps: false,
+            strip_exif: false,
+        }
+    }
+}
+
+/// `[assets]` section, bundled, minified and fingerprinted css and js of the static folder
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AssetsConfiguration {
+    pub enabled: bool,
+    /// remove the white space and comments, scripts are only minified in production
+    pub minify: bool,
+    /// publish a copy named after the content hash, e.g. `main.3f9a1c2b.css`, of every file
+    /// of the static folder
+    pub fingerprint: bool,
+    /// compute the subresource integrity hash of every asset
+    pub integrity: bool,
+}
+
+impl Default for AssetsConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            minify: true,
+            fingerprint: false,
+            integrity: false,
+        }
+    }
+}
+
+/// `[html]` section, post-processing of the generated pages
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct HtmlConfiguration {
+    /// collapse white space and remove comments, only in production when unset. Pages
+    /// are never minified while serving the website.
+    pub minify: Option<bool>,
+}
+
+/// `[compression]` section, gzip and brotli variants written next to the outputs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CompressionConfiguration {
+    pub enabled: bool,
+    pub gzip: bool,
+    pub brotli: bool,
+    /// smaller files, in bytes, are not worth compressing
+    pub threshold: u64,
+}
+
+impl Default for CompressionConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gzip: true,
+            brotli: true,
+            threshold: 1024,
+        }
+    }
+}
+
+/// `[markdown.headings]` section, heading anchors and table of contents
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HeadingsConfiguration {
+    /// add an `id` to every heading so they can be linked to
+    pub ids: bool,
+    /// add a `#` link pointing to the heading itself, requires `ids`
+    pub anchor_links: bool,
+    /// deepest heading level listed in the `toc` of a document
+    pub toc_depth: u8,
+}
+
+impl Default for HeadingsConfiguration {
+    fn default() -> Self {
+        Self {
+            ids: false,
+            anchor_links: false,
+            toc_depth: 3,
+        }
+    }
+}
+
+/// `[markdown.highlight]` section, syntax highlighting of fenced code blocks at build time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HighlightConfiguration {
+    pub enabled: bool,
+    /// any of the themes bundled with syntect (e.g. `InspiredGitHub`, `base16-ocean.dark`)
+    pub theme: String,
+    /// use css classes instead of inline styles, the theme is then written to `stylesheet`
+    pub css_classes: bool,
+    /// file name of the generated theme stylesheet, relative to the output folder
+    pub stylesheet: String,
+    pub line_numbers: bool,
+}
+
+impl Default for HighlightConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            theme: "InspiredGitHub".to_string(),
+            css_classes: false,
+            stylesheet: "highlight.css".to_string(),
+            line_numbers: false,
+        }
+    }
+}
+
+/// `[data]` section, controls how data files are turned into pages
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DataConfiguration {
+    /// record field used as the output file name of data-driven pages
+    #[serde(default = "DataConfiguration::default_slug_field")]
+    pub slug_field: String,
+    /// overrides `slug_field` for a given data collection (e.g. `products = "sku"`)
+    #[serde(default)]
+    pub slug_fields: HashMap<String, String>,
+}
+
+impl DataConfiguration {
+    fn default_slug_field() -> String {
+        "slug".to_string()
+    }
+
+    pub fn slug_field_for(&self, collection_name: &str) -> &str {
+        self.slug_fields
+            .get(collection_name)
+            .unwrap_or(&self.slug_field)
+    }
+}
+
+impl Default for DataConfiguration {
+    fn default() -> Self {
+        Self {
+            slug_field: Self::default_slug_field(),
+            slug_fields: HashMap::new(),
+        }
+    }
 }
 
 impl Configuration {
@@ -46,5 +317,23 @@ mod tests {
             "nice",
             config.custom_keys.get("something").unwrap().as_str()
         );
+        assert_eq!("slug", config.data.slug_field_for("products"));
+        assert!(!config.markdown.gfm);
+    }
+
+    #[test]
+    fn test_data_slug_fields() {
+        let content = r#"
+        website_name = "my website"
+        uri = "https://my_website.com"
+        [custom_keys]
+        [data]
+        slug_field = "id"
+        [data.slug_fields]
+        products = "sku"
+        "#;
+        let config: Configuration = toml::from_str(content).unwrap();
+        assert_eq!("sku", config.data.slug_field_for("products"));
+        assert_eq!("id", config.data.slug_field_for("speakers"));
     }
 }
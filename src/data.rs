@@ -0,0 +1,402 @@
+//! Data files exposed as template state.
+//!
+//! Every JSON, YAML, TOML or CSV file inside the data folder is parsed and made
+//! available to templates as `data.<file name>`. Files inside sub folders are
+//! nested, so `data/team/members.json` becomes `data.team.members`.
+//!
+//! Files holding a list of records can also generate one page per record, in the
+//! same way markdown collections do, through a `_<file name>.template` partial.
+
+use crate::diagnostic::Diagnostic;
+use crate::slug::slugify;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tinylang::types::{State, TinyLangType};
+
+/// Extensions we know how to parse.
+pub const DATA_EXTENSIONS: &[&str] = &["json", "yaml", "yml", "toml", "csv"];
+
+#[derive(Debug, Clone)]
+pub struct DataFile {
+    /// path relative to the data folder split in segments, without the extension.
+    /// e.g. `team/members.json` -> `["team", "members"]`
+    pub key: Vec<String>,
+    pub path: PathBuf,
+    pub value: Value,
+}
+
+impl DataFile {
+    pub fn new(path: &Path, data_folder: &Path, contents: &str) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .ok_or(anyhow!("data file {:?} has no extension", path))?;
+
+        let relative = path.strip_prefix(data_folder).unwrap_or(path);
+        let mut key: Vec<String> = relative
+            .parent()
+            .map(|p| {
+                p.components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        key.push(
+            path.file_stem()
+                .ok_or(anyhow!("could not get the file name of {:?}", path))?
+                .to_string_lossy()
+                .to_string(),
+        );
+
+        let value = Self::parse(extension, contents)
+            .with_context(|| format!("could not parse data file {:?}", path))?;
+
+        Ok(Self {
+            key,
+            path: path.to_path_buf(),
+            value,
+        })
+    }
+
+    fn parse(extension: &str, contents: &str) -> Result<Value> {
+        let value = match extension {
+            "json" => serde_json::from_str(contents)?,
+            "yaml" | "yml" => serde_yaml::from_str(contents)?,
+            "toml" => toml::from_str(contents)?,
+            "csv" => {
+                let mut reader = csv::Reader::from_reader(contents.as_bytes());
+                let mut records = Vec::new();
+                for record in reader.deserialize() {
+                    let record: HashMap<String, String> = record?;
+                    records.push(serde_json::to_value(record)?);
+                }
+                Value::Array(records)
+            }
+            _ => return Err(anyhow!("unsupported data file extension {extension}")),
+        };
+
+        Ok(value)
+    }
+
+    /// name used to access the file from the templates (`data.<name>`)
+    pub fn top_level_name(&self) -> &str {
+        self.key.first().map(|k| k.as_str()).unwrap_or_default()
+    }
+}
+
+/// A data file holding a list of records, each record generates its own page.
+#[derive(Debug, Clone)]
+pub struct DataCollection {
+    pub name: String,
+    pub path: PathBuf,
+    pub records: Vec<DataRecord>,
+    /// records without a usable slug, they do not generate pages
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DataRecord {
+    /// output file name (without `.html`)
+    pub slug: String,
+    pub value: Value,
+}
+
+impl DataCollection {
+    /// returns None if the data file is not a list of records
+    pub fn new(file: &DataFile, slug_field: &str) -> Option<Self> {
+        let items = file.value.as_array()?;
+        let name = file.key.last()?.clone();
+
+        let mut records = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut slugs = HashSet::new();
+        for (position, item) in items.iter().enumerate() {
+            let slug = match item.get(slug_field) {
+                Some(Value::String(s)) => slugify(s),
+                Some(Value::Number(n)) => slugify(&n.to_string()),
+                _ => {
+                    diagnostics.push(Diagnostic::warning(
+                        &file.path,
+                        format!("record {position} has no `{slug_field}` field, skipping its page"),
+                    ));
+                    continue;
+                }
+            };
+
+            if slug.is_empty() {
+                diagnostics.push(Diagnostic::error(
+                    &file.path,
+                    format!("record {position} has an empty slug, skipping its page"),
+                ));
+                continue;
+            }
+            if !slugs.insert(slug.clone()) {
+                diagnostics.push(Diagnostic::error(
+                    &file.path,
+                    format!("record {position} has the same slug as an earlier record: {slug}"),
+                ));
+                continue;
+            }
+
+            records.push(DataRecord {
+                slug,
+                value: item.clone(),
+            });
+        }
+
+        Some(Self {
+            name,
+            path: file.path.clone(),
+            records,
+            diagnostics,
+        })
+    }
+}
+
+impl DataRecord {
+    /// exposes the record as TinyLang::State so it can be used as the `content` of the
+    /// collection template
+    pub fn as_tinylang_state(&self, collection_name: &str) -> State {
+        let mut state = match to_tinylang(&self.value) {
+            TinyLangType::Object(o) => o,
+            _ => State::new(),
+        };
+
+        state.insert("slug".into(), self.slug.clone().into());
+        state.insert(
+            "partial_uri".into(),
+            format!("/{collection_name}/{}.html", self.slug).into(),
+        );
+        state
+    }
+}
+
+/// converts any parsed data value into the TinyLang representation
+pub fn to_tinylang(value: &Value) -> TinyLangType {
+    match value {
+        Value::Null => TinyLangType::Nil,
+        Value::Bool(b) => TinyLangType::Bool(*b),
+        Value::Number(n) => TinyLangType::Numeric(n.as_f64().unwrap_or_default()),
+        Value::String(s) => TinyLangType::String(s.clone()),
+        Value::Array(items) => TinyLangType::Vec(items.iter().map(to_tinylang).collect()),
+        Value::Object(map) => TinyLangType::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), to_tinylang(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Removes the files that would overwrite each other in the `data` object, like
+/// `team.json` and the files of the `team/` folder, or `team.json` and `team.yaml`.
+/// Files are sorted by path and the first one of each clash is kept.
+pub fn remove_clashes(files: &mut Vec<DataFile>) -> Vec<Diagnostic> {
+    files.sort_by(|a, b| a.path.as_os_str().cmp(b.path.as_os_str()));
+
+    let mut diagnostics = Vec::new();
+    let mut kept: Vec<DataFile> = Vec::new();
+    for file in files.drain(..) {
+        let clash = kept
+            .iter()
+            .find(|other| other.key.starts_with(&file.key) || file.key.starts_with(&other.key));
+        match clash {
+            Some(other) => diagnostics.push(Diagnostic::error(
+                &file.path,
+                format!(
+                    "`data.{}` clashes with {}, skipping this file",
+                    file.key.join("."),
+                    other.path.display()
+                ),
+            )),
+            None => kept.push(file),
+        }
+    }
+
+    *files = kept;
+    diagnostics
+}
+
+/// builds the `data` object passed to the templates, nesting files that live
+/// inside sub folders.
+pub fn as_tinylang_state(files: &[DataFile]) -> State {
+    let mut state = State::new();
+
+    for file in files {
+        let (name, parents) = match file.key.split_last() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        let mut current = &mut state;
+        for parent in parents {
+            let entry = current
+                .entry(parent.clone())
+                .or_insert_with(|| TinyLangType::Object(State::new()));
+            if !matches!(entry, TinyLangType::Object(_)) {
+                *entry = TinyLangType::Object(State::new());
+            }
+            current = match entry {
+                TinyLangType::Object(o) => o,
+                _ => unreachable!(),
+            };
+        }
+
+        current.insert(name.clone(), to_tinylang(&file.value));
+    }
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_all_formats() {
+        let folder = Path::new("data");
+
+        let json = DataFile::new(
+            Path::new("data/menu.json"),
+            folder,
+            r#"{"items": [{"title": "Home", "order": 1}]}"#,
+        )
+        .unwrap();
+        let yaml = DataFile::new(Path::new("data/menu.yaml"), folder, "title: Home").unwrap();
+        let toml = DataFile::new(Path::new("data/menu.toml"), folder, "title = \"Home\"").unwrap();
+        let csv = DataFile::new(
+            Path::new("data/talks.csv"),
+            folder,
+            "title,speaker\nRust,Era\nTinyLang,Era",
+        )
+        .unwrap();
+
+        assert_eq!(vec!["menu"], json.key);
+        assert_eq!(1.0, json.value["items"][0]["order"].as_f64().unwrap());
+        assert_eq!("Home", yaml.value["title"]);
+        assert_eq!("Home", toml.value["title"]);
+        assert_eq!("TinyLang", csv.value[1]["title"]);
+    }
+
+    #[test]
+    fn test_nests_sub_folders() {
+        let folder = Path::new("data");
+        let file = DataFile::new(
+            Path::new("data/team/members.json"),
+            folder,
+            r#"[{"name": "Era"}]"#,
+        )
+        .unwrap();
+
+        assert_eq!("team", file.top_level_name());
+
+        let state = as_tinylang_state(&[file]);
+        let team = match state.get("team") {
+            Some(TinyLangType::Object(o)) => o,
+            _ => panic!("team should be an object"),
+        };
+        match team.get("members") {
+            Some(TinyLangType::Vec(members)) => assert_eq!(1, members.len()),
+            _ => panic!("members should be a vector"),
+        }
+    }
+
+    #[test]
+    fn test_reports_clashing_files() {
+        let folder = Path::new("data");
+        let file = |path: &str| DataFile::new(Path::new(path), folder, "[]").unwrap();
+        let mut files = vec![
+            file("data/team/members.json"),
+            file("data/team.json"),
+            file("data/menu.yaml"),
+            file("data/menu.json"),
+            file("data/talks.json"),
+        ];
+
+        let diagnostics = remove_clashes(&mut files);
+
+        let paths: Vec<&Path> = files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            vec![
+                Path::new("data/menu.json"),
+                Path::new("data/talks.json"),
+                Path::new("data/team.json")
+            ],
+            paths
+        );
+        let skipped: Vec<&Path> = diagnostics.iter().map(|d| d.path.as_path()).collect();
+        assert_eq!(
+            vec![
+                Path::new("data/menu.yaml"),
+                Path::new("data/team/members.json")
+            ],
+            skipped
+        );
+        assert!(diagnostics.iter().all(Diagnostic::is_error));
+    }
+
+    #[test]
+    fn test_data_collection_uses_slug_field() {
+        let file = DataFile::new(
+            Path::new("data/products.json"),
+            Path::new("data"),
+            r#"[{"sku": "Blue Shirt", "price": 10}, {"name": "no sku"}, {"sku": 42}]"#,
+        )
+        .unwrap();
+
+        let collection = DataCollection::new(&file, "sku").unwrap();
+        assert_eq!("products", collection.name);
+
+        let slugs: Vec<&str> = collection.records.iter().map(|r| r.slug.as_str()).collect();
+        assert_eq!(vec!["blue-shirt", "42"], slugs);
+        assert_eq!(1, collection.diagnostics.len());
+        assert!(!collection.diagnostics[0].is_error());
+
+        let state = collection.records[0].as_tinylang_state(&collection.name);
+        assert_eq!(
+            "/products/blue-shirt.html",
+            state.get("partial_uri").unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_data_collection_reports_unusable_slugs() {
+        let file = DataFile::new(
+            Path::new("data/products.json"),
+            Path::new("data"),
+            r#"[{"slug": "shirt"}, {"slug": "Shirt"}, {"slug": "!!!"}, {"slug": "hat"}]"#,
+        )
+        .unwrap();
+
+        let collection = DataCollection::new(&file, "slug").unwrap();
+        let slugs: Vec<&str> = collection.records.iter().map(|r| r.slug.as_str()).collect();
+        assert_eq!(vec!["shirt", "hat"], slugs);
+
+        let messages: Vec<&str> = collection
+            .diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "record 1 has the same slug as an earlier record: shirt",
+                "record 2 has an empty slug, skipping its page",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_data_collection_requires_list() {
+        let file = DataFile::new(Path::new("data/menu.toml"), Path::new("data"), "a = 1").unwrap();
+        assert!(DataCollection::new(&file, "slug").is_none());
+    }
+
+    #[test]
+    fn test_rejects_invalid_content() {
+        let result = DataFile::new(Path::new("data/menu.json"), Path::new("data"), "{");
+        assert!(result.is_err());
+    }
+}
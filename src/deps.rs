@@ -13,6 +13,8 @@ pub enum FileChangeType {
     Markdown,
     Config,
     Static,
+    Data,
+    Gallery,
 }
 
 /// A file change event with the specific path(s) that changed.
@@ -27,7 +29,13 @@ pub struct FileChangeEvent {
 /// Tracks:
 /// - Template A includes template B via render() → when B changes, A's output must rebuild
 /// - Partial _X.template renders collection X → when partial changes, all X outputs rebuild
-/// - Markdown in collection X → when md changes, only that md's output rebuilds
+/// - Template A reads `data.X` → when a data file under X changes, A's output must rebuild
+/// - Partial _X.template renders data file X → when X changes, all its record outputs rebuild
+/// - Markdown M uses shortcode S → when S changes, M's output must rebuild
+/// - Markdown M uses admonition kind K → when K's template changes, M's output must rebuild
+/// - Stylesheet S loads Sass file F → when F changes, S must be compiled again
+/// - Template A calls `asset('X')` → when the url or integrity of X changes, A's output must rebuild
+/// - Photos and `_gallery.template` are listed by any template → full rebuild
 /// - Config affects global state → full rebuild
 pub struct DependencyGraph {
     template_folder: PathBuf,
@@ -48,6 +56,22 @@ pub struct DependencyGraph {
     output_to_markdown: HashMap<PathBuf, (PathBuf, String)>,
     /// All templates (for transitive closure)
     all_templates: HashSet<PathBuf>,
+    /// Data name (`data.<name>`) -> templates reading it
+    data_dependents: HashMap<String, HashSet<PathBuf>>,
+    /// Data file path -> data name
+    data_files: HashMap<PathBuf, String>,
+    /// Output path -> (data file path, collection_name) for data-driven pages
+    data_record_outputs: HashMap<PathBuf, (PathBuf, String)>,
+    /// Shortcode template path -> markdown files using it
+    shortcode_users: HashMap<PathBuf, HashSet<PathBuf>>,
+    /// Admonition template path -> markdown files using its kind
+    admonition_users: HashMap<PathBuf, HashSet<PathBuf>>,
+    /// Templates rendering the photo pages
+    gallery_templates: HashSet<PathBuf>,
+    /// Sass file -> stylesheets loading it
+    stylesheet_users: HashMap<PathBuf, HashSet<PathBuf>>,
+    /// Asset path, relative to the website root -> templates using it
+    asset_dependents: HashMap<String, HashSet<PathBuf>>,
 }
 
 impl DependencyGraph {
@@ -63,6 +87,14 @@ impl DependencyGraph {
             output_to_template: HashMap::new(),
             output_to_markdown: HashMap::new(),
             all_templates: HashSet::new(),
+            data_dependents: HashMap::new(),
+            data_files: HashMap::new(),
+            data_record_outputs: HashMap::new(),
+            shortcode_users: HashMap::new(),
+            admonition_users: HashMap::new(),
+            gallery_templates: HashSet::new(),
+            stylesheet_users: HashMap::new(),
+            asset_dependents: HashMap::new(),
         }
     }
 
@@ -79,6 +111,24 @@ impl DependencyGraph {
         paths
     }
 
+    /// Extract the data names read by a template. Matches `data.<name>`.
+    fn parse_data_usages(content: &str) -> HashSet<String> {
+        regex::Regex::new(r"\bdata\.([A-Za-z0-9_\-]+)")
+            .unwrap()
+            .captures_iter(content)
+            .map(|cap| cap[1].to_string())
+            .collect()
+    }
+
+    /// Extract the assets used by a template. Matches `asset('path')` and `asset_integrity('path')`.
+    fn parse_asset_usages(content: &str) -> HashSet<String> {
+        regex::Regex::new(r#"\basset(?:_integrity)?\s*\(\s*['"]/?([^'"]+)['"]\s*\)"#)
+            .unwrap()
+            .captures_iter(content)
+            .map(|cap| cap[1].to_string())
+            .collect()
+    }
+
     /// Resolve a path from a render() call to an absolute path.
     fn resolve_render_path(
         render_path: &str,
@@ -130,6 +180,26 @@ impl DependencyGraph {
                     .insert(template_path.clone());
             }
         }
+
+        for data_name in Self::parse_data_usages(content) {
+            self.data_dependents
+                .entry(data_name)
+                .or_default()
+                .insert(template_path.clone());
+        }
+
+        for asset in Self::parse_asset_usages(content) {
+            self.asset_dependents
+                .entry(asset)
+                .or_default()
+                .insert(template_path.clone());
+        }
+    }
+
+    /// Register a data file, `data_name` is how templates access it (`data.<data_name>`).
+    pub fn register_data_file(&mut self, data_path: PathBuf, data_name: &str) {
+        let data_path = data_path.canonicalize().unwrap_or(data_path);
+        self.data_files.insert(data_path, data_name.to_string());
     }
 
     /// Register a standalone template (produces one output).
@@ -168,6 +238,69 @@ impl DependencyGraph {
             .insert(output_path, (markdown_path, collection_name.to_string()));
     }
 
+    /// Register the output of a record of a data collection.
+    pub fn register_data_record_output(
+        &mut self,
+        data_path: PathBuf,
+        collection_name: &str,
+        output_path: PathBuf,
+    ) {
+        let data_path = data_path.canonicalize().unwrap_or(data_path);
+        self.data_record_outputs
+            .insert(output_path, (data_path, collection_name.to_string()));
+    }
+
+    /// Register a markdown file rendering the given shortcode template.
+    pub fn register_shortcode_usage(&mut self, markdown_path: PathBuf, shortcode_path: PathBuf) {
+        let markdown_path = markdown_path.canonicalize().unwrap_or(markdown_path);
+        let shortcode_path = shortcode_path.canonicalize().unwrap_or(shortcode_path);
+        self.shortcode_users
+            .entry(shortcode_path)
+            .or_default()
+            .insert(markdown_path);
+    }
+
+    /// Register a markdown file rendering the given admonition template.
+    pub fn register_admonition_usage(&mut self, markdown_path: PathBuf, template_path: PathBuf) {
+        let markdown_path = markdown_path.canonicalize().unwrap_or(markdown_path);
+        let template_path = template_path.canonicalize().unwrap_or(template_path);
+        self.admonition_users
+            .entry(template_path)
+            .or_default()
+            .insert(markdown_path);
+    }
+
+    /// Register the template rendering the photo pages of the galleries.
+    pub fn register_gallery_template(&mut self, template_path: PathBuf) {
+        let template_path = template_path.canonicalize().unwrap_or(template_path);
+        self.gallery_templates.insert(template_path);
+    }
+
+    /// Register a stylesheet loading a Sass file with `@use`, `@forward` or `@import`.
+    pub fn register_stylesheet_import(&mut self, stylesheet_path: PathBuf, import_path: PathBuf) {
+        let stylesheet_path = stylesheet_path.canonicalize().unwrap_or(stylesheet_path);
+        let import_path = import_path.canonicalize().unwrap_or(import_path);
+        self.stylesheet_users
+            .entry(import_path)
+            .or_default()
+            .insert(stylesheet_path);
+    }
+
+    /// Find the stylesheets that must be compiled again when the given static files change.
+    pub fn affected_stylesheets(&self, change: &FileChangeEvent) -> HashSet<PathBuf> {
+        if change.change_type != FileChangeType::Static {
+            return HashSet::new();
+        }
+        change
+            .paths
+            .iter()
+            .map(|path| path.canonicalize().unwrap_or(path.clone()))
+            .filter_map(|path| self.stylesheet_users.get(&path))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
     /// Find all output paths that need to be rebuilt when the given file changes.
     pub fn affected_outputs(&self, change: &FileChangeEvent) -> HashSet<PathBuf> {
         let mut outputs = HashSet::new();
@@ -176,7 +309,7 @@ impl DependencyGraph {
             let path = path.canonicalize().unwrap_or(path.clone());
 
             match &change.change_type {
-                FileChangeType::Config => {
+                FileChangeType::Config | FileChangeType::Gallery => {
                     // Config change: full rebuild - return empty to signal full rebuild
                     return HashSet::new();
                 }
@@ -184,49 +317,34 @@ impl DependencyGraph {
                     // Static: caller copies files. No template outputs to rebuild.
                 }
                 FileChangeType::Markdown => {
-                    if let Some((_, output_path)) = self.markdown_outputs.get(&path) {
-                        outputs.insert(output_path.clone());
-                    }
+                    // Markdown: every document is converted again, see `build_incremental`.
                 }
                 FileChangeType::Template => {
-                    // 1. Outputs from this template directly (standalone or partial)
-                    if let Some(out) = self.standalone_outputs.get(&path) {
-                        outputs.insert(out.clone());
-                    }
-                    if let Some(coll_name) = self.partial_to_collection.get(&path) {
-                        for (_, (_, out)) in self
-                            .markdown_outputs
-                            .iter()
-                            .filter(|(_, (c, _))| c == coll_name)
-                        {
-                            outputs.insert(out.clone());
+                    self.collect_template_outputs(&path, &mut outputs);
+                    let users = [&self.shortcode_users, &self.admonition_users]
+                        .into_iter()
+                        .filter_map(|users| users.get(&path))
+                        .flatten();
+                    for markdown_path in users {
+                        if let Some((_, output_path)) = self.markdown_outputs.get(markdown_path) {
+                            outputs.insert(output_path.clone());
                         }
                     }
-                    // 2. Templates that include this one (transitive)
-                    let mut to_check = vec![path.clone()];
-                    let mut checked = HashSet::new();
-                    while let Some(check_path) = to_check.pop() {
-                        if !checked.insert(check_path.clone()) {
-                            continue;
-                        }
-                        if let Some(dependents) = self.reverse_template_deps.get(&check_path) {
-                            for dep in dependents {
-                                to_check.push(dep.clone());
-                                // Add outputs for these dependent templates
-                                if let Some(out) = self.standalone_outputs.get(dep) {
-                                    outputs.insert(out.clone());
-                                }
-                                if let Some(coll_name) = self.partial_to_collection.get(dep) {
-                                    for (_, (_, out)) in self
-                                        .markdown_outputs
-                                        .iter()
-                                        .filter(|(_, (c, _))| c == coll_name)
-                                    {
-                                        outputs.insert(out.clone());
-                                    }
-                                }
-                            }
-                        }
+                }
+                FileChangeType::Data => {
+                    let dependents = self
+                        .data_files
+                        .get(&path)
+                        .and_then(|name| self.data_dependents.get(name));
+                    for template in dependents.into_iter().flatten() {
+                        self.collect_template_outputs(template, &mut outputs);
+                    }
+                    for (out, _) in self
+                        .data_record_outputs
+                        .iter()
+                        .filter(|(_, (data_path, _))| data_path == &path)
+                    {
+                        outputs.insert(out.clone());
                     }
                 }
             }
@@ -235,9 +353,84 @@ impl DependencyGraph {
         outputs
     }
 
+    /// Find the outputs using the given assets, `assets` are paths relative to the website root.
+    pub fn affected_asset_outputs(&self, assets: &HashSet<String>) -> HashSet<PathBuf> {
+        let mut outputs = HashSet::new();
+        for template in assets
+            .iter()
+            .filter_map(|asset| self.asset_dependents.get(asset))
+            .flatten()
+        {
+            self.collect_template_outputs(template, &mut outputs);
+        }
+        outputs
+    }
+
+    /// Add the outputs produced by the template and by every template that includes it.
+    fn collect_template_outputs(&self, path: &Path, outputs: &mut HashSet<PathBuf>) {
+        // 1. Outputs from this template directly (standalone or partial)
+        self.collect_direct_outputs(path, outputs);
+        // 2. Templates that include this one (transitive)
+        let mut to_check = vec![path.to_path_buf()];
+        let mut checked = HashSet::new();
+        while let Some(check_path) = to_check.pop() {
+            if !checked.insert(check_path.clone()) {
+                continue;
+            }
+            if let Some(dependents) = self.reverse_template_deps.get(&check_path) {
+                for dep in dependents {
+                    to_check.push(dep.clone());
+                    // Add outputs for these dependent templates
+                    self.collect_direct_outputs(dep, outputs);
+                }
+            }
+        }
+    }
+
+    /// Add the outputs produced by the template itself (standalone or partial).
+    fn collect_direct_outputs(&self, path: &Path, outputs: &mut HashSet<PathBuf>) {
+        if let Some(out) = self.standalone_outputs.get(path) {
+            outputs.insert(out.clone());
+        }
+        if let Some(coll_name) = self.partial_to_collection.get(path) {
+            for (_, (_, out)) in self
+                .markdown_outputs
+                .iter()
+                .filter(|(_, (c, _))| c == coll_name)
+            {
+                outputs.insert(out.clone());
+            }
+            for (out, _) in self
+                .data_record_outputs
+                .iter()
+                .filter(|(_, (_, c))| c == coll_name)
+            {
+                outputs.insert(out.clone());
+            }
+        }
+    }
+
     /// Returns true if the change requires a full rebuild (e.g. config change).
     pub fn requires_full_rebuild(&self, change: &FileChangeEvent) -> bool {
-        change.change_type == FileChangeType::Config
+        match change.change_type {
+            FileChangeType::Config | FileChangeType::Gallery => true,
+            FileChangeType::Template => change.paths.iter().any(|path| {
+                let path = path.canonicalize().unwrap_or(path.clone());
+                self.gallery_templates.contains(&path)
+            }),
+            _ => false,
+        }
+    }
+
+    /// Returns true if a shortcode or admonition template used by a markdown file changed,
+    /// the markdown must be converted again before its output is rebuilt.
+    pub fn is_markdown_template_change(&self, change: &FileChangeEvent) -> bool {
+        change.change_type == FileChangeType::Template
+            && change.paths.iter().any(|path| {
+                let path = path.canonicalize().unwrap_or(path.clone());
+                self.shortcode_users.contains_key(&path)
+                    || self.admonition_users.contains_key(&path)
+            })
     }
 
     /// Returns true if this is a static file change (handled by copy, not template build).
@@ -245,6 +438,16 @@ impl DependencyGraph {
         change.change_type == FileChangeType::Static
     }
 
+    /// All the pages produced by templates, markdown documents and data records.
+    pub fn outputs(&self) -> HashSet<PathBuf> {
+        self.standalone_outputs
+            .values()
+            .chain(self.output_to_markdown.keys())
+            .chain(self.data_record_outputs.keys())
+            .cloned()
+            .collect()
+    }
+
     /// Get the template path that produces this output (for standalone templates).
     pub fn template_for_output(&self, output_path: &Path) -> Option<PathBuf> {
         self.output_to_template.get(output_path).cloned()
@@ -255,8 +458,51 @@ impl DependencyGraph {
         self.output_to_markdown.get(output_path).cloned()
     }
 
+    /// Get the (data file path, collection name) for a data-driven page.
+    pub fn data_record_for_output(&self, output_path: &Path) -> Option<(PathBuf, String)> {
+        self.data_record_outputs.get(output_path).cloned()
+    }
+
     /// Get the partial template path for a collection.
     pub fn partial_for_collection(&self, collection_name: &str) -> Option<PathBuf> {
         self.collection_partials.get(collection_name).cloned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(change_type: FileChangeType, path: &str) -> FileChangeEvent {
+        FileChangeEvent {
+            change_type,
+            paths: vec![PathBuf::from(path)],
+        }
+    }
+
+    #[test]
+    fn test_admonition_templates_rebuild_their_users() {
+        let mut deps = DependencyGraph::new("templates".into(), "output".into());
+        deps.register_markdown_output("posts/a.md".into(), "posts", "output/posts/a.html".into());
+        deps.register_markdown_output("posts/b.md".into(), "posts", "output/posts/b.html".into());
+        deps.register_admonition_usage(
+            "posts/a.md".into(),
+            "templates/admonitions/tip.template".into(),
+        );
+
+        let tip = change(
+            FileChangeType::Template,
+            "templates/admonitions/tip.template",
+        );
+        assert!(deps.is_markdown_template_change(&tip));
+        assert_eq!(
+            HashSet::from([PathBuf::from("output/posts/a.html")]),
+            deps.affected_outputs(&tip)
+        );
+        let note = change(
+            FileChangeType::Template,
+            "templates/admonitions/note.template",
+        );
+        assert!(!deps.is_markdown_template_change(&note));
+    }
+}
@@ -0,0 +1,183 @@
+//! Code blocks and code spans of a markdown document, for the extensions working on
+//! the markdown source (shortcodes, admonitions...) which must leave code alone.
+
+use regex::Regex;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+fn code_fence_regex() -> &'static Regex {
+    static FENCE: OnceLock<Regex> = OnceLock::new();
+    FENCE.get_or_init(|| Regex::new(r"^\s{0,3}(`{3,}|~{3,})").unwrap())
+}
+
+/// four spaces or a tab, the indentation of indented code blocks
+fn indented_regex() -> &'static Regex {
+    static INDENTED: OnceLock<Regex> = OnceLock::new();
+    INDENTED.get_or_init(|| Regex::new(r"^ {0,3}(?:\t| {4})").unwrap())
+}
+
+fn list_item_regex() -> &'static Regex {
+    static LIST_ITEM: OnceLock<Regex> = OnceLock::new();
+    LIST_ITEM.get_or_init(|| Regex::new(r"^ {0,3}(?:[-*+]|\d{1,9}[.)])(?:[ \t]|$)").unwrap())
+}
+
+/// follows the code blocks while the lines of the document are read in order
+#[derive(Default)]
+pub struct CodeBlocks {
+    fence: Option<String>,
+    /// inside an indented code block
+    indented: bool,
+    /// the previous line is text, which indented lines continue
+    paragraph: bool,
+    /// inside a list, where indented lines belong to the items
+    list: bool,
+}
+
+impl CodeBlocks {
+    /// byte ranges of the lines of the document belonging to code blocks
+    pub fn ranges(markdown: &str) -> Vec<Range<usize>> {
+        let mut code = Self::default();
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        for line in markdown.split_inclusive('\n') {
+            if code.contains(line.trim_end_matches(['\r', '\n'])) {
+                ranges.push(start..start + line.len());
+            }
+            start += line.len();
+        }
+        ranges
+    }
+
+    /// byte ranges of the code blocks and of the `code spans` of the document
+    pub fn ranges_with_spans(markdown: &str) -> Vec<Range<usize>> {
+        let mut ranges = Self::ranges(markdown);
+        let mut spans = Vec::new();
+        let mut text = 0;
+        for range in ranges.iter().chain([&(markdown.len()..markdown.len())]) {
+            code_spans(&markdown[text..range.start], text, &mut spans);
+            text = range.end;
+        }
+        ranges.extend(spans);
+        ranges
+    }
+
+    /// whether the line belongs to a code block, its fences included
+    pub fn contains(&mut self, line: &str) -> bool {
+        if self.fence.is_none() {
+            if line.trim().is_empty() {
+                self.paragraph = false;
+                return false;
+            }
+            let indented = indented_regex().is_match(line);
+            if indented && (self.indented || !(self.paragraph || self.list)) {
+                self.indented = true;
+                return true;
+            }
+            self.indented = false;
+            if list_item_regex().is_match(line) {
+                self.list = true;
+            } else if !indented && !self.paragraph {
+                self.list = false;
+            }
+        }
+
+        let Some(marker) = code_fence_regex().captures(line).map(|c| c[1].to_string()) else {
+            self.paragraph = self.fence.is_none();
+            return self.fence.is_some();
+        };
+        match &self.fence {
+            Some(f) if marker.starts_with(f.as_str()) => self.fence = None,
+            None => self.fence = Some(marker),
+            _ => {}
+        }
+        self.paragraph = false;
+        true
+    }
+}
+
+/// finds the code spans of text outside code blocks, a run of backticks opens a span
+/// closed by a run of the same length in the same paragraph
+fn code_spans(text: &str, offset: usize, spans: &mut Vec<Range<usize>>) {
+    let bytes = text.as_bytes();
+    let run = |start: usize| bytes[start..].iter().take_while(|b| **b == b'`').count();
+
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] == b'\\' {
+            position += 2;
+            continue;
+        }
+        if bytes[position] != b'`' {
+            position += 1;
+            continue;
+        }
+
+        let opening = run(position);
+        let mut end = position + opening;
+        let mut closed = None;
+        while end < bytes.len() {
+            if bytes[end] == b'`' {
+                let closing = run(end);
+                if closing == opening {
+                    closed = Some(end + closing);
+                    break;
+                }
+                end += closing;
+            } else if bytes[end..].starts_with(b"\n\n") {
+                break;
+            } else {
+                end += 1;
+            }
+        }
+
+        match closed {
+            Some(close) => {
+                spans.push(offset + position..offset + close);
+                position = close;
+            }
+            None => position += opening,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_lines(markdown: &str) -> Vec<bool> {
+        let mut code = CodeBlocks::default();
+        markdown.lines().map(|l| code.contains(l)).collect()
+    }
+
+    #[test]
+    fn test_fenced_code_blocks() {
+        let markdown = "text\n```rust\n~~~\ncode\n```\ntext\n~~~~\n```\n~~~~~\ntext";
+
+        assert_eq!(
+            vec![false, true, true, true, true, false, true, true, true, false],
+            code_lines(markdown)
+        );
+    }
+
+    #[test]
+    fn test_indented_code_blocks() {
+        let markdown = "text\n    continued\n\n    code\n\n\tcode\ntext\n\n- item\n\n    item text";
+
+        assert_eq!(
+            vec![false, false, false, true, false, true, false, false, false, false, false],
+            code_lines(markdown)
+        );
+    }
+
+    #[test]
+    fn test_code_spans() {
+        let markdown = "a `one` b ``two ` two`` \\`no` ```\n\n`x`\n```\n`in block`\n```\n";
+
+        let spans: Vec<&str> = CodeBlocks::ranges_with_spans(markdown)
+            .into_iter()
+            .map(|range| &markdown[range])
+            .filter(|code| !code.ends_with('\n'))
+            .collect();
+        assert_eq!(vec!["`one`", "``two ` two``", "`x`"], spans);
+    }
+}
@@ -0,0 +1,661 @@
+//! Photo galleries, collections built straight from folders of images.
+//!
+//! Every image inside the gallery folder belongs to the collection named after its
+//! folder, like markdown documents do, and gets a page rendered by `_gallery.template`
+//! with the photo as `content`. Photos expose their dimensions, EXIF data and
+//! thumbnails, and are published next to their page, optionally without their EXIF.
+
+use crate::config::{GalleryConfiguration, ImageProcessingConfiguration};
+use crate::diagnostic::Diagnostic;
+use crate::io::{write_if_changed, LazyFolderReader};
+use crate::placeholder::{Placeholder, Placeholders};
+use crate::resize::{ImageProcessor, Original, Variant};
+use anyhow::{anyhow, Context, Result};
+use exif::{In, Tag, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use tinylang::types::{State, TinyLangType};
+
+/// partial rendering the page of every photo
+pub const GALLERY_TEMPLATE: &str = "_gallery.template";
+
+/// extensions of the images that become photos
+pub const PHOTO_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "JPG", "JPEG", "PNG", "WEBP"];
+
+#[derive(Debug, Clone)]
+pub struct GalleryCollection {
+    pub name: String,
+    pub photos: Vec<Photo>,
+}
+
+impl GalleryCollection {
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        state.insert(
+            "size".into(),
+            TinyLangType::Numeric(self.photos.len() as f64),
+        );
+        state.insert(
+            "items".into(),
+            TinyLangType::Vec(
+                self.photos
+                    .iter()
+                    .map(|p| TinyLangType::Object(p.as_tinylang_state()))
+                    .collect(),
+            ),
+        );
+        state
+    }
+}
+
+/// EXIF data we expose, formatted for humans
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PhotoExif {
+    pub date_taken: Option<String>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub exposure: Option<String>,
+    pub aperture: Option<String>,
+    pub iso: Option<String>,
+    pub focal_length: Option<String>,
+    /// (latitude, longitude), only read when `gps` is enabled
+    pub gps: Option<(f64, f64)>,
+    /// how the image must be rotated to be displayed, 1 means as is
+    pub orientation: u32,
+}
+
+impl PhotoExif {
+    /// images without EXIF data give an empty one
+    pub fn read(contents: &[u8], gps: bool) -> Self {
+        let exif = match exif::Reader::new().read_from_container(&mut Cursor::new(contents)) {
+            Ok(exif) => exif,
+            Err(_) => {
+                return Self {
+                    orientation: 1,
+                    ..Default::default()
+                }
+            }
+        };
+
+        let text = |tag: Tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => values
+                .first()
+                .map(|v| String::from_utf8_lossy(v).trim().to_string())
+                .filter(|v| !v.is_empty()),
+            _ => None,
+        };
+        let display = |tag: Tag| {
+            exif.get_field(tag, In::PRIMARY)
+                .map(|f| f.display_value().with_unit(&exif).to_string())
+        };
+
+        let camera = match (text(Tag::Make), text(Tag::Model)) {
+            // most models already start with the make, e.g. `Canon` `Canon EOS R6`
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{make} {model}")),
+            (make, model) => make.or(model),
+        };
+
+        Self {
+            date_taken: display(Tag::DateTimeOriginal).or_else(|| display(Tag::DateTime)),
+            camera,
+            lens: text(Tag::LensModel),
+            exposure: display(Tag::ExposureTime),
+            aperture: display(Tag::FNumber),
+            iso: display(Tag::PhotographicSensitivity),
+            focal_length: display(Tag::FocalLength),
+            gps: gps.then(|| Self::coordinates(&exif)).flatten(),
+            orientation: exif
+                .get_field(Tag::Orientation, In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+                .unwrap_or(1),
+        }
+    }
+
+    fn coordinates(exif: &exif::Exif) -> Option<(f64, f64)> {
+        let coordinate = |tag: Tag, reference: Tag, negative: &str| {
+            let degrees = match &exif.get_field(tag, In::PRIMARY)?.value {
+                Value::Rational(parts) if parts.len() == 3 => {
+                    parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0
+                }
+                _ => return None,
+            };
+            let reference = exif
+                .get_field(reference, In::PRIMARY)
+                .map(|f| f.display_value().to_string())
+                .unwrap_or_default();
+            Some(match reference.contains(negative) {
+                true => -degrees,
+                false => degrees,
+            })
+        };
+
+        Some((
+            coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?,
+            coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?,
+        ))
+    }
+
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        let fields = [
+            ("date_taken", &self.date_taken),
+            ("camera", &self.camera),
+            ("lens", &self.lens),
+            ("exposure", &self.exposure),
+            ("aperture", &self.aperture),
+            ("iso", &self.iso),
+            ("focal_length", &self.focal_length),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                state.insert(key.into(), value.clone().into());
+            }
+        }
+        if let Some((latitude, longitude)) = self.gps {
+            let mut gps = State::new();
+            gps.insert("latitude".into(), TinyLangType::Numeric(latitude));
+            gps.insert("longitude".into(), TinyLangType::Numeric(longitude));
+            state.insert("gps".into(), TinyLangType::Object(gps));
+        }
+        state
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Photo {
+    /// file name, e.g. `sunset.jpg`
+    pub name: String,
+    /// url of the published image
+    pub src: String,
+    /// url of the photo page
+    pub partial_uri: String,
+    /// as displayed, after applying the EXIF orientation
+    pub width: u32,
+    pub height: u32,
+    pub exif: PhotoExif,
+    /// smallest first
+    pub thumbnails: Vec<Variant>,
+    pub placeholder: Option<Placeholder>,
+}
+
+impl Photo {
+    /// name of the generated page
+    pub fn page_name(&self) -> String {
+        Path::new(&self.name)
+            .with_extension("html")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        let title = Path::new(&self.name)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        state.insert("title".into(), title.into());
+        state.insert("name".into(), self.name.clone().into());
+        state.insert("src".into(), self.src.clone().into());
+        state.insert("partial_uri".into(), self.partial_uri.clone().into());
+        state.insert("width".into(), TinyLangType::Numeric(self.width as f64));
+        state.insert("height".into(), TinyLangType::Numeric(self.height as f64));
+        // top level so galleries can be sorted with `sort_by_key`
+        if let Some(date) = &self.exif.date_taken {
+            state.insert("date".into(), date.clone().into());
+        }
+        state.insert(
+            "exif".into(),
+            TinyLangType::Object(self.exif.as_tinylang_state()),
+        );
+
+        let thumbnails: Vec<TinyLangType> = self
+            .thumbnails
+            .iter()
+            .map(|t| {
+                let mut thumbnail = State::new();
+                thumbnail.insert("src".into(), t.url.clone().into());
+                thumbnail.insert("width".into(), TinyLangType::Numeric(t.width as f64));
+                TinyLangType::Object(thumbnail)
+            })
+            .collect();
+        if let Some(TinyLangType::Object(first)) = thumbnails.first() {
+            state.insert("thumbnail".into(), first["src"].clone());
+        }
+        state.insert("thumbnails".into(), TinyLangType::Vec(thumbnails));
+
+        if let Some(placeholder) = &self.placeholder {
+            placeholder.insert_into_state(&mut state);
+        }
+        state
+    }
+}
+
+/// builds the galleries of a folder, publishing the photos and their thumbnails
+pub struct GalleryBuilder<'a> {
+    config: &'a GalleryConfiguration,
+    thumbnails: ImageProcessor,
+    placeholders: Option<&'a Placeholders>,
+    output_folder: &'a Path,
+    /// names of the template state, galleries cannot take them
+    reserved: HashSet<String>,
+}
+
+impl<'a> GalleryBuilder<'a> {
+    pub fn new(
+        config: &'a GalleryConfiguration,
+        images: &ImageProcessingConfiguration,
+        cache_folder: &Path,
+        output_folder: &'a Path,
+    ) -> Self {
+        let thumbnails = ImageProcessingConfiguration {
+            widths: config.thumbnails.clone(),
+            formats: vec![config.format.clone()],
+            ..images.clone()
+        };
+        Self {
+            config,
+            thumbnails: ImageProcessor::new(
+                thumbnails,
+                Vec::new(),
+                cache_folder.to_path_buf(),
+                output_folder.to_path_buf(),
+            ),
+            placeholders: None,
+            output_folder,
+            reserved: HashSet::new(),
+        }
+    }
+
+    pub fn with_placeholders(mut self, placeholders: Option<&'a Placeholders>) -> Self {
+        self.placeholders = placeholders;
+        self
+    }
+
+    pub fn with_reserved_names(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.reserved = names.into_iter().collect();
+        self
+    }
+
+    /// photos that cannot be read are skipped, galleries with a reserved name are not
+    /// published and reported
+    pub fn build(
+        &self,
+        folder: &Path,
+    ) -> Result<(HashMap<String, GalleryCollection>, Vec<Diagnostic>)> {
+        let mut galleries: HashMap<String, GalleryCollection> = HashMap::new();
+        let mut rejected = BTreeMap::new();
+
+        let mut files = LazyFolderReader::with_extensions(folder, PHOTO_EXTENSIONS)
+            .context("could not create lazy folder reader for gallery folder")?
+            .into_paths();
+        files.sort();
+
+        for path in files {
+            let name = match path.parent().and_then(|p| p.file_name()) {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if self.reserved.contains(&name) {
+                if let Some(parent) = path.parent() {
+                    rejected.insert(parent.to_path_buf(), name);
+                }
+                continue;
+            }
+
+            match self.photo(&path, &name) {
+                Ok(photo) => galleries
+                    .entry(name.clone())
+                    .or_insert_with(|| GalleryCollection {
+                        name,
+                        photos: Vec::new(),
+                    })
+                    .photos
+                    .push(photo),
+                Err(e) => eprintln!("{e:?}"),
+            }
+        }
+
+        let diagnostics = rejected
+            .into_iter()
+            .map(|(folder, name)| {
+                Diagnostic::error(
+                    folder,
+                    format!(
+                        "the gallery `{name}` has the name of a collection or template variable"
+                    ),
+                )
+            })
+            .collect();
+        Ok((galleries, diagnostics))
+    }
+
+    fn photo(&self, path: &Path, gallery: &str) -> Result<Photo> {
+        let contents = fs::read(path).with_context(|| format!("could not read {:?}", path))?;
+        let name = path
+            .file_name()
+            .ok_or(anyhow!("{:?} has no file name", path))?
+            .to_string_lossy()
+            .to_string();
+
+        let size = imagesize::blob_size(&contents)
+            .with_context(|| format!("could not read the size of {:?}", path))?;
+        let exif = PhotoExif::read(&contents, self.config.gps);
+        // orientations 5 to 8 are rotated by 90 degrees
+        let (width, height) = match exif.orientation {
+            5..=8 => (size.height as u32, size.width as u32),
+            _ => (size.width as u32, size.height as u32),
+        };
+
+        let published = match self.config.strip_exif {
+            true => strip_exif(&contents, exif.orientation)
+                .with_context(|| format!("could not strip the EXIF of {:?}", path))?,
+            false => contents,
+        };
+        let output = self.output_folder.join(gallery);
+        fs::create_dir_all(&output)?;
+        write_if_changed(&output.join(&name), published)?;
+
+        let thumbnails = Original::read(path)
+            .and_then(|mut original| self.thumbnails.variants(&mut original, &self.config.format))
+            .with_context(|| format!("could not generate the thumbnails of {:?}", path))?;
+        let placeholder = match self.placeholders.map(|p| p.get(path)) {
+            Some(Ok(placeholder)) => Some(placeholder),
+            Some(Err(e)) => {
+                eprintln!("{e:?}");
+                None
+            }
+            None => None,
+        };
+
+        let photo = Photo {
+            src: format!("/{gallery}/{name}"),
+            partial_uri: String::new(),
+            name,
+            width,
+            height,
+            exif,
+            thumbnails,
+            placeholder,
+        };
+        Ok(Photo {
+            partial_uri: format!("/{gallery}/{}", photo.page_name()),
+            ..photo
+        })
+    }
+}
+
+/// removes the EXIF and XMP metadata of a JPEG, PNG or WebP image without re-encoding it.
+/// JPEGs keep their orientation so they are still displayed the right way up.
+pub fn strip_exif(contents: &[u8], orientation: u32) -> Result<Vec<u8>> {
+    if contents.starts_with(&[0xFF, 0xD8]) {
+        strip_jpeg(contents, orientation)
+    } else if contents.starts_with(b"\x89PNG\r\n\x1a\n") {
+        strip_png(contents)
+    } else if contents.starts_with(b"RIFF") && contents.get(8..12) == Some(&b"WEBP"[..]) {
+        strip_webp(contents)
+    } else {
+        Err(anyhow!("unsupported image format"))
+    }
+}
+
+fn strip_jpeg(contents: &[u8], orientation: u32) -> Result<Vec<u8>> {
+    let mut output = contents[..2].to_vec();
+    if orientation > 1 {
+        output.extend(orientation_segment(orientation as u16));
+    }
+
+    let mut position = 2;
+    while position + 4 <= contents.len() {
+        if contents[position] != 0xFF {
+            return Err(anyhow!("invalid JPEG marker at {position}"));
+        }
+        let marker = contents[position + 1];
+        // start of scan, the rest is image data
+        if marker == 0xDA {
+            break;
+        }
+        let length = u16::from_be_bytes([contents[position + 2], contents[position + 3]]) as usize;
+        // the length counts its own two bytes
+        if length < 2 {
+            return Err(anyhow!("invalid JPEG segment length at {position}"));
+        }
+        let end = position + 2 + length;
+        let segment = contents
+            .get(position..end)
+            .ok_or(anyhow!("truncated JPEG segment"))?;
+        let data = &segment[4..];
+        let metadata = marker == 0xE1
+            && (data.starts_with(b"Exif\0") || data.starts_with(b"http://ns.adobe.com/xap/"));
+        if !metadata {
+            output.extend_from_slice(segment);
+        }
+        position = end;
+    }
+
+    output.extend_from_slice(&contents[position..]);
+    Ok(output)
+}
+
+/// APP1 segment with an EXIF holding only the orientation
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut exif = b"Exif\0\0II\x2a\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+    exif.extend(orientation.to_le_bytes());
+    exif.extend([0, 0, 0, 0, 0, 0]);
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend(((exif.len() + 2) as u16).to_be_bytes());
+    segment.extend(exif);
+    segment
+}
+
+fn strip_png(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut output = contents[..8].to_vec();
+    let mut position = 8;
+    while position + 8 <= contents.len() {
+        let length = u32::from_be_bytes(contents[position..position + 4].try_into()?) as usize;
+        let end = position + 12 + length;
+        let chunk = contents
+            .get(position..end)
+            .ok_or(anyhow!("truncated PNG chunk"))?;
+        let kind = &chunk[4..8];
+        let metadata =
+            kind == b"eXIf" || (kind == b"iTXt" && chunk[8..].starts_with(b"XML:com.adobe.xmp"));
+        if !metadata {
+            output.extend_from_slice(chunk);
+        }
+        position = end;
+    }
+    Ok(output)
+}
+
+fn strip_webp(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut position = 12;
+    while position + 8 <= contents.len() {
+        let length = u32::from_le_bytes(contents[position + 4..position + 8].try_into()?) as usize;
+        // chunks are padded to an even size
+        let end = (position + 8 + length + length % 2).min(contents.len());
+        let kind = &contents[position..position + 4];
+        if kind != b"EXIF" && kind != b"XMP " {
+            let mut chunk = contents[position..end].to_vec();
+            // clear the EXIF and XMP flags of the extended header
+            if kind == b"VP8X" && chunk.len() > 8 {
+                chunk[8] &= !0b1100;
+            }
+            chunks.extend(chunk);
+        }
+        position = end;
+    }
+
+    let mut output = b"RIFF".to_vec();
+    output.extend(((chunks.len() + 4) as u32).to_le_bytes());
+    output.extend(b"WEBP");
+    output.extend(chunks);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::experimental::Writer;
+    use exif::{Field, Rational};
+    use image::codecs::jpeg::JpegEncoder;
+    use image::RgbImage;
+    use tempdir::TempDir;
+
+    /// a 40x20 jpeg rotated by 90 degrees, taken at Copacabana
+    fn jpeg_with_exif() -> Vec<u8> {
+        let field = |tag, value| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        };
+        let ascii = |text: &str| Value::Ascii(vec![text.as_bytes().to_vec()]);
+        let degrees = |d, m| {
+            Value::Rational(vec![
+                Rational::from((d, 1)),
+                Rational::from((m, 1)),
+                Rational::from((0, 1)),
+            ])
+        };
+        let fields = [
+            field(Tag::Make, ascii("Canon")),
+            field(Tag::Model, ascii("Canon EOS R6")),
+            field(Tag::Orientation, Value::Short(vec![6])),
+            field(Tag::DateTimeOriginal, ascii("2023:01:02 10:11:12")),
+            field(Tag::ExposureTime, Value::Rational(vec![(1, 250).into()])),
+            field(Tag::GPSLatitudeRef, ascii("S")),
+            field(Tag::GPSLatitude, degrees(22, 58)),
+            field(Tag::GPSLongitudeRef, ascii("W")),
+            field(Tag::GPSLongitude, degrees(43, 11)),
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+
+        let mut jpeg = Vec::new();
+        RgbImage::new(40, 20)
+            .write_with_encoder(JpegEncoder::new(&mut jpeg))
+            .unwrap();
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff.into_inner());
+        let mut output = jpeg[..2].to_vec();
+        output.extend([0xFF, 0xE1]);
+        output.extend(((app1.len() + 2) as u16).to_be_bytes());
+        output.extend(app1);
+        output.extend(&jpeg[2..]);
+        output
+    }
+
+    #[test]
+    fn test_reads_exif() {
+        let exif = PhotoExif::read(&jpeg_with_exif(), true);
+
+        assert_eq!(Some("Canon EOS R6".to_string()), exif.camera);
+        assert_eq!(Some("2023-01-02 10:11:12".to_string()), exif.date_taken);
+        assert_eq!(Some("1/250 s".to_string()), exif.exposure);
+        assert_eq!(6, exif.orientation);
+        let (latitude, longitude) = exif.gps.unwrap();
+        assert!((latitude + 22.9667).abs() < 0.001);
+        assert!((longitude + 43.1833).abs() < 0.001);
+
+        assert_eq!(None, PhotoExif::read(&jpeg_with_exif(), false).gps);
+    }
+
+    #[test]
+    fn test_strips_exif_but_keeps_orientation() {
+        let stripped = strip_exif(&jpeg_with_exif(), 6).unwrap();
+
+        let exif = PhotoExif::read(&stripped, true);
+        assert_eq!(
+            PhotoExif {
+                orientation: 6,
+                ..Default::default()
+            },
+            exif
+        );
+        assert_eq!((20, 40), decode_dimensions(&stripped));
+    }
+
+    #[test]
+    fn test_invalid_jpeg_segments_are_errors() {
+        for length in [0, 1] {
+            let jpeg = [0xFF, 0xD8, 0xFF, 0xE1, 0, length, 0xFF, 0xD9];
+            assert!(strip_exif(&jpeg, 1).is_err());
+        }
+    }
+
+    fn decode_dimensions(contents: &[u8]) -> (u32, u32) {
+        let image = crate::resize::decode(contents).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn test_builds_galleries() {
+        let tempdir = TempDir::new("gallery").unwrap();
+        let folder = tempdir.path().join("photos").join("trips");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("rio.jpg"), jpeg_with_exif()).unwrap();
+        fs::write(folder.join("notes.txt"), "not a photo").unwrap();
+        let output = tempdir.path().join("output");
+        let config = GalleryConfiguration {
+            thumbnails: vec![10],
+            strip_exif: true,
+            ..Default::default()
+        };
+
+        let (galleries, diagnostics) = GalleryBuilder::new(
+            &config,
+            &ImageProcessingConfiguration::default(),
+            &tempdir.path().join("cache"),
+            &output,
+        )
+        .build(&tempdir.path().join("photos"))
+        .unwrap();
+        assert!(diagnostics.is_empty());
+
+        let photos = &galleries["trips"].photos;
+        assert_eq!(1, photos.len());
+        assert_eq!("/trips/rio.jpg", photos[0].src);
+        assert_eq!("/trips/rio.html", photos[0].partial_uri);
+        assert_eq!((20, 40), (photos[0].width, photos[0].height));
+        assert_eq!(None, photos[0].exif.gps);
+        assert_eq!(10, photos[0].thumbnails[0].width);
+
+        let published = fs::read(output.join("trips").join("rio.jpg")).unwrap();
+        assert_eq!(None, PhotoExif::read(&published, false).camera);
+        assert!(output.join(&photos[0].thumbnails[0].url[1..]).exists());
+    }
+
+    #[test]
+    fn test_reserved_names_are_not_published() {
+        let tempdir = TempDir::new("gallery").unwrap();
+        let folder = tempdir.path().join("photos").join("posts");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("rio.jpg"), jpeg_with_exif()).unwrap();
+        let output = tempdir.path().join("output");
+        let config = GalleryConfiguration::default();
+
+        let (galleries, diagnostics) = GalleryBuilder::new(
+            &config,
+            &ImageProcessingConfiguration::default(),
+            &tempdir.path().join("cache"),
+            &output,
+        )
+        .with_reserved_names(["posts".to_string()])
+        .build(&tempdir.path().join("photos"))
+        .unwrap();
+
+        assert!(galleries.is_empty());
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(folder, diagnostics[0].path);
+        assert!(!output.join("posts").exists());
+    }
+}
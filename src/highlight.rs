@@ -0,0 +1,360 @@
+//! Syntax highlighting of fenced code blocks at build time.
+//!
+//! markdown-rs does not keep the info string meta (e.g. ```` ```rust {3-5} ````) on the
+//! html it produces, so we read the code blocks from the markdown tree and replace
+//! their html counterparts in the same order.
+
+use crate::config::HighlightConfiguration;
+use anyhow::{anyhow, Result};
+use markdown::mdast::Node;
+use markdown::ParseOptions;
+use regex::{Captures, Regex};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    css_for_theme_with_class_style, line_tokens_to_classed_spans, styled_line_to_highlighted_html,
+    ClassStyle, IncludeBackground,
+};
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// used for highlighted lines when the theme does not define a line highlight color
+const DEFAULT_LINE_HIGHLIGHT: Color = Color {
+    r: 0xff,
+    g: 0xf8,
+    b: 0xc5,
+    a: 0xff,
+};
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme(name: &str) -> Result<&'static Theme> {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES
+        .get_or_init(ThemeSet::load_defaults)
+        .themes
+        .get(name)
+        .ok_or(anyhow!("unknown highlight theme `{name}`"))
+}
+
+fn code_block_regex() -> &'static Regex {
+    static CODE_BLOCK: OnceLock<Regex> = OnceLock::new();
+    CODE_BLOCK
+        .get_or_init(|| Regex::new(r#"(?s)<pre><code( class="[^"]*")?>.*?</code></pre>"#).unwrap())
+}
+
+/// a code block found in the markdown tree, `lang` is None for blocks that
+/// should be left untouched (no language or math blocks)
+struct CodeBlock {
+    lang: Option<String>,
+    meta: Option<String>,
+    value: String,
+}
+
+fn collect_code_blocks(node: &Node, blocks: &mut Vec<CodeBlock>) {
+    match node {
+        Node::Code(code) => blocks.push(CodeBlock {
+            lang: code.lang.clone(),
+            meta: code.meta.clone(),
+            value: code.value.clone(),
+        }),
+        // math blocks are also rendered as <pre><code>
+        Node::Math(math) => blocks.push(CodeBlock {
+            lang: None,
+            meta: None,
+            value: math.value.clone(),
+        }),
+        _ => {}
+    }
+
+    for child in node.children().into_iter().flatten() {
+        collect_code_blocks(child, blocks);
+    }
+}
+
+/// replaces the code blocks of `html` (generated from `markdown`) by their highlighted version
+pub fn highlight_code_blocks(
+    html: &str,
+    markdown: &str,
+    parse_options: &ParseOptions,
+    config: &HighlightConfiguration,
+) -> Result<String> {
+    let tree = markdown::to_mdast(markdown, parse_options).map_err(|e| anyhow!("{e}"))?;
+    let mut blocks = Vec::new();
+    collect_code_blocks(&tree, &mut blocks);
+
+    // raw html may contain its own <pre><code>, in which case we cannot pair the
+    // blocks with the html and we leave the document as it is
+    if code_block_regex().find_iter(html).count() != blocks.len() {
+        return Ok(html.to_string());
+    }
+
+    let theme = theme(&config.theme)?;
+    let mut blocks = blocks.into_iter();
+    let mut error = None;
+
+    let highlighted = code_block_regex().replace_all(html, |caps: &Captures| {
+        let original = caps[0].to_string();
+        let block = match blocks.next() {
+            // ```math blocks are rendered as MathML instead
+            Some(b) if b.lang.as_deref().is_some_and(|l| l != "math") => b,
+            _ => return original,
+        };
+
+        // reuse the class markdown-rs generated, it is already escaped
+        let class = caps.get(1).map(|c| c.as_str()).unwrap_or_default();
+        match highlight_block(&block, class, config, theme) {
+            Ok(h) => h,
+            Err(e) => {
+                error.get_or_insert(e);
+                original
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(highlighted.to_string()),
+    }
+}
+
+fn highlight_block(
+    block: &CodeBlock,
+    class: &str,
+    config: &HighlightConfiguration,
+    theme: &Theme,
+) -> Result<String> {
+    let syntax_set = syntax_set();
+    let lang = block.lang.as_deref().unwrap_or_default();
+    // unknown languages are still wrapped the same way, just without colors
+    let syntax = syntax_set
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+
+    let lines = if config.css_classes {
+        classed_lines(&block.value, syntax)?
+    } else {
+        styled_lines(&block.value, syntax, theme)?
+    };
+
+    let marked = block
+        .meta
+        .as_deref()
+        .map(|meta| parse_line_ranges(meta, lines.len()))
+        .unwrap_or_default();
+
+    let line_highlight = theme
+        .settings
+        .line_highlight
+        .unwrap_or(DEFAULT_LINE_HIGHLIGHT);
+    let mut html = if config.css_classes {
+        format!(r#"<pre class="hl-code"><code{class}>"#)
+    } else {
+        let background = theme.settings.background.unwrap_or(Color::WHITE);
+        format!(
+            r#"<pre style="background-color:{};"><code{class}>"#,
+            hex(background)
+        )
+    };
+
+    for (number, line) in lines.iter().enumerate().map(|(i, l)| (i + 1, l)) {
+        let is_marked = marked.contains(&number);
+        match (config.css_classes, is_marked) {
+            (true, true) => html.push_str(r#"<span class="line highlighted">"#),
+            (true, false) => html.push_str(r#"<span class="line">"#),
+            (false, true) => html.push_str(&format!(
+                r#"<span class="line" style="display:inline-block;width:100%;background-color:{};">"#,
+                hex(line_highlight)
+            )),
+            (false, false) => html.push_str(r#"<span class="line">"#),
+        }
+
+        if config.line_numbers {
+            if config.css_classes {
+                html.push_str(&format!(r#"<span class="line-number">{number}</span>"#));
+            } else {
+                html.push_str(&format!(
+                    r#"<span class="line-number" style="user-select:none;opacity:0.5;margin-right:1em;">{number}</span>"#
+                ));
+            }
+        }
+
+        html.push_str(line);
+        html.push_str("</span>\n");
+    }
+
+    html.push_str("</code></pre>");
+    Ok(html)
+}
+
+/// highlights every line with inline styles, each line is self-contained
+fn styled_lines(code: &str, syntax: &SyntaxReference, theme: &Theme) -> Result<Vec<String>> {
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = Vec::new();
+
+    for line in LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(line, syntax_set())?;
+        let html = styled_line_to_highlighted_html(&regions, IncludeBackground::No)?;
+        lines.push(strip_line_ending(html));
+    }
+
+    Ok(lines)
+}
+
+/// highlights every line with css classes, spans still open at the end of a line are
+/// closed and opened again on the next one so each line is self-contained
+fn classed_lines(code: &str, syntax: &SyntaxReference) -> Result<Vec<String>> {
+    let mut parse_state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut lines = Vec::new();
+
+    for line in LinesWithEndings::from(code) {
+        let mut html: String = stack.as_slice().iter().map(|s| open_span(*s)).collect();
+
+        let ops = parse_state.parse_line(line, syntax_set())?;
+        let (spans, _) = line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut stack)?;
+        html.push_str(&spans);
+        html.push_str(&"</span>".repeat(stack.len()));
+
+        lines.push(strip_line_ending(html));
+    }
+
+    Ok(lines)
+}
+
+fn open_span(scope: Scope) -> String {
+    let classes: Vec<String> = scope
+        .build_string()
+        .split('.')
+        .map(|atom| format!("hl-{atom}"))
+        .collect();
+    format!(r#"<span class="{}">"#, classes.join(" "))
+}
+
+/// LinesWithEndings keeps the line ending, we add our own after each line
+fn strip_line_ending(html: String) -> String {
+    html.replace(['\n', '\r'], "")
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// parses the line ranges from the info string meta, e.g. `{1,3-5}`, up to the last line
+fn parse_line_ranges(meta: &str, line_count: usize) -> HashSet<usize> {
+    let mut lines = HashSet::new();
+    let ranges = match (meta.find('{'), meta.find('}')) {
+        (Some(start), Some(end)) if start < end => &meta[start + 1..end],
+        _ => return lines,
+    };
+
+    for range in ranges.split(',').map(str::trim) {
+        let bounds = match range.split_once('-') {
+            Some((from, to)) => (from.trim().parse::<usize>(), to.trim().parse::<usize>()),
+            None => (range.parse::<usize>(), range.parse::<usize>()),
+        };
+        if let (Ok(from), Ok(to)) = bounds {
+            lines.extend(from..=to.min(line_count));
+        }
+    }
+
+    lines
+}
+
+/// stylesheet for the theme when highlighting with css classes
+pub fn theme_stylesheet(config: &HighlightConfiguration) -> Result<String> {
+    let theme = theme(&config.theme)?;
+    let mut css = css_for_theme_with_class_style(theme, CLASS_STYLE)?;
+
+    let line_highlight = theme
+        .settings
+        .line_highlight
+        .unwrap_or(DEFAULT_LINE_HIGHLIGHT);
+    css.push_str(&format!(
+        ".line.highlighted {{\n display: inline-block;\n width: 100%;\n background-color: {};\n}}\n",
+        hex(line_highlight)
+    ));
+    css.push_str(".line-number {\n user-select: none;\n opacity: 0.5;\n margin-right: 1em;\n}\n");
+
+    Ok(css)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlight(markdown: &str, config: &HighlightConfiguration) -> String {
+        let html = markdown::to_html(markdown);
+        highlight_code_blocks(&html, markdown, &ParseOptions::default(), config).unwrap()
+    }
+
+    #[test]
+    fn test_highlights_with_inline_styles() {
+        let config = HighlightConfiguration {
+            enabled: true,
+            ..Default::default()
+        };
+        let html = highlight("```rust\nfn main() {}\n```", &config);
+
+        assert!(html.starts_with(r#"<pre style="background-color:"#));
+        assert!(html.contains(r#"<code class="language-rust">"#));
+        assert!(html.contains("<span style="));
+    }
+
+    #[test]
+    fn test_highlights_with_classes_line_numbers_and_ranges() {
+        let config = HighlightConfiguration {
+            enabled: true,
+            css_classes: true,
+            line_numbers: true,
+            ..Default::default()
+        };
+        let html = highlight("```rust {2}\n/* a\ncomment */\nlet a = 1;\n```", &config);
+
+        assert_eq!(2, html.matches(r#"<span class="line">"#).count());
+        assert_eq!(
+            1,
+            html.matches(r#"<span class="line highlighted">"#).count()
+        );
+        assert!(html.contains(r#"<span class="line-number">3</span>"#));
+        // every line closes the spans it opens
+        assert_eq!(
+            html.matches("<span").count(),
+            html.matches("</span>").count()
+        );
+
+        assert!(theme_stylesheet(&config).unwrap().contains(".hl-code"));
+    }
+
+    #[test]
+    fn test_unknown_language_and_plain_blocks() {
+        let config = HighlightConfiguration {
+            enabled: true,
+            css_classes: true,
+            ..Default::default()
+        };
+
+        let html = highlight("```not-a-language\n<b>\n```", &config);
+        assert!(html.contains(r#"<code class="language-not-a-language">"#));
+        assert!(html.contains("&lt;b&gt;"));
+
+        let plain = "```\nno language\n```";
+        assert_eq!(markdown::to_html(plain), highlight(plain, &config));
+    }
+
+    #[test]
+    fn test_parse_line_ranges() {
+        let lines = parse_line_ranges("title=\"x\" {1, 3-5}", 10);
+        assert_eq!(HashSet::from([1, 3, 4, 5]), lines);
+        assert!(parse_line_ranges("", 10).is_empty());
+        let lines = parse_line_ranges("{2-18446744073709551615, 9}", 3);
+        assert_eq!(HashSet::from([2, 3]), lines);
+    }
+}
@@ -0,0 +1,63 @@
+//! Small helpers to work with the html generated from markdown and templates.
+
+use minify_html::Cfg;
+use regex::Regex;
+use std::sync::OnceLock;
+
+fn tag_regex() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"<[^>]*>").unwrap())
+}
+
+/// removes every html tag, keeping the text (still escaped)
+pub fn strip_tags(html: &str) -> String {
+    tag_regex().replace_all(html, "").to_string()
+}
+
+/// decodes the entities markdown-rs uses when escaping text
+pub fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// escapes text to be used in html
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// collapses white space, removes comments and minifies inline `<style>` and `<script>`,
+/// the content of `<pre>` and `<textarea>` is left alone
+pub fn minify(html: &str) -> String {
+    let cfg = Cfg {
+        do_not_minify_doctype: true,
+        ensure_spec_compliant_unquoted_attribute_values: true,
+        keep_closing_tags: true,
+        keep_html_and_head_opening_tags: true,
+        keep_spaces_between_attributes: true,
+        minify_css: true,
+        minify_js: true,
+        ..Cfg::default()
+    };
+    String::from_utf8_lossy(&minify_html::minify(html.as_bytes(), &cfg)).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify() {
+        let html = "<div>\n    <!-- menu -->\n    <p>Hello   <b>you</b></p>\n</div>\n\
+            <pre>  keep\n    this  </pre>\n<style>\n  p {\n    color: #ff0000;\n  }\n</style>";
+
+        assert_eq!(
+            "<div><p>Hello <b>you</b></p></div><pre>  keep\n    this  </pre><style>p{color:red}</style>",
+            minify(html)
+        );
+    }
+}
@@ -2,7 +2,10 @@ use tokio::task::JoinHandle;
 use tower_http::services::ServeDir;
 
 pub fn serve(port: u16, folder: &str) -> JoinHandle<()> {
-    let service = ServeDir::new(folder);
+    // the gzip and brotli variants are sent when the browser accepts them, as in production
+    let service = ServeDir::new(folder)
+        .precompressed_br()
+        .precompressed_gzip();
 
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
 
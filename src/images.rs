@@ -0,0 +1,439 @@
+//! Post-processing of the images of markdown documents.
+//!
+//! When enabled in `[markdown.images]`, local images get their `width` and `height`, read
+//! from the file, so the page does not shift while they load, and are loaded lazily. An
+//! image alone in its paragraph with a title becomes a `<figure>` with the title as its
+//! caption. When `[images]` is enabled,
+//! images we can resize are wrapped in a `<picture>` with a `srcset` of their variants, and
+//! with `placeholders` images get a `data-blurhash` and their dominant color as background.
+
+use crate::config::ImagesConfiguration;
+use crate::placeholder::{Placeholder, Placeholders};
+use crate::resize::{ImageProcessor, Original};
+use anyhow::{anyhow, Result};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tinylang::types::{State, TinyLangType};
+
+fn image_regex() -> &'static Regex {
+    static IMAGE: OnceLock<Regex> = OnceLock::new();
+    IMAGE.get_or_init(|| Regex::new(r"(<p>)?(<img\s[^>]*?)\s*/?>(</p>)?").unwrap())
+}
+
+fn attribute_regex() -> &'static Regex {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    ATTRIBUTE.get_or_init(|| Regex::new(r#"\s([A-Za-z\-]+)="([^"]*)""#).unwrap())
+}
+
+fn scheme_regex() -> &'static Regex {
+    static SCHEME: OnceLock<Regex> = OnceLock::new();
+    SCHEME.get_or_init(|| Regex::new(r"^([A-Za-z][A-Za-z0-9+.\-]*:|//)").unwrap())
+}
+
+/// a local image used by a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub src: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub placeholder: Option<Placeholder>,
+}
+
+impl ImageInfo {
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        state.insert("src".into(), self.src.clone().into());
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            state.insert("width".into(), TinyLangType::Numeric(width as f64));
+            state.insert("height".into(), TinyLangType::Numeric(height as f64));
+        }
+        if let Some(placeholder) = &self.placeholder {
+            placeholder.insert_into_state(&mut state);
+        }
+        state
+    }
+}
+
+/// finds the files behind the images of the documents
+pub struct ImageResolver {
+    /// folders absolute urls (`/images/cat.png`) are served from, in order
+    roots: Vec<PathBuf>,
+    processor: Option<ImageProcessor>,
+    placeholders: Option<Placeholders>,
+}
+
+impl ImageResolver {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            processor: None,
+            placeholders: None,
+        }
+    }
+
+    /// computes the placeholders of the images, see [`Placeholders`]
+    pub fn with_placeholders(mut self, placeholders: Option<Placeholders>) -> Self {
+        self.placeholders = placeholders;
+        self
+    }
+
+    pub fn placeholders(&self) -> Option<&Placeholders> {
+        self.placeholders.as_ref()
+    }
+
+    /// details of the local image at `src`, None for remote images
+    pub fn info(&self, src: &str, source: &Path) -> Option<Result<ImageInfo>> {
+        let path = match self.resolve(src, source)? {
+            Ok(path) => path,
+            Err(e) => return Some(Err(e)),
+        };
+        // formats we cannot read (e.g. svg) are left without dimensions
+        let size = imagesize::size(&path).ok();
+        let placeholder = match (&self.placeholders, &size) {
+            (Some(placeholders), Some(_)) => match placeholders.get(&path) {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    eprintln!("{e:?}");
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        Some(Ok(ImageInfo {
+            src: src.to_string(),
+            width: size.as_ref().map(|s| s.width as u32),
+            height: size.as_ref().map(|s| s.height as u32),
+            placeholder,
+        }))
+    }
+
+    /// generates resized variants of the images with the processor
+    pub fn with_processor(mut self, processor: Option<ImageProcessor>) -> Self {
+        self.processor = processor;
+        self
+    }
+
+    /// whether images must exist, which is when something reads them
+    pub fn requires_files(&self, config: &ImagesConfiguration) -> bool {
+        config.dimensions || self.processor.is_some() || self.placeholders.is_some()
+    }
+
+    /// local file of the image, None for remote images
+    pub fn resolve(&self, src: &str, source: &Path) -> Option<Result<PathBuf>> {
+        if src.is_empty() || scheme_regex().is_match(src) {
+            return None;
+        }
+
+        let path = src
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .replace("%20", " ");
+        let candidates: Vec<PathBuf> = match path.strip_prefix('/') {
+            Some(path) => self.roots.iter().map(|root| root.join(path)).collect(),
+            None => vec![source.parent().unwrap_or(Path::new("")).join(&path)],
+        };
+
+        Some(
+            candidates
+                .into_iter()
+                .find(|candidate| candidate.is_file())
+                .ok_or(anyhow!("image {src} used by {:?} does not exist", source)),
+        )
+    }
+
+    /// adds dimensions and lazy loading to the images of the html generated from `source`,
+    /// returning the html and the local images it uses
+    pub fn process(
+        &self,
+        html: &str,
+        source: &Path,
+        config: &ImagesConfiguration,
+    ) -> Result<(String, Vec<ImageInfo>)> {
+        let mut error = None;
+        let mut images = Vec::new();
+
+        let html = image_regex().replace_all(html, |caps: &Captures| {
+            match self.process_image(caps, source, config, &mut images) {
+                Ok(image) => image,
+                Err(e) => {
+                    error.get_or_insert(e);
+                    caps[0].to_string()
+                }
+            }
+        });
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok((html.to_string(), images)),
+        }
+    }
+
+    fn process_image(
+        &self,
+        caps: &Captures,
+        source: &Path,
+        config: &ImagesConfiguration,
+        images: &mut Vec<ImageInfo>,
+    ) -> Result<String> {
+        let mut tag = caps[2].to_string();
+        let attributes: Vec<(String, String)> = attribute_regex()
+            .captures_iter(&tag)
+            .map(|a| (a[1].to_lowercase(), a[2].to_string()))
+            .collect();
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        let mut sources = String::new();
+        let info = match attribute("src").and_then(|src| self.info(src, source)) {
+            Some(Err(e)) if self.requires_files(config) => return Err(e),
+            Some(Err(_)) | None => None,
+            Some(Ok(info)) => Some(info),
+        };
+        if let Some(src) = attribute("src") {
+            if let Some(info) = info {
+                if let Some(processor) = &self.processor {
+                    let path = self.resolve(src, source).unwrap()?;
+                    if ImageProcessor::can_resize(&path) {
+                        sources = self.picture_sources(processor, &path);
+                    }
+                }
+                if config.dimensions
+                    && attribute("width").is_none()
+                    && attribute("height").is_none()
+                {
+                    if let (Some(width), Some(height)) = (info.width, info.height) {
+                        tag.push_str(&format!(r#" width="{width}" height="{height}""#));
+                    }
+                }
+                if let Some(placeholder) = &info.placeholder {
+                    tag.push_str(&format!(r#" data-blurhash="{}""#, placeholder.blurhash));
+                    if attribute("style").is_none() {
+                        tag.push_str(&format!(
+                            r#" style="background-color: {}""#,
+                            placeholder.color
+                        ));
+                    }
+                }
+                images.push(info);
+            }
+        }
+
+        if config.lazy_loading {
+            if attribute("loading").is_none() {
+                tag.push_str(r#" loading="lazy""#);
+            }
+            if attribute("decoding").is_none() {
+                tag.push_str(r#" decoding="async""#);
+            }
+        }
+
+        let alone = caps.get(1).is_some() && caps.get(3).is_some();
+        let title = attribute("title").filter(|t| !t.is_empty());
+        let picture = |tag: &str| match sources.is_empty() {
+            true => format!("{tag} />"),
+            false => format!("<picture>{sources}{tag} /></picture>"),
+        };
+        match title {
+            Some(title) if alone && config.figures => {
+                let title_attribute = format!(r#" title="{title}""#);
+                let tag = tag.replacen(&title_attribute, "", 1);
+                Ok(format!(
+                    "<figure>{}<figcaption>{title}</figcaption></figure>",
+                    picture(&tag)
+                ))
+            }
+            _ => Ok(format!(
+                "{}{}{}",
+                caps.get(1).map(|p| p.as_str()).unwrap_or_default(),
+                picture(&tag),
+                caps.get(3).map(|p| p.as_str()).unwrap_or_default()
+            )),
+        }
+    }
+
+    /// a `<source>` per configured format, images we fail to convert are left as they are
+    fn picture_sources(&self, processor: &ImageProcessor, path: &Path) -> String {
+        let config = processor.config();
+        let mut original = match Original::read(path) {
+            Ok(original) => original,
+            Err(e) => {
+                eprintln!("{e:?}");
+                return String::new();
+            }
+        };
+        let mut sources = String::new();
+        for format in &config.formats {
+            let variants = match processor.variants(&mut original, format) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("{e:?}");
+                    return String::new();
+                }
+            };
+            let srcset: Vec<String> = variants
+                .iter()
+                .map(|v| format!("{} {}w", v.url, v.width))
+                .collect();
+            sources.push_str(&format!(
+                r#"<source type="{}" srcset="{}" sizes="{}">"#,
+                mime_type(format),
+                srcset.join(", "),
+                config.sizes
+            ));
+        }
+        sources
+    }
+}
+
+/// media type of the variants of a format
+fn mime_type(format: &str) -> String {
+    match format {
+        "jpg" => "image/jpeg".to_string(),
+        format => format!("image/{format}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ImageProcessingConfiguration;
+    use std::fs::{create_dir, File};
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    // smallest valid gif, 3x2 pixels
+    const GIF: &[u8] = &[
+        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x3b,
+    ];
+
+    fn enabled() -> ImagesConfiguration {
+        ImagesConfiguration {
+            dimensions: true,
+            lazy_loading: true,
+            figures: true,
+        }
+    }
+
+    fn folders() -> (TempDir, PathBuf, ImageResolver) {
+        let tempdir = TempDir::new("images").unwrap();
+        let posts = tempdir.path().join("posts");
+        let statics = tempdir.path().join("static");
+        create_dir(&posts).unwrap();
+        create_dir(&statics).unwrap();
+        File::create(posts.join("cat.gif"))
+            .unwrap()
+            .write_all(GIF)
+            .unwrap();
+        File::create(statics.join("dog.gif"))
+            .unwrap()
+            .write_all(GIF)
+            .unwrap();
+
+        let resolver = ImageResolver::new(vec![posts.clone(), statics]);
+        (tempdir, posts.join("post.md"), resolver)
+    }
+
+    #[test]
+    fn test_adds_dimensions_lazy_loading_and_figures() {
+        let (_tempdir, source, resolver) = folders();
+        let html = r#"<p><img src="cat.gif" alt="A cat" title="My cat" /></p>
+<p>text <img src="/dog.gif" alt="" /> <img src="https://x.com/a.png" alt="" /></p>"#;
+
+        let (html, images) = resolver.process(html, &source, &enabled()).unwrap();
+
+        assert_eq!(
+            vec!["cat.gif", "/dog.gif"],
+            images.iter().map(|i| i.src.as_str()).collect::<Vec<_>>()
+        );
+        assert!(html.starts_with(
+            r#"<figure><img src="cat.gif" alt="A cat" width="3" height="2" loading="lazy" decoding="async" /><figcaption>My cat</figcaption></figure>"#
+        ));
+        assert!(html
+            .contains(r#"<p>text <img src="/dog.gif" alt="" width="3" height="2" loading="lazy""#));
+        assert!(html.contains(
+            r#"<img src="https://x.com/a.png" alt="" loading="lazy" decoding="async" /></p>"#
+        ));
+    }
+
+    #[test]
+    fn test_pictures_with_resized_variants() {
+        let (tempdir, source, resolver) = folders();
+        image::RgbImage::new(100, 50)
+            .save(source.with_file_name("big.png"))
+            .unwrap();
+        let config = ImageProcessingConfiguration {
+            enabled: true,
+            widths: vec![50, 200],
+            formats: vec!["avif".to_string(), "jpg".to_string()],
+            ..Default::default()
+        };
+        let resolver = resolver.with_processor(Some(ImageProcessor::new(
+            config,
+            vec![],
+            tempdir.path().join("cache"),
+            tempdir.path().join("output"),
+        )));
+        let html =
+            r#"<p><img src="big.png" alt="" title="Big" /> <img src="cat.gif" alt="" /></p>"#;
+
+        let (html, _) = resolver.process(html, &source, &enabled()).unwrap();
+
+        let srcset = regex::Regex::new(
+            r#"^<p><picture><source type="image/avif" srcset="/_images/big-[0-9a-f]{16}-50\.avif 50w, /_images/big-[0-9a-f]{16}-100\.avif 100w" sizes="100vw"><source type="image/jpeg" srcset="/_images/big-[0-9a-f]{16}-50\.jpg 50w, /_images/big-[0-9a-f]{16}-100\.jpg 100w" sizes="100vw"><img src="big.png" alt="" title="Big" width="100" height="50""#,
+        )
+        .unwrap();
+        assert!(srcset.is_match(&html), "{html}");
+        assert!(html.ends_with(r#"<img src="cat.gif" alt="" width="3" height="2" loading="lazy" decoding="async" /></p>"#));
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let (tempdir, source, resolver) = folders();
+        image::RgbImage::from_pixel(8, 8, image::Rgb([255, 0, 0]))
+            .save(source.with_file_name("red.png"))
+            .unwrap();
+        let resolver =
+            resolver.with_placeholders(Some(Placeholders::load(&tempdir.path().join("cache"))));
+        let html = r#"<p><img src="red.png" alt="" /></p>"#;
+
+        let (html, images) = resolver.process(html, &source, &enabled()).unwrap();
+
+        let placeholder = images[0].placeholder.as_ref().unwrap();
+        assert_eq!("#ff0000", placeholder.color);
+        assert!(html.contains(&format!(
+            r#" data-blurhash="{}" style="background-color: #ff0000""#,
+            placeholder.blurhash
+        )));
+    }
+
+    #[test]
+    fn test_missing_images_are_errors() {
+        let (_tempdir, source, resolver) = folders();
+        let html = r#"<p><img src="nope.png" alt="" /></p>"#;
+
+        let error = resolver.process(html, &source, &enabled()).unwrap_err();
+        assert!(error.to_string().contains("nope.png"));
+    }
+
+    #[test]
+    fn test_images_are_left_alone_by_default() {
+        let (_tempdir, source, resolver) = folders();
+        let html =
+            r#"<p><img src="cat.gif" alt="" title="Cat" /> <img src="nope.png" alt="" /></p>"#;
+
+        let (output, images) = resolver
+            .process(html, &source, &ImagesConfiguration::default())
+            .unwrap();
+
+        assert_eq!(html, output);
+        assert_eq!(1, images.len());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::outputs;
 use anyhow::Result;
 use anyhow::{anyhow, Context};
+use std::fmt::{self, Display};
 use std::fs;
 use std::fs::ReadDir;
 use std::path::Path;
@@ -26,6 +28,20 @@ impl TemplateFile {
             Err(e) => return Err(e).context("could not read file"),
         };
 
+        Self::with_contents(path, contents)
+    }
+
+    /// same as `new`, without blocking the runtime thread
+    pub(crate) async fn read(path: &PathBuf) -> Result<Self> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) => return Err(e).context("could not read file"),
+        };
+
+        Self::with_contents(path, contents)
+    }
+
+    fn with_contents(path: &Path, contents: String) -> Result<Self> {
         Ok(TemplateFile {
             name: path
                 .file_name()
@@ -74,23 +90,39 @@ impl Iterator for LazyFolderReader {
 
 impl LazyFolderReader {
     pub fn new(dir: &Path, extension: &str) -> Result<Self> {
+        Self::with_extensions(dir, &[extension])
+    }
+
+    /// same as `new`, but accepts any file matching one of the extensions
+    pub fn with_extensions(dir: &Path, extensions: &[&str]) -> Result<Self> {
         let paths = fs::read_dir(dir).context("could not read the folder")?;
 
-        let files = Self::scan(paths, extension)?;
+        let files = Self::scan(paths, extensions)?;
 
         Ok(Self { files })
     }
 
+    /// skips the files inside the given sub folder
+    pub fn without_folder(mut self, folder: &Path) -> Self {
+        self.files.retain(|file| !file.starts_with(folder));
+        self
+    }
+
+    /// paths of the files, for those that are not text
+    pub fn into_paths(self) -> Vec<PathBuf> {
+        self.files
+    }
+
     pub async fn async_next(&mut self) -> Option<Result<TemplateFile>> {
         if self.files.is_empty() {
             return None;
         }
 
         let current = self.files.pop().unwrap();
-        Some(TemplateFile::new(&current))
+        Some(TemplateFile::read(&current).await)
     }
 
-    fn scan(paths: ReadDir, extension: &str) -> Result<Vec<PathBuf>> {
+    fn scan(paths: ReadDir, extensions: &[&str]) -> Result<Vec<PathBuf>> {
         let paths: Vec<PathBuf> = paths
             .map(|path| {
                 let path = path.unwrap();
@@ -104,7 +136,7 @@ impl LazyFolderReader {
             .filter(|path| path.is_file())
             .filter(|path| {
                 if let Some(e) = path.extension() {
-                    e.eq(extension)
+                    extensions.iter().any(|extension| e.eq(*extension))
                 } else {
                     false
                 }
@@ -116,7 +148,7 @@ impl LazyFolderReader {
             .filter(|path| path.is_dir())
             .map(|dir| fs::read_dir(dir).context("could not read the folder"))
             .filter(|r| r.is_ok())
-            .map(|dir| Self::scan(dir.unwrap(), extension))
+            .map(|dir| Self::scan(dir.unwrap(), extensions))
             .filter(|r| r.is_ok())
             .flat_map(|r| r.unwrap())
             .collect();
@@ -139,14 +171,93 @@ impl<'a> IntoIterator for &'a LazyFolderReader {
     }
 }
 
-pub(crate) async fn write_to_disk(dir: PathBuf, file_name: &str, output: String) {
+/// a file generated by the build, and whether it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFile {
+    pub name: String,
+    /// false when the file already had the same contents
+    pub written: bool,
+}
+
+/// how many files were written, and how many were left alone since they did not change
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct WriteSummary {
+    pub written: usize,
+    pub unchanged: usize,
+}
+
+impl WriteSummary {
+    pub fn add(&mut self, written: bool) {
+        match written {
+            true => self.written += 1,
+            false => self.unchanged += 1,
+        }
+    }
+}
+
+impl Display for WriteSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} written, {} unchanged", self.written, self.unchanged)
+    }
+}
+
+/// whether the file already has these contents, the size is checked before reading it
+fn is_unchanged(path: &Path, contents: &[u8]) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.len() == contents.len() as u64)
+        && fs::read(path).is_ok_and(|existing| existing == contents)
+}
+
+/// writes the file unless it already has these contents, so its mtime only changes
+/// with it and deploys only upload what changed. Returns whether it was written.
+pub(crate) fn write_if_changed(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<bool> {
+    let contents = contents.as_ref();
+    outputs::record(path);
+    if is_unchanged(path, contents) {
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// writes a temporary file renamed over the destination, so builds running at the same
+/// time never read a partly written file
+pub(crate) fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// returns whether the file was written, see `write_if_changed`
+pub(crate) async fn write_to_disk(dir: PathBuf, file_name: &str, output: String) -> bool {
     tokio::fs::create_dir_all(&dir).await.unwrap();
     let output_file = dir.join(file_name);
+    outputs::record(&output_file);
+    if let Ok(existing) = tokio::fs::read(&output_file).await {
+        if existing == output.as_bytes() {
+            return false;
+        }
+    }
     let mut file = File::create(output_file).await.unwrap();
     file.write_all(output.as_bytes()).await.unwrap();
+    true
 }
 //based on https://stackoverflow.com/questions/26958489/how-to-copy-a-folder-recursively-in-rust
-pub(crate) fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+/// files that did not change are not copied again
+pub(crate) fn copy_dir(from: &Path, to: &Path) -> Result<WriteSummary> {
+    copy_dir_filtered(from, to, |_| true)
+}
+
+/// copies the images and other files of the markdown folder, which relative links of
+/// the documents point to, leaving the markdown files out
+pub(crate) fn copy_markdown_assets(from: &Path, to: &Path) -> Result<WriteSummary> {
+    copy_dir_filtered(from, to, |path| {
+        path.extension().and_then(|e| e.to_str()) != Some("md")
+    })
+}
+
+fn copy_dir_filtered(from: &Path, to: &Path, keep: impl Fn(&Path) -> bool) -> Result<WriteSummary> {
+    let mut summary = WriteSummary::default();
     let mut stack = Vec::new();
     stack.push(from.to_path_buf());
 
@@ -172,11 +283,20 @@ pub(crate) fn copy_dir(from: &Path, to: &Path) -> Result<()> {
             let path = entry.path();
             if path.is_dir() {
                 stack.push(path);
-            } else {
+            } else if keep(&path) {
                 match path.file_name() {
                     Some(filename) => {
                         let dest_path = dest.join(filename);
-                        fs::copy(&path, &dest_path)?;
+                        let same = fs::metadata(&path)?.len()
+                            == fs::metadata(&dest_path)
+                                .map(|m| m.len())
+                                .unwrap_or(u64::MAX)
+                            && fs::read(&path)? == fs::read(&dest_path)?;
+                        if !same {
+                            fs::copy(&path, &dest_path)?;
+                        }
+                        outputs::record(&dest_path);
+                        summary.add(!same);
                     }
                     None => return Err(anyhow!("could not copy {:?}", path)),
                 }
@@ -184,7 +304,7 @@ pub(crate) fn copy_dir(from: &Path, to: &Path) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(summary)
 }
 
 #[cfg(test)]
@@ -266,6 +386,55 @@ mod test {
         assert_eq!(5, checks);
     }
 
+    #[test]
+    fn test_reader_without_folder() {
+        let tempdir = TempDir::new("templates").unwrap();
+        create_random_template_files(tempdir.path(), 2);
+        let subdir = tempdir.path().join("subdir");
+        create_dir(&subdir).unwrap();
+        create_random_template_files(&subdir, 5);
+
+        let reader = LazyFolderReader::new(tempdir.path(), "template")
+            .unwrap()
+            .without_folder(&subdir);
+
+        assert_eq!(2, reader.files.len());
+    }
+
+    #[test]
+    fn test_unchanged_files_are_not_written() {
+        let tempdir = TempDir::new("output").unwrap();
+        let static_folder = tempdir.path().join("static");
+        create_dir(&static_folder).unwrap();
+        fs::write(static_folder.join("style.css"), "p {}").unwrap();
+        let output = tempdir.path().join("output");
+
+        assert_eq!(1, copy_dir(&static_folder, &output).unwrap().written);
+        let copied = output.join("style.css");
+        let modified = fs::metadata(&copied).unwrap().modified().unwrap();
+        assert_eq!(1, copy_dir(&static_folder, &output).unwrap().unchanged);
+        assert!(!write_if_changed(&copied, "p {}").unwrap());
+        assert_eq!(modified, fs::metadata(&copied).unwrap().modified().unwrap());
+
+        assert!(write_if_changed(&copied, "a {}").unwrap());
+        assert_eq!("a {}", fs::read_to_string(&copied).unwrap());
+    }
+
+    #[test]
+    fn test_copies_markdown_assets_without_documents() {
+        let tempdir = TempDir::new("output").unwrap();
+        let images = tempdir.path().join("markdown/posts/img");
+        fs::create_dir_all(&images).unwrap();
+        fs::write(images.join("cat.png"), "png").unwrap();
+        fs::write(tempdir.path().join("markdown/posts/a.md"), "# a").unwrap();
+        let output = tempdir.path().join("output");
+
+        let summary = copy_markdown_assets(&tempdir.path().join("markdown"), &output).unwrap();
+        assert_eq!(1, summary.written);
+        assert!(output.join("posts/img/cat.png").exists());
+        assert!(!output.join("posts/a.md").exists());
+    }
+
     #[test]
     fn test_reader_sub_dirs_iter() {
         let tempdir = TempDir::new("templates").unwrap();
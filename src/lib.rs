@@ -1,15 +1,37 @@
+mod admonition;
 mod app;
+mod assets;
+mod cache;
+mod compress;
 mod config;
+mod data;
 mod deps;
+mod diagnostic;
+mod fence;
+mod gallery;
+mod highlight;
+mod html;
 mod http;
+mod images;
 mod io;
+mod links;
+mod math;
 mod md;
+mod outputs;
+mod placeholder;
+mod resize;
 mod rss;
+mod sass;
+mod shortcode;
+mod slug;
 mod template;
 mod tinylang;
+mod toc;
 mod watch;
+mod wikilink;
 
 pub use app::App;
 pub use config::Configuration;
+pub use io::OutputFile;
 pub use md::{MarkdownCollection, MarkdownDocument};
 pub use template::Website;
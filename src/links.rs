@@ -0,0 +1,158 @@
+//! Relative links between markdown documents.
+//!
+//! Authors link to other posts with `[see here](other-post.md)` so links also work in
+//! editors. Relative links and image paths are resolved against the source file: links
+//! to markdown files become the url of the generated page and everything else becomes
+//! an absolute path inside the posts folder.
+
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+fn link_regex() -> &'static Regex {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    LINK.get_or_init(|| Regex::new(r#"(<(?:a|img)\s[^>]*?(?:href|src)=")([^"]*)(")"#).unwrap())
+}
+
+fn scheme_regex() -> &'static Regex {
+    static SCHEME: OnceLock<Regex> = OnceLock::new();
+    SCHEME.get_or_init(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.\-]*:").unwrap())
+}
+
+pub struct LinkResolver {
+    posts_folder: PathBuf,
+    /// markdown source path -> url of the generated page
+    documents: HashMap<PathBuf, String>,
+}
+
+impl LinkResolver {
+    pub fn new(
+        posts_folder: &Path,
+        documents: impl IntoIterator<Item = (PathBuf, String)>,
+    ) -> Self {
+        Self {
+            posts_folder: normalize(posts_folder),
+            documents: documents
+                .into_iter()
+                .map(|(path, uri)| (normalize(&path), uri))
+                .collect(),
+        }
+    }
+
+    /// rewrites the relative links of the html generated from `source`, returning
+    /// the new html and the links pointing to documents that do not exist
+    pub fn rewrite(&self, html: &str, source: &Path) -> (String, Vec<String>) {
+        let mut missing = Vec::new();
+        let directory = source.parent().unwrap_or(Path::new(""));
+
+        let html = link_regex().replace_all(html, |caps: &Captures| {
+            let url = &caps[2];
+            match self.resolve(url, directory) {
+                Some(Ok(resolved)) => format!("{}{resolved}{}", &caps[1], &caps[3]),
+                Some(Err(())) => {
+                    missing.push(url.to_string());
+                    caps[0].to_string()
+                }
+                None => caps[0].to_string(),
+            }
+        });
+
+        (html.to_string(), missing)
+    }
+
+    /// None for links that are not relative, Err for markdown documents we do not know
+    fn resolve(&self, url: &str, directory: &Path) -> Option<Result<String, ()>> {
+        if url.is_empty()
+            || url.starts_with('/')
+            || url.starts_with('#')
+            || scheme_regex().is_match(url)
+        {
+            return None;
+        }
+
+        let split = url.find(['?', '#']).unwrap_or(url.len());
+        let (path, suffix) = url.split_at(split);
+        let target = normalize(&directory.join(path.replace("%20", " ")));
+
+        if target.extension().is_some_and(|e| e == "md") {
+            return Some(
+                self.documents
+                    .get(&target)
+                    .map(|uri| format!("{uri}{suffix}"))
+                    .ok_or(()),
+            );
+        }
+
+        // files outside the posts folder are left as they are
+        let relative = target.strip_prefix(&self.posts_folder).ok()?;
+        let segments: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().replace(' ', "%20"))
+            .collect();
+        Some(Ok(format!("/{}{suffix}", segments.join("/"))))
+    }
+}
+
+/// removes `.` and `..` from the path without touching the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> LinkResolver {
+        LinkResolver::new(
+            Path::new("site/posts"),
+            [
+                (
+                    PathBuf::from("site/posts/blog/other-post.md"),
+                    "/blog/other-post.html".to_string(),
+                ),
+                (
+                    PathBuf::from("site/posts/notes/a.md"),
+                    "/notes/a.html".to_string(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_rewrites_markdown_links() {
+        let html = r##"<p><a href="other-post.md#setup">here</a> <a href="../notes/a.md">a</a> <a href="./missing.md">x</a></p>"##;
+
+        let (html, missing) = resolver().rewrite(html, Path::new("site/posts/blog/post.md"));
+
+        assert!(html.contains(r##"<a href="/blog/other-post.html#setup">"##));
+        assert!(html.contains(r#"<a href="/notes/a.html">"#));
+        assert!(html.contains(r#"<a href="./missing.md">"#));
+        assert_eq!(vec!["./missing.md"], missing);
+    }
+
+    #[test]
+    fn test_resolves_images_and_skips_absolute_links() {
+        let html = r##"<img src="img/cat.png" alt="" /><a href="https://x.com/a.md">x</a><a href="/about.html">y</a><a href="#top">z</a>"##;
+
+        let (output, missing) = resolver().rewrite(html, Path::new("site/posts/blog/post.md"));
+
+        assert!(output.contains(r#"<img src="/blog/img/cat.png""#));
+        assert!(output.contains(r#"href="https://x.com/a.md""#));
+        assert!(output.contains(r#"href="/about.html""#));
+        assert!(output.contains(r##"href="#top""##));
+        assert!(missing.is_empty());
+    }
+}
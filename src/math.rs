@@ -0,0 +1,751 @@
+//! LaTeX math converted to MathML at build time.
+//!
+//! markdown-rs outputs `$inline$` math as `<code class="language-math math-inline">` and
+//! `$$` blocks as `<pre><code class="language-math math-display">`, we replace both by
+//! MathML so pages do not need any javascript to render them. Only the commonly used
+//! subset of LaTeX is supported: unknown commands are kept as they are in `<merror>`,
+//! which browsers show in red, and invalid expressions are reported as errors.
+
+use crate::html::decode_entities;
+use anyhow::{anyhow, Result};
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+fn math_regex() -> &'static Regex {
+    static MATH: OnceLock<Regex> = OnceLock::new();
+    MATH.get_or_init(|| {
+        Regex::new(
+            r#"(?s)<pre><code class="language-math(?: math-display)?">(.*?)</code></pre>|<code class="language-math math-inline">(.*?)</code>"#,
+        )
+        .unwrap()
+    })
+}
+
+/// replaces every math element of the html by its MathML
+pub fn render_math(html: &str) -> Result<String> {
+    let mut error = None;
+
+    let rendered = math_regex().replace_all(html, |caps: &Captures| {
+        let (latex, display) = match caps.get(1) {
+            Some(block) => (block.as_str(), true),
+            None => (&caps[2], false),
+        };
+        let latex = decode_entities(latex);
+
+        match latex_to_mathml(latex.trim(), display) {
+            Ok(mathml) => mathml,
+            Err(e) => {
+                error.get_or_insert(anyhow!("`{}`: {e}", latex.trim()));
+                caps[0].to_string()
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(rendered.to_string()),
+    }
+}
+
+pub fn latex_to_mathml(latex: &str, display: bool) -> Result<String> {
+    let mut parser = Parser {
+        chars: latex.chars().collect(),
+        position: 0,
+        display,
+    };
+    let row = parser.parse_row()?;
+    if let Some(c) = parser.peek() {
+        return Err(anyhow!("unexpected `{c}`"));
+    }
+
+    let display = if display { "block" } else { "inline" };
+    Ok(format!(
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="{display}"><mrow>{row}</mrow></math>"#
+    ))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    display: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.position += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(anyhow!("expected `{expected}`, found `{c}`")),
+            None => Err(anyhow!(
+                "expected `{expected}`, found the end of the expression"
+            )),
+        }
+    }
+
+    /// reads the name of the command starting at the current `\`, without consuming it
+    fn peek_command(&self) -> Option<String> {
+        if self.peek() != Some('\\') {
+            return None;
+        }
+        let rest = &self.chars[self.position + 1..];
+        let letters: String = rest
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        if letters.is_empty() {
+            rest.first().map(|c| c.to_string())
+        } else {
+            Some(letters)
+        }
+    }
+
+    fn read_command(&mut self) -> Result<String> {
+        let name = self
+            .peek_command()
+            .ok_or(anyhow!("expected a command after `\\`"))?;
+        self.position += 1 + name.chars().count();
+        Ok(name)
+    }
+
+    /// elements until the end of the current group, cell or `\right`
+    fn parse_row(&mut self) -> Result<String> {
+        let mut row = String::new();
+        while let Some(element) = self.parse_element()? {
+            row.push_str(&element);
+        }
+        Ok(row)
+    }
+
+    fn at_row_end(&self) -> bool {
+        match self.peek() {
+            None | Some('}') | Some('&') => true,
+            Some('\\') => matches!(
+                self.peek_command().as_deref(),
+                Some("\\") | Some("right") | Some("end")
+            ),
+            _ => false,
+        }
+    }
+
+    /// an element with its sub and super scripts
+    fn parse_element(&mut self) -> Result<Option<String>> {
+        self.skip_whitespace();
+        if self.at_row_end() {
+            return Ok(None);
+        }
+
+        let (base, large_operator) = match self.peek() {
+            Some('^') | Some('_') => ("<mrow></mrow>".to_string(), false),
+            _ => self.parse_base()?,
+        };
+
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.parse_argument()?);
+                }
+                Some('^') if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.parse_argument()?);
+                }
+                Some('\'') if sup.is_none() => {
+                    let mut primes = String::new();
+                    while self.peek() == Some('\'') {
+                        self.next();
+                        primes.push('′');
+                    }
+                    sup = Some(format!("<mo>{primes}</mo>"));
+                }
+                Some('_') | Some('^') => return Err(anyhow!("double script")),
+                _ => break,
+            }
+        }
+
+        // limits of sums and similar operators go above and below them when displayed
+        let (under, over, both) = if large_operator && self.display {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        let element = match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) => format!("<{both}>{base}{sub}{sup}</{both}>"),
+        };
+        Ok(Some(element))
+    }
+
+    /// the argument of a command or script, a group or a single element
+    fn parse_argument(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if self.at_row_end() {
+            return Err(anyhow!("missing argument"));
+        }
+        Ok(self.parse_base()?.0)
+    }
+
+    /// a `{}` group, e.g. the argument of `\text`, without parsing it
+    fn parse_raw_group(&mut self) -> Result<String> {
+        self.expect('{')?;
+        let mut depth = 0;
+        let mut text = String::new();
+        loop {
+            let c = self.next().ok_or(anyhow!("unclosed `{{`"))?;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Ok(text),
+                '}' => depth -= 1,
+                _ => {}
+            }
+            text.push(c);
+        }
+    }
+
+    /// returns the MathML of the element and whether it is a large operator
+    fn parse_base(&mut self) -> Result<(String, bool)> {
+        let c = self
+            .peek()
+            .ok_or(anyhow!("unexpected end of the expression"))?;
+
+        let element = match c {
+            '{' => {
+                self.next();
+                let row = self.parse_row()?;
+                self.expect('}')?;
+                format!("<mrow>{row}</mrow>")
+            }
+            '}' => return Err(anyhow!("unexpected `}}`")),
+            '\\' => return self.parse_command(),
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                    self.next();
+                }
+                if number == "." {
+                    "<mo>.</mo>".to_string()
+                } else {
+                    format!("<mn>{number}</mn>")
+                }
+            }
+            c if c.is_alphabetic() => {
+                self.next();
+                format!("<mi>{c}</mi>")
+            }
+            c => {
+                self.next();
+                format!("<mo>{}</mo>", escape(&c.to_string()))
+            }
+        };
+        Ok((element, false))
+    }
+
+    fn parse_command(&mut self) -> Result<(String, bool)> {
+        let name = self.read_command()?;
+
+        if let Some(symbol) = large_operator(&name) {
+            return Ok((format!("<mo>{symbol}</mo>"), !name.contains("int")));
+        }
+        if let Some(variant) = math_variant(&name) {
+            let argument = self.parse_argument()?;
+            return Ok((
+                format!(r#"<mstyle mathvariant="{variant}">{argument}</mstyle>"#),
+                false,
+            ));
+        }
+        if let Some((accent, over)) = accent(&name) {
+            let argument = self.parse_argument()?;
+            let element = if over {
+                format!(r#"<mover accent="true">{argument}<mo>{accent}</mo></mover>"#)
+            } else {
+                format!(r#"<munder accentunder="true">{argument}<mo>{accent}</mo></munder>"#)
+            };
+            return Ok((element, false));
+        }
+
+        let element = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                format!("<mfrac>{numerator}{denominator}</mfrac>")
+            }
+            "binom" => {
+                let top = self.parse_argument()?;
+                let bottom = self.parse_argument()?;
+                format!(
+                    r#"<mrow><mo>(</mo><mfrac linethickness="0">{top}{bottom}</mfrac><mo>)</mo></mrow>"#
+                )
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.next();
+                    let mut index = String::new();
+                    while self.peek().is_some_and(|c| c != ']') {
+                        index.push_str(&self.parse_argument()?);
+                        self.skip_whitespace();
+                    }
+                    self.expect(']')?;
+                    let radicand = self.parse_argument()?;
+                    format!("<mroot>{radicand}<mrow>{index}</mrow></mroot>")
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument()?)
+                }
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape(&self.parse_raw_group()?))
+            }
+            "operatorname" => format!("<mi>{}</mi>", escape(&self.parse_raw_group()?)),
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let row = self.parse_row()?;
+                if self.peek_command().as_deref() != Some("right") {
+                    return Err(anyhow!("`\\left` without `\\right`"));
+                }
+                self.read_command()?;
+                let close = self.parse_delimiter()?;
+                format!("<mrow>{open}{row}{close}</mrow>")
+            }
+            "right" => return Err(anyhow!("`\\right` without `\\left`")),
+            "begin" => self.parse_environment()?,
+            "end" => return Err(anyhow!("`\\end` without `\\begin`")),
+            "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            _ => {
+                if let Some(space) = space(&name) {
+                    format!(r#"<mspace width="{space}"></mspace>"#)
+                } else if let Some(letter) = greek(&name) {
+                    if letter.is_uppercase() {
+                        format!(r#"<mi mathvariant="normal">{letter}</mi>"#)
+                    } else {
+                        format!("<mi>{letter}</mi>")
+                    }
+                } else if let Some(symbol) = symbol(&name) {
+                    format!("<mo>{}</mo>", escape(symbol))
+                } else if let Some(symbol) = identifier(&name) {
+                    format!("<mi>{symbol}</mi>")
+                } else if FUNCTIONS.contains(&name.as_str()) {
+                    format!("<mi>{name}</mi>")
+                } else if LIMITS.contains(&name.as_str()) {
+                    return Ok((format!(r#"<mo movablelimits="true">{name}</mo>"#), true));
+                } else {
+                    unknown(&name)
+                }
+            }
+        };
+
+        Ok((element, false))
+    }
+
+    /// the delimiter after `\left` or `\right`, `.` means no delimiter
+    fn parse_delimiter(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        let delimiter = match self.peek() {
+            Some('.') => {
+                self.next();
+                return Ok(String::new());
+            }
+            Some('\\') => {
+                let name = self.read_command()?;
+                match name.as_str() {
+                    "{" | "}" | "|" => name.replace('|', "‖"),
+                    "langle" => "⟨".to_string(),
+                    "rangle" => "⟩".to_string(),
+                    "lfloor" => "⌊".to_string(),
+                    "rfloor" => "⌋".to_string(),
+                    "lceil" => "⌈".to_string(),
+                    "rceil" => "⌉".to_string(),
+                    "vert" => "|".to_string(),
+                    "Vert" => "‖".to_string(),
+                    _ => return Ok(unknown(&name)),
+                }
+            }
+            Some(c) if "()[]|/".contains(c) => {
+                self.next();
+                c.to_string()
+            }
+            _ => return Err(anyhow!("missing delimiter")),
+        };
+        Ok(format!(r#"<mo fence="true">{}</mo>"#, escape(&delimiter)))
+    }
+
+    fn parse_environment(&mut self) -> Result<String> {
+        let name = self.parse_raw_group()?;
+        let (open, close) = match name.as_str() {
+            "matrix" | "aligned" | "align" | "align*" | "gathered" | "array" => ("", ""),
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            "cases" => ("{", ""),
+            _ => return Err(anyhow!("unknown environment `{name}`")),
+        };
+        if name == "array" {
+            // column alignment, e.g. {cc|l}
+            self.parse_raw_group()?;
+        }
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            cells.push(format!("<mtd>{}</mtd>", self.parse_row()?));
+            match self.peek() {
+                Some('&') => {
+                    self.next();
+                }
+                Some('\\') => match self.read_command()?.as_str() {
+                    "\\" => rows.push(format!(
+                        "<mtr>{}</mtr>",
+                        cells.drain(..).collect::<String>()
+                    )),
+                    "end" => {
+                        let end = self.parse_raw_group()?;
+                        if end != name {
+                            return Err(anyhow!("`\\begin{{{name}}}` closed by `\\end{{{end}}}`"));
+                        }
+                        break;
+                    }
+                    other => return Err(anyhow!("unexpected `\\{other}`")),
+                },
+                _ => return Err(anyhow!("`\\begin{{{name}}}` without `\\end{{{name}}}`")),
+            }
+        }
+        // a trailing `\\` leaves an empty last row
+        if !(cells.len() == 1 && cells[0] == "<mtd></mtd>" && !rows.is_empty()) {
+            rows.push(format!("<mtr>{}</mtr>", cells.concat()));
+        }
+
+        let align = match name.as_str() {
+            "aligned" | "align" | "align*" => r#" columnalign="right left""#,
+            "cases" => r#" columnalign="left""#,
+            _ => "",
+        };
+        let table = format!("<mtable{align}>{}</mtable>", rows.concat());
+        let fence = |delimiter: &str| {
+            if delimiter.is_empty() {
+                String::new()
+            } else {
+                format!(r#"<mo fence="true">{delimiter}</mo>"#)
+            }
+        };
+        Ok(format!(
+            "<mrow>{}{table}{}</mrow>",
+            fence(open),
+            fence(close)
+        ))
+    }
+}
+
+/// the command as it was written, for the reader to see what is missing
+fn unknown(name: &str) -> String {
+    format!("<merror><mtext>\\{}</mtext></merror>", escape(name))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "det", "dim", "gcd", "deg", "hom", "ker", "arg", "Pr",
+];
+
+/// functions whose scripts are limits, placed under them when displayed
+const LIMITS: &[&str] = &[
+    "lim", "max", "min", "sup", "inf", "limsup", "liminf", "argmax", "argmin",
+];
+
+fn large_operator(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        "bigvee" => "⋁",
+        "bigwedge" => "⋀",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+fn math_variant(name: &str) -> Option<&'static str> {
+    let variant = match name {
+        "mathbf" | "bf" => "bold",
+        "mathit" => "italic",
+        "mathrm" | "rm" => "normal",
+        "mathbb" => "double-struck",
+        "mathcal" => "script",
+        "mathfrak" => "fraktur",
+        "mathsf" => "sans-serif",
+        "mathtt" => "monospace",
+        "boldsymbol" => "bold-italic",
+        _ => return None,
+    };
+    Some(variant)
+}
+
+/// returns the accent and whether it goes over the argument
+fn accent(name: &str) -> Option<(&'static str, bool)> {
+    let accent = match name {
+        "hat" | "widehat" => ("^", true),
+        "bar" | "overline" => ("¯", true),
+        "vec" => ("→", true),
+        "dot" => ("˙", true),
+        "ddot" => ("¨", true),
+        "tilde" | "widetilde" => ("~", true),
+        "overrightarrow" => ("→", true),
+        "underline" => ("_", false),
+        _ => return None,
+    };
+    Some(accent)
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    let width = match name {
+        "," => "0.1667em",
+        ":" | ">" => "0.2222em",
+        ";" => "0.2778em",
+        " " => "0.25em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        "!" => "-0.1667em",
+        _ => return None,
+    };
+    Some(width)
+}
+
+fn greek(name: &str) -> Option<char> {
+    let letter = match name {
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" => 'ϵ',
+        "varepsilon" => 'ε',
+        "zeta" => 'ζ',
+        "eta" => 'η',
+        "theta" => 'θ',
+        "vartheta" => 'ϑ',
+        "iota" => 'ι',
+        "kappa" => 'κ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "nu" => 'ν',
+        "xi" => 'ξ',
+        "pi" => 'π',
+        "varpi" => 'ϖ',
+        "rho" => 'ρ',
+        "varrho" => 'ϱ',
+        "sigma" => 'σ',
+        "varsigma" => 'ς',
+        "tau" => 'τ',
+        "upsilon" => 'υ',
+        "phi" => 'ϕ',
+        "varphi" => 'φ',
+        "chi" => 'χ',
+        "psi" => 'ψ',
+        "omega" => 'ω',
+        "Gamma" => 'Γ',
+        "Delta" => 'Δ',
+        "Theta" => 'Θ',
+        "Lambda" => 'Λ',
+        "Xi" => 'Ξ',
+        "Pi" => 'Π',
+        "Sigma" => 'Σ',
+        "Upsilon" => 'Υ',
+        "Phi" => 'Φ',
+        "Psi" => 'Ψ',
+        "Omega" => 'Ω',
+        _ => return None,
+    };
+    Some(letter)
+}
+
+/// symbols that are identifiers rather than operators
+fn identifier(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "emptyset" | "varnothing" => "∅",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        "aleph" => "ℵ",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+fn symbol(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "{" => "{",
+        "}" => "}",
+        "%" => "%",
+        "$" => "$",
+        "&" => "&",
+        "#" => "#",
+        "_" => "_",
+        "|" => "‖",
+        "times" => "×",
+        "cdot" => "⋅",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "∙",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "ll" => "≪",
+        "gg" => "≫",
+        "approx" => "≈",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "equiv" => "≡",
+        "propto" => "∝",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "neg" | "lnot" => "¬",
+        "forall" => "∀",
+        "exists" => "∃",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "mid" => "|",
+        "parallel" => "∥",
+        "perp" => "⊥",
+        "angle" => "∠",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "prime" => "′",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converts_common_latex() {
+        let mathml = latex_to_mathml(r"\frac{1}{2} + x^2_i \leq \sqrt[3]{\alpha}", false).unwrap();
+
+        assert!(mathml
+            .starts_with(r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="inline">"#));
+        assert!(mathml.contains("<mfrac><mrow><mn>1</mn></mrow><mrow><mn>2</mn></mrow></mfrac>"));
+        assert!(mathml.contains("<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>"));
+        assert!(mathml.contains("<mo>≤</mo>"));
+        assert!(mathml.contains("<mroot><mrow><mi>α</mi></mrow><mrow><mn>3</mn></mrow></mroot>"));
+    }
+
+    #[test]
+    fn test_display_limits_and_environments() {
+        let mathml = latex_to_mathml(
+            r"\sum_{i=0}^n \left( \begin{pmatrix} a & b \\ c & d \end{pmatrix} \right) \text{if } x < 1",
+            true,
+        )
+        .unwrap();
+
+        assert!(mathml.contains("<munderover><mo>∑</mo>"));
+        assert!(mathml.contains(r#"<mo fence="true">(</mo><mtable><mtr><mtd><mi>a</mi></mtd>"#));
+        assert_eq!(2, mathml.matches("<mtr>").count());
+        assert!(mathml.contains("<mtext>if </mtext>"));
+        assert!(mathml.contains("<mo>&lt;</mo>"));
+    }
+
+    #[test]
+    fn test_reports_invalid_latex() {
+        assert!(latex_to_mathml(r"\frac{1}", false).is_err());
+        assert!(latex_to_mathml(r"x^", false).is_err());
+        assert!(latex_to_mathml(r"{x", false).is_err());
+        assert!(latex_to_mathml(r"\left( x", false).is_err());
+
+        let error =
+            render_math(r#"<code class="language-math math-inline">{\foo</code>"#).unwrap_err();
+        assert_eq!(
+            r"`{\foo`: expected `}`, found the end of the expression",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_unknown_commands_are_kept() {
+        let mathml = latex_to_mathml(r"\foo{x} + \left\lbrack y \right)", false).unwrap();
+
+        assert!(mathml.contains(r"<merror><mtext>\foo</mtext></merror><mrow><mi>x</mi></mrow>"));
+        assert!(mathml.contains(r#"<merror><mtext>\lbrack</mtext></merror><mi>y</mi>"#));
+    }
+
+    #[test]
+    fn test_renders_math_in_html() {
+        let html = "<p>a <code class=\"language-math math-inline\">x&lt;1</code></p>\n<pre><code class=\"language-math math-display\">y\n</code></pre>";
+
+        let html = render_math(html).unwrap();
+
+        assert!(html
+            .contains(r#"display="inline"><mrow><mi>x</mi><mo>&lt;</mo><mn>1</mn></mrow></math>"#));
+        assert!(html.contains(r#"display="block"><mrow><mi>y</mi></mrow></math>"#));
+    }
+}
@@ -1,12 +1,43 @@
-use anyhow::Result;
+use crate::admonition::Admonitions;
+use crate::config::MarkdownConfiguration;
+use crate::fence::CodeBlocks;
+use crate::highlight;
+use crate::html::{decode_entities, escape, strip_tags};
+use crate::images::ImageInfo;
+use crate::math;
+use crate::shortcode::Shortcodes;
+use crate::toc::{self, TocEntry};
+use crate::wikilink::Backlink;
+use anyhow::{anyhow, Context, Result};
 use chrono::DateTime;
 use chrono::Utc;
 use gray_matter::engine::YAML;
 use gray_matter::Matter;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::OnceLock;
 use tinylang::types::{State, TinyLangType};
 
+/// length, in characters, of the summary when the document has no `<!-- more -->`
+const SUMMARY_LENGTH: usize = 150;
+const WORDS_PER_MINUTE: usize = 200;
+const MORE_PLACEHOLDER: &str = "SQUIDMOREEND";
+
+/// `<!-- more -->` alone in its line splits the summary from the rest of the document
+fn more_regex() -> &'static Regex {
+    static MORE: OnceLock<Regex> = OnceLock::new();
+    MORE.get_or_init(|| Regex::new(r"(?m)^[ \t]*<!--\s*more\s*-->[ \t]*$").unwrap())
+}
+
+/// headings and code blocks do not make a good summary
+fn non_prose_regex() -> &'static Regex {
+    static NON_PROSE: OnceLock<Regex> = OnceLock::new();
+    NON_PROSE
+        .get_or_init(|| Regex::new(r"(?s)<h[1-6][^>]*>.*?</h[1-6]>|<pre[^>]*>.*?</pre>").unwrap())
+}
+
 #[derive(Debug, Clone)]
 pub struct MarkdownCollection {
     pub name: String,
@@ -50,34 +81,229 @@ impl MarkdownCollection {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarkdownDocument {
     pub header: HashMap<String, String>,
     pub html_content: String,
+    /// html before `<!-- more -->`, or the beginning of the text cut at a word boundary
+    pub summary: String,
+    pub word_count: usize,
+    /// minutes, rounded up
+    pub reading_time: usize,
+    pub toc: Vec<TocEntry>,
+    /// names of the shortcodes used by the document
+    pub shortcodes: Vec<String>,
+    /// kinds of the admonitions used by the document
+    pub admonitions: Vec<String>,
+    /// documents linking to this one with wiki-links
+    pub backlinks: Vec<Backlink>,
+    /// local images of the content
+    pub images: Vec<ImageInfo>,
+    /// the `cover_image` of the front matter
+    pub cover: Option<ImageInfo>,
     pub name: String,
+    /// source file, only the file name until the website sets the full path
+    pub path: PathBuf,
     pub partial_uri: String,
 }
 
+/// markdown features enabled for a single document, resolved from the `[markdown]`
+/// configuration and the `markdown` key of the document front matter
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Dialect {
+    tables: bool,
+    footnotes: bool,
+    strikethrough: bool,
+    autolinks: bool,
+    task_lists: bool,
+    math: bool,
+    frontmatter: bool,
+    allow_dangerous_html: bool,
+}
+
+impl Dialect {
+    fn new(config: &MarkdownConfiguration) -> Self {
+        Self {
+            tables: config.tables.unwrap_or(config.gfm),
+            footnotes: config.footnotes.unwrap_or(config.gfm),
+            strikethrough: config.strikethrough.unwrap_or(config.gfm),
+            autolinks: config.autolinks.unwrap_or(config.gfm),
+            task_lists: config.task_lists.unwrap_or(config.gfm),
+            math: config.math,
+            frontmatter: config.frontmatter,
+            allow_dangerous_html: config.allow_dangerous_html,
+        }
+    }
+
+    /// applies a comma separated list of features, prefixing a feature with `-`
+    /// disables it (e.g. `gfm, -tables`)
+    fn apply_overrides(&mut self, overrides: &str) -> Result<()> {
+        for feature in overrides
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+        {
+            match feature.strip_prefix('-') {
+                Some(feature) => self.set(feature.trim(), false)?,
+                None => self.set(feature, true)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, feature: &str, enabled: bool) -> Result<()> {
+        match feature {
+            "gfm" => {
+                self.tables = enabled;
+                self.footnotes = enabled;
+                self.strikethrough = enabled;
+                self.autolinks = enabled;
+                self.task_lists = enabled;
+            }
+            "tables" => self.tables = enabled,
+            "footnotes" => self.footnotes = enabled,
+            "strikethrough" => self.strikethrough = enabled,
+            "autolinks" => self.autolinks = enabled,
+            "task_lists" => self.task_lists = enabled,
+            "math" => self.math = enabled,
+            "frontmatter" => self.frontmatter = enabled,
+            "allow_dangerous_html" => self.allow_dangerous_html = enabled,
+            _ => return Err(anyhow!("unknown markdown feature `{feature}`")),
+        }
+        Ok(())
+    }
+
+    fn options(&self) -> markdown::Options {
+        let mut options = markdown::Options::default();
+
+        let constructs = &mut options.parse.constructs;
+        constructs.gfm_table = self.tables;
+        constructs.gfm_footnote_definition = self.footnotes;
+        constructs.gfm_label_start_footnote = self.footnotes;
+        constructs.gfm_strikethrough = self.strikethrough;
+        constructs.gfm_autolink_literal = self.autolinks;
+        constructs.gfm_task_list_item = self.task_lists;
+        constructs.math_flow = self.math;
+        constructs.math_text = self.math;
+        constructs.frontmatter = self.frontmatter;
+
+        options.compile.allow_dangerous_html = self.allow_dangerous_html;
+        // same as markdown::Options::gfm(), filters html that is dangerous even when
+        // raw html is allowed
+        options.compile.gfm_tagfilter = self.allow_dangerous_html;
+        options
+    }
+}
+
 impl MarkdownDocument {
     pub fn new(content: &str, name: String, partial_uri: String) -> Result<Self> {
+        Self::with_configuration(
+            content,
+            name,
+            partial_uri,
+            &MarkdownConfiguration::default(),
+            &Shortcodes::default(),
+            &Admonitions::default(),
+        )
+    }
+
+    /// same as `new`, but converts the document using the markdown dialect selected
+    /// in the configuration and renders the shortcodes and admonitions it uses
+    pub fn with_configuration(
+        content: &str,
+        name: String,
+        partial_uri: String,
+        config: &MarkdownConfiguration,
+        shortcodes: &Shortcodes,
+        admonitions: &Admonitions,
+    ) -> Result<Self> {
         let matter = Matter::<YAML>::new();
         let header = matter.parse(content);
-        let html_content = markdown::to_html(&header.content);
 
-        let header: HashMap<String, String> = match header.data {
+        let header_data = header.data;
+        let (body, shortcode_calls) = shortcodes.extract(&header.content);
+        let body = mark_more(&body);
+        let kinds = &config.admonitions.kinds;
+        let (body, containers) = if config.admonitions.enabled {
+            admonitions.extract(&body, kinds)
+        } else {
+            (body.to_string(), Vec::new())
+        };
+        let header: HashMap<String, String> = match header_data {
             Some(d) => d.deserialize()?,
             None => HashMap::new(),
         };
 
+        let mut dialect = Dialect::new(config);
+        if let Some(overrides) = header.get("markdown") {
+            dialect.apply_overrides(overrides)?;
+        }
+
+        let options = dialect.options();
+        let mut html_content = markdown::to_html_with_options(&body, &options)
+            .map_err(|e| anyhow!("could not convert {name} to html: {e}"))?;
+
+        if config.highlight.enabled {
+            html_content = highlight::highlight_code_blocks(
+                &html_content,
+                &body,
+                &options.parse,
+                &config.highlight,
+            )
+            .with_context(|| format!("could not highlight code blocks of {name}"))?;
+        }
+
+        if dialect.math {
+            html_content = math::render_math(&html_content)
+                .map_err(|e| anyhow!("could not convert math in {name}: {e}"))?;
+        }
+
+        let mut used_admonitions = Vec::new();
+        if config.admonitions.enabled {
+            (html_content, used_admonitions) = admonitions
+                .render(&html_content, &containers, kinds)
+                .with_context(|| format!("could not render admonitions of {name}"))?;
+        }
+
+        let (html_content, toc) = toc::process_headings(&html_content, &config.headings);
+
+        let page: State = header
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone().into()))
+            .collect();
+        let html_content = shortcodes
+            .render(&html_content, &shortcode_calls, &page)
+            .with_context(|| format!("could not render shortcodes of {name}"))?;
+
+        let (html_content, summary) = split_summary(&html_content);
+        let summary = summary
+            .unwrap_or_else(|| escape(&truncate_words(&plain_text(&html_content), SUMMARY_LENGTH)));
+        let word_count = strip_tags(&html_content).split_whitespace().count();
+
+        let mut used_shortcodes: Vec<String> =
+            shortcode_calls.into_iter().map(|call| call.name).collect();
+        used_shortcodes.sort();
+        used_shortcodes.dedup();
+
         Ok(Self {
             header,
+            summary,
+            word_count,
+            reading_time: word_count.div_ceil(WORDS_PER_MINUTE).max(1),
             html_content,
+            toc,
+            shortcodes: used_shortcodes,
+            admonitions: used_admonitions,
+            backlinks: Vec::new(),
+            images: Vec::new(),
+            cover: None,
+            path: PathBuf::from(&name),
             name,
             partial_uri,
         })
     }
 
-    pub fn to_post_metadata(&self, base_url: &str) -> Result<crate::rss::PostMetadata> {
+    pub fn to_post_metadata(&self, _base_url: &str) -> Result<crate::rss::PostMetadata> {
         // Parse date from header
         let date = self
             .header
@@ -97,7 +323,7 @@ impl MarkdownDocument {
                 // Try simple date format (e.g., "2024-01-10")
                 if let Ok(naive_date) = chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d") {
                     if let Some(naive_datetime) = naive_date.and_hms_opt(0, 0, 0) {
-                        return Some(DateTime::<Utc>::from_utc(naive_datetime, Utc));
+                        return Some(naive_datetime.and_utc());
                     }
                 }
 
@@ -105,23 +331,13 @@ impl MarkdownDocument {
             })
             .unwrap_or_else(Utc::now);
 
-        // Get excerpt from header or generate from content
+        // Get excerpt from header or fall back to the document summary
         let excerpt = self
             .header
             .get("excerpt")
             .cloned()
             .or_else(|| self.header.get("description").cloned())
-            .unwrap_or_else(|| {
-                // Extract first paragraph from HTML content
-                let plain_text = html2text::from_read(self.html_content.as_bytes(), 80).unwrap();
-                plain_text
-                    .split("\n\n")
-                    .next()
-                    .unwrap_or("")
-                    .chars()
-                    .take(150)
-                    .collect()
-            });
+            .unwrap_or_else(|| self.summary.clone());
 
         // Extract tags from header
         let tags = self
@@ -134,7 +350,7 @@ impl MarkdownDocument {
                     .filter(|s| !s.is_empty())
                     .collect()
             })
-            .unwrap_or_else(Vec::new);
+            .unwrap_or_default();
 
         Ok(crate::rss::PostMetadata {
             title: self.header.get("title").cloned().unwrap_or_default(),
@@ -156,12 +372,125 @@ impl MarkdownDocument {
         }
 
         item_state.insert("content".into(), self.html_content.clone().into());
+        item_state.insert("summary".into(), self.summary.clone().into());
+        item_state.insert(
+            "word_count".into(),
+            TinyLangType::Numeric(self.word_count as f64),
+        );
+        item_state.insert(
+            "reading_time".into(),
+            TinyLangType::Numeric(self.reading_time as f64),
+        );
+        item_state.insert(
+            "toc".into(),
+            TinyLangType::Vec(
+                self.toc
+                    .iter()
+                    .map(|entry| TinyLangType::Object(entry.as_tinylang_state()))
+                    .collect(),
+            ),
+        );
+
+        item_state.insert(
+            "backlinks".into(),
+            TinyLangType::Vec(
+                self.backlinks
+                    .iter()
+                    .map(|b| TinyLangType::Object(b.as_tinylang_state()))
+                    .collect(),
+            ),
+        );
+
+        item_state.insert(
+            "images".into(),
+            TinyLangType::Vec(
+                self.images
+                    .iter()
+                    .map(|i| TinyLangType::Object(i.as_tinylang_state()))
+                    .collect(),
+            ),
+        );
+        if let Some(cover) = &self.cover {
+            item_state.insert(
+                "cover".into(),
+                TinyLangType::Object(cover.as_tinylang_state()),
+            );
+        }
 
         item_state.insert("partial_uri".to_string(), self.partial_uri.clone().into());
         item_state
     }
 }
 
+/// replaces the first `<!-- more -->` outside code blocks by a placeholder
+fn mark_more(markdown: &str) -> String {
+    let mut code = CodeBlocks::default();
+    let mut found = false;
+    let lines: Vec<String> = markdown
+        .lines()
+        .map(|line| {
+            if !found && !code.contains(line) && more_regex().is_match(line) {
+                found = true;
+                // blank lines around the placeholder make sure it ends up in its own paragraph
+                format!("\n{MORE_PLACEHOLDER}\n")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// removes the `<!-- more -->` placeholder, returning the html before it as the summary
+fn split_summary(html: &str) -> (String, Option<String>) {
+    let block = format!("<p>{MORE_PLACEHOLDER}</p>");
+    match html.find(&block) {
+        Some(position) => (
+            html.replacen(&block, "", 1),
+            Some(html[..position].trim_end().to_string()),
+        ),
+        // the separator ended up somewhere we cannot split (e.g. inside a list)
+        None => (html.replace(MORE_PLACEHOLDER, ""), None),
+    }
+}
+
+/// text of the document without headings and code blocks, whitespace collapsed, it is not
+/// escaped
+fn plain_text(html: &str) -> String {
+    let prose = non_prose_regex().replace_all(html, " ");
+    decode_entities(&strip_tags(&prose))
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// cuts the text at the last word boundary before `length` characters
+fn truncate_words(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+
+    let mut summary = String::new();
+    for word in text.split(' ') {
+        let extra = if summary.is_empty() { 0 } else { 1 };
+        if summary.chars().count() + extra + word.chars().count() > length {
+            break;
+        }
+        if !summary.is_empty() {
+            summary.push(' ');
+        }
+        summary.push_str(word);
+    }
+
+    // a single word longer than the summary, cut it anyway
+    if summary.is_empty() {
+        summary = text.chars().take(length).collect();
+    }
+
+    summary.push('…');
+    summary
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,6 +515,61 @@ title: This is pretty cool
             markdown.header.get("title").unwrap().as_str()
         );
     }
+    #[test]
+    fn test_default_dialect_is_commonmark() {
+        let content = "| a |\n| - |\n| b |\n\n~~gone~~";
+
+        let markdown =
+            MarkdownDocument::new(content, "my_file.md".into(), "/posts/".to_string()).unwrap();
+
+        assert_eq!(markdown::to_html(content), markdown.html_content);
+    }
+
+    #[test]
+    fn test_gfm_dialect() {
+        let content = "| a |\n| - |\n| b |\n\n~~gone~~\n\n* [x] done";
+        let config = MarkdownConfiguration {
+            gfm: true,
+            ..Default::default()
+        };
+
+        let markdown = MarkdownDocument::with_configuration(
+            content,
+            "my_file.md".into(),
+            "/posts/".to_string(),
+            &config,
+            &Shortcodes::default(),
+            &Admonitions::default(),
+        )
+        .unwrap();
+
+        assert!(markdown.html_content.contains("<table>"));
+        assert!(markdown.html_content.contains("<del>gone</del>"));
+        assert!(markdown.html_content.contains(r#"type="checkbox""#));
+    }
+
+    #[test]
+    fn test_front_matter_overrides_dialect() {
+        let content = r#"---
+title: Overrides
+markdown: gfm, -tables
+---
+| a |
+| - |
+| b |
+
+~~gone~~"#;
+
+        let markdown =
+            MarkdownDocument::new(content, "my_file.md".into(), "/posts/".to_string()).unwrap();
+
+        assert!(!markdown.html_content.contains("<table>"));
+        assert!(markdown.html_content.contains("<del>gone</del>"));
+
+        let invalid = "---\nmarkdown: mdx\n---\n# title";
+        assert!(MarkdownDocument::new(invalid, "my_file.md".into(), "/".into()).is_err());
+    }
+
     #[test]
     fn test_to_post_metadata() {
         let content = r#"---
@@ -212,4 +596,88 @@ excerpt: Test excerpt
         assert_eq!(metadata.tags, vec!["rust", "blogging"]);
         assert_eq!(metadata.file_name, "/posts/test-post");
     }
+
+    #[test]
+    fn test_more_separator_summary() {
+        let content = "# Title\n\nFirst *paragraph*.\n<!-- more -->\n\nRest of the post.";
+
+        let markdown = MarkdownDocument::new(content, "post.md".into(), "/".into()).unwrap();
+
+        assert_eq!(
+            "<h1>Title</h1>\n<p>First <em>paragraph</em>.</p>",
+            markdown.summary
+        );
+        assert!(!markdown.html_content.contains("SQUIDMORE"));
+        assert!(markdown.html_content.contains("<p>Rest of the post.</p>"));
+        assert_eq!(7, markdown.word_count);
+        assert_eq!(1, markdown.reading_time);
+    }
+
+    #[test]
+    fn test_summary_fallback_cuts_at_word_boundary() {
+        let content = format!("# Heading\n\n{}", "lorem &amp; ipsum ".repeat(30));
+
+        let markdown = MarkdownDocument::new(&content, "post.md".into(), "/".into()).unwrap();
+
+        assert!(markdown.summary.starts_with("lorem &amp; ipsum"));
+        assert!(markdown.summary.ends_with("ipsum…") || markdown.summary.ends_with("&amp;…"));
+        assert_eq!(91, markdown.word_count);
+
+        let long = "word ".repeat(450);
+        let markdown = MarkdownDocument::new(&long, "post.md".into(), "/".into()).unwrap();
+        assert_eq!(3, markdown.reading_time);
+    }
+
+    #[test]
+    fn test_summary_fallback_is_escaped() {
+        let content = "A &lt;script&gt; tag, *not* a script.";
+
+        let markdown = MarkdownDocument::new(content, "post.md".into(), "/".into()).unwrap();
+
+        assert_eq!("A &lt;script&gt; tag, not a script.", markdown.summary);
+    }
+
+    #[test]
+    fn test_more_separator_in_code_is_code() {
+        let content = "Intro\n\n```html\n<!-- more -->\n```\n\nRest.";
+
+        let markdown = MarkdownDocument::new(content, "post.md".into(), "/".into()).unwrap();
+
+        assert!(markdown.html_content.contains("&lt;!-- more --&gt;"));
+        // no separator, the summary is the whole prose
+        assert_eq!("Intro Rest.", markdown.summary);
+    }
+
+    #[test]
+    fn test_math_is_rendered_as_mathml() {
+        let content = "Energy $E = mc^2$\n\n$$\n\\frac{a}{b}\n$$";
+
+        let config = MarkdownConfiguration {
+            math: true,
+            ..Default::default()
+        };
+        let convert = |content: &str| {
+            MarkdownDocument::with_configuration(
+                content,
+                "post.md".into(),
+                "/".into(),
+                &config,
+                &Shortcodes::default(),
+                &Admonitions::default(),
+            )
+        };
+
+        let markdown = convert(content).unwrap();
+
+        assert!(markdown
+            .html_content
+            .contains(r#"display="inline"><mrow><mi>E</mi>"#));
+        assert!(markdown
+            .html_content
+            .contains(r#"display="block"><mrow><mfrac>"#));
+
+        let error = convert("${x$").unwrap_err();
+        assert!(error.to_string().contains("post.md"));
+        assert!(error.to_string().contains("{x"));
+    }
 }
@@ -0,0 +1,208 @@
+//! Files squid produced in the output folder.
+//!
+//! Every file written to, or kept in, the output is recorded while building. The list is
+//! saved in the cache folder of the website, out of what gets published, so the files a
+//! later build does not produce anymore (the
+//! page of a deleted post, an old fingerprinted stylesheet...) can be removed, and
+//! `squid clean` removes what squid owns without touching anything else.
+
+use crate::cache;
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// file, in the cache folder, listing what squid produced
+const MANIFEST_FILE: &str = "outputs.json";
+
+/// extensions of the variants written next to an output, see `compress`
+const VARIANT_EXTENSIONS: &[&str] = &["gz", "br"];
+
+static RECORDED: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+
+thread_local! {
+    /// outputs recorded on this thread by `capture`
+    static CAPTURED: RefCell<Option<Vec<PathBuf>>> = const { RefCell::new(None) };
+}
+
+fn recorded() -> &'static Mutex<HashSet<PathBuf>> {
+    RECORDED.get_or_init(Default::default)
+}
+
+/// records a file of the output, whether it was written or left as it was
+pub fn record(path: &Path) {
+    recorded().lock().unwrap().insert(path.to_path_buf());
+    CAPTURED.with_borrow_mut(|captured| {
+        if let Some(captured) = captured {
+            captured.push(path.to_path_buf());
+        }
+    });
+}
+
+/// runs `f`, returning the outputs it recorded along with its result, so whoever skips
+/// running it again can record them instead
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<PathBuf>) {
+    let outer = CAPTURED.replace(Some(Vec::new()));
+    let result = f();
+    let captured = CAPTURED.replace(outer).unwrap_or_default();
+    // an outer capture gets them as well
+    CAPTURED.with_borrow_mut(|outer| {
+        if let Some(outer) = outer {
+            outer.extend(captured.iter().cloned());
+        }
+    });
+    (result, captured)
+}
+
+/// removes an output along with its compressed variants, returns whether it existed
+pub fn remove(path: &Path) -> bool {
+    for extension in VARIANT_EXTENSIONS {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(format!(".{extension}"));
+        let _ = fs::remove_file(PathBuf::from(variant));
+    }
+    fs::remove_file(path).is_ok()
+}
+
+pub struct BuildManifest {
+    output_folder: PathBuf,
+    /// where the manifest is saved
+    path: PathBuf,
+    /// paths relative to the output folder
+    files: BTreeSet<PathBuf>,
+}
+
+impl BuildManifest {
+    /// a missing manifest means squid owns nothing yet
+    pub fn load(output_folder: &Path) -> Self {
+        let path = cache::cache_folder(output_folder).join(MANIFEST_FILE);
+        let files = fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        Self {
+            output_folder: output_folder.to_path_buf(),
+            path,
+            files,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(folder) = self.path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let json = serde_json::to_string_pretty(&self.files)?;
+        crate::io::write_atomically(&self.path, json)
+            .with_context(|| format!("could not write {:?}", self.path))
+    }
+
+    /// files of the output recorded since the last call
+    fn take_recorded(&self) -> BTreeSet<PathBuf> {
+        let mut recorded = recorded().lock().unwrap();
+        let (mine, others): (HashSet<PathBuf>, HashSet<PathBuf>) = recorded
+            .drain()
+            .partition(|path| path.starts_with(&self.output_folder));
+        *recorded = others;
+        mine.iter()
+            .filter_map(|path| path.strip_prefix(&self.output_folder).ok())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+
+    /// After a full build, the recorded files become the manifest and the files
+    /// previously produced but not anymore are removed. Returns the removed files.
+    pub fn replace_with_recorded(&mut self) -> Vec<PathBuf> {
+        let files = self.take_recorded();
+        let stale: Vec<PathBuf> = self.files.difference(&files).cloned().collect();
+        self.files = files;
+        stale
+            .into_iter()
+            .filter(|file| self.remove_file(file))
+            .collect()
+    }
+
+    /// After an incremental build, which produces part of the website, adds the recorded
+    /// files and forgets the removed ones.
+    pub fn extend_with_recorded(&mut self) {
+        let recorded = self.take_recorded();
+        self.files.extend(recorded);
+        let output_folder = &self.output_folder;
+        self.files.retain(|file| output_folder.join(file).exists());
+    }
+
+    /// removes every file squid produced, and the manifest. Returns how many were removed.
+    pub fn clean(self) -> Result<usize> {
+        let removed = self
+            .files
+            .iter()
+            .filter(|file| self.remove_file(file))
+            .count();
+        if self.path.exists() {
+            fs::remove_file(&self.path)
+                .with_context(|| format!("could not remove {:?}", self.path))?;
+        }
+        Ok(removed)
+    }
+
+    /// removes the file, and its folders once they are empty
+    fn remove_file(&self, file: &Path) -> bool {
+        let path = self.output_folder.join(file);
+        if !remove(&path) {
+            return false;
+        }
+        let mut folder = path.parent();
+        while let Some(f) = folder.filter(|f| *f != self.output_folder) {
+            // fails when the folder is not empty
+            if fs::remove_dir(f).is_err() {
+                break;
+            }
+            folder = f.parent();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_removes_only_what_is_not_produced_anymore() {
+        let tempdir = TempDir::new("outputs").unwrap();
+        let output = tempdir.path().join("output");
+        fs::create_dir_all(output.join("posts")).unwrap();
+        for file in [
+            "index.html",
+            "posts/old.html",
+            "posts/old.html.gz",
+            "mine.txt",
+        ] {
+            fs::write(output.join(file), "").unwrap();
+        }
+        record(&output.join("index.html"));
+        record(&output.join("posts/old.html"));
+        let mut manifest = BuildManifest::load(&output);
+        assert!(manifest.replace_with_recorded().is_empty());
+        manifest.save().unwrap();
+
+        // the post was deleted
+        record(&output.join("index.html"));
+        let mut manifest = BuildManifest::load(&output);
+        let removed = manifest.replace_with_recorded();
+
+        assert_eq!(vec![PathBuf::from("posts/old.html")], removed);
+        assert!(!output.join("posts").exists());
+        assert!(output.join("index.html").exists());
+        assert!(output.join("mine.txt").exists());
+
+        assert_eq!(1, manifest.clean().unwrap());
+        assert!(!output.join("index.html").exists());
+        let manifest = cache::cache_folder(&output).join(MANIFEST_FILE);
+        assert!(!manifest.exists());
+        assert!(!output.join(MANIFEST_FILE).exists());
+        assert!(output.join("mine.txt").exists());
+    }
+}
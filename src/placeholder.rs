@@ -0,0 +1,183 @@
+//! Low quality placeholders of images: a blurhash and the dominant color.
+//!
+//! Computing them means decoding the whole image, so they are cached in the cache folder
+//! keyed on the content hash of the image and only computed again when it changes.
+
+use crate::resize::decode;
+use anyhow::{anyhow, Context, Result};
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tinylang::types::State;
+
+/// file, inside the cache folder, holding the computed placeholders
+const CACHE_FILE: &str = "placeholders.json";
+
+/// images are shrunk to this size before computing anything, plenty for a blur
+const THUMBNAIL_SIZE: u32 = 64;
+
+/// blurhash components, more means more detail and longer hashes
+const COMPONENTS: (u32, u32) = (4, 3);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub blurhash: String,
+    /// `#rrggbb`
+    pub color: String,
+}
+
+impl Placeholder {
+    pub fn compute(contents: &[u8]) -> Result<Self> {
+        let image = decode(contents)?;
+        let thumbnail = match image.width().max(image.height()) > THUMBNAIL_SIZE {
+            true => image.resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle),
+            false => image,
+        }
+        .to_rgba8();
+        let blurhash = blurhash::encode(
+            COMPONENTS.0,
+            COMPONENTS.1,
+            thumbnail.width(),
+            thumbnail.height(),
+            thumbnail.as_raw(),
+        )
+        .map_err(|e| anyhow!("could not compute the blurhash: {e}"))?;
+
+        Ok(Self {
+            blurhash,
+            color: dominant_color(thumbnail.as_raw()),
+        })
+    }
+
+    pub fn insert_into_state(&self, state: &mut State) {
+        state.insert("blurhash".into(), self.blurhash.clone().into());
+        state.insert("color".into(), self.color.clone().into());
+    }
+}
+
+/// average color of the most common bucket of colors, ignoring transparent pixels
+fn dominant_color(rgba: &[u8]) -> String {
+    // bucket -> (pixels, sum of each channel)
+    let mut buckets: HashMap<(u8, u8, u8), (u64, [u64; 3])> = HashMap::new();
+    for pixel in rgba.chunks_exact(4).filter(|p| p[3] >= 128) {
+        let bucket = buckets
+            .entry((pixel[0] >> 5, pixel[1] >> 5, pixel[2] >> 5))
+            .or_default();
+        bucket.0 += 1;
+        for (sum, value) in bucket.1.iter_mut().zip(pixel) {
+            *sum += *value as u64;
+        }
+    }
+
+    let (count, sums) = buckets
+        .into_values()
+        .max_by_key(|(count, sums)| (*count, sums.iter().sum::<u64>()))
+        .unwrap_or((1, [0; 3]));
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        sums[0] / count,
+        sums[1] / count,
+        sums[2] / count
+    )
+}
+
+/// placeholders of the images of the website, loaded from and saved to the cache folder
+pub struct Placeholders {
+    file: PathBuf,
+    /// content hash -> placeholder, and whether it changed since it was loaded
+    cache: Mutex<(HashMap<String, Placeholder>, bool)>,
+}
+
+impl Placeholders {
+    /// a missing or unreadable cache only means computing everything again
+    pub fn load(cache_folder: &Path) -> Self {
+        let file = cache_folder.join(CACHE_FILE);
+        let cache = fs::read_to_string(&file)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+
+        Self {
+            file,
+            cache: Mutex::new((cache, false)),
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Result<Placeholder> {
+        let contents = fs::read(path).with_context(|| format!("could not read {:?}", path))?;
+        let hash = format!("{:x}", Sha256::digest(&contents));
+
+        if let Some(placeholder) = self.cache.lock().unwrap().0.get(&hash) {
+            return Ok(placeholder.clone());
+        }
+
+        let placeholder = Placeholder::compute(&contents)
+            .with_context(|| format!("could not compute the placeholder of {:?}", path))?;
+        let mut cache = self.cache.lock().unwrap();
+        cache.0.insert(hash, placeholder.clone());
+        cache.1 = true;
+        Ok(placeholder)
+    }
+
+    /// writes the cache when new placeholders were computed
+    pub fn save(&self) -> Result<()> {
+        let cache = self.cache.lock().unwrap();
+        if !cache.1 {
+            return Ok(());
+        }
+        if let Some(folder) = self.file.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        // placeholders saved by another build since it was loaded are kept
+        let mut placeholders: HashMap<String, Placeholder> = fs::read_to_string(&self.file)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        placeholders.extend(cache.0.clone());
+        crate::io::write_atomically(&self.file, serde_json::to_string(&placeholders)?)
+            .with_context(|| format!("could not write {:?}", self.file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_computes_and_caches_placeholders() {
+        let tempdir = TempDir::new("placeholder").unwrap();
+        let image = tempdir.path().join("red.png");
+        let mut pixels = RgbImage::from_pixel(20, 10, Rgb([200, 10, 10]));
+        pixels.put_pixel(0, 0, Rgb([0, 0, 255]));
+        pixels.save(&image).unwrap();
+        let cache_folder = tempdir.path().join("cache");
+
+        let placeholders = Placeholders::load(&cache_folder);
+        let placeholder = placeholders.get(&image).unwrap();
+        placeholders.save().unwrap();
+
+        assert_eq!("#c80a0a", placeholder.color);
+        assert_eq!(28, placeholder.blurhash.len());
+
+        // cached values are not computed again
+        let mut cache: HashMap<String, Placeholder> =
+            serde_json::from_str(&fs::read_to_string(cache_folder.join(CACHE_FILE)).unwrap())
+                .unwrap();
+        cache.values_mut().for_each(|p| p.color = "#000000".into());
+        fs::write(
+            cache_folder.join(CACHE_FILE),
+            serde_json::to_string(&cache).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            "#000000",
+            Placeholders::load(&cache_folder).get(&image).unwrap().color
+        );
+    }
+}
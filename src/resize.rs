@@ -0,0 +1,388 @@
+//! Resized and converted variants of the images used by the website.
+//!
+//! Variants are named after the content hash of the original image and kept in a cache
+//! folder, so an image is only resized again when it changes. They are copied to the
+//! `[images] folder` of the output and referenced from there.
+
+use crate::config::ImageProcessingConfiguration;
+use crate::outputs;
+use anyhow::{anyhow, Context, Result};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor};
+use std::path::{Path, PathBuf};
+use tinylang::types::{FuncArguments, State, TinyLangType};
+
+/// key of the state holding what `resize_image` needs, functions cannot capture it
+const STATE_KEY: &str = "_image_processing";
+
+/// extensions we can decode and resize
+const RESIZABLE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+#[derive(Clone)]
+pub struct ImageProcessor {
+    config: ImageProcessingConfiguration,
+    /// folders absolute urls are looked up in, in order
+    roots: Vec<PathBuf>,
+    cache_folder: PathBuf,
+    output_folder: PathBuf,
+}
+
+/// an image being resized, read and hashed once for all its variants
+pub struct Original {
+    path: PathBuf,
+    contents: Vec<u8>,
+    hash: String,
+    width: u32,
+    /// decoded when a variant is not cached
+    image: Option<DynamicImage>,
+}
+
+impl Original {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read(path).with_context(|| format!("could not read {:?}", path))?;
+        let width = imagesize::blob_size(&contents)
+            .with_context(|| format!("could not read the size of {:?}", path))?
+            .width as u32;
+        Ok(Self {
+            path: path.to_path_buf(),
+            hash: format!("{:x}", Sha256::digest(&contents)),
+            contents,
+            width,
+            image: None,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn image(&mut self) -> Result<&DynamicImage> {
+        if self.image.is_none() {
+            let image = decode(&self.contents)
+                .with_context(|| format!("could not decode {:?}", self.path))?;
+            self.image = Some(image);
+        }
+        Ok(self.image.as_ref().unwrap())
+    }
+}
+
+/// a generated variant, `url` is relative to the website root
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub url: String,
+    pub width: u32,
+}
+
+impl ImageProcessor {
+    pub fn new(
+        config: ImageProcessingConfiguration,
+        roots: Vec<PathBuf>,
+        cache_folder: PathBuf,
+        output_folder: PathBuf,
+    ) -> Self {
+        Self {
+            config,
+            roots,
+            cache_folder,
+            output_folder,
+        }
+    }
+
+    pub fn config(&self) -> &ImageProcessingConfiguration {
+        &self.config
+    }
+
+    pub fn can_resize(path: &Path) -> bool {
+        path.extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .is_some_and(|e| RESIZABLE_EXTENSIONS.contains(&e.as_str()))
+    }
+
+    /// variants of the image at the configured widths, images are never enlarged
+    pub fn variants(&self, original: &mut Original, format: &str) -> Result<Vec<Variant>> {
+        let mut widths: Vec<u32> = self
+            .config
+            .widths
+            .iter()
+            .copied()
+            .filter(|w| *w < original.width)
+            .collect();
+        if widths.len() < self.config.widths.len() {
+            widths.push(original.width);
+        }
+
+        widths
+            .into_iter()
+            .map(|width| self.variant(original, width, format))
+            .collect()
+    }
+
+    /// generates the variant, unless it is already cached, and returns it
+    fn variant(&self, original: &mut Original, width: u32, format: &str) -> Result<Variant> {
+        let path = original.path.clone();
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = format!("{stem}-{}-{width}.{format}", &original.hash[..16]);
+
+        let cached = self.cache_folder.join("images").join(&name);
+        if !cached.exists() {
+            let image = original.image()?;
+            let resized = if width < image.width() {
+                let height =
+                    (image.height() as u64 * width as u64 / image.width() as u64).max(1) as u32;
+                image.resize_exact(width, height, FilterType::Lanczos3)
+            } else {
+                image.clone()
+            };
+            fs::create_dir_all(cached.parent().unwrap())?;
+            // renamed once complete, an interrupted build must not leave a broken variant
+            let temporary = cached.with_extension(format!("{format}.{}.tmp", std::process::id()));
+            self.encode(&resized, &temporary, format)
+                .with_context(|| format!("could not convert {:?} to {format}", path))?;
+            fs::rename(&temporary, &cached)?;
+        }
+
+        let output = self.output_folder.join(&self.config.folder).join(&name);
+        outputs::record(&output);
+        if !output.exists() {
+            fs::create_dir_all(output.parent().unwrap())?;
+            fs::copy(&cached, &output)?;
+        }
+
+        Ok(Variant {
+            url: format!("/{}/{name}", self.config.folder.trim_matches('/')),
+            width,
+        })
+    }
+
+    fn encode(&self, image: &DynamicImage, path: &Path, format: &str) -> Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let image = DynamicImage::ImageRgba8(image.to_rgba8());
+        match format {
+            // the encoder only writes lossless webp, `quality` does not apply
+            "webp" => image.write_with_encoder(WebPEncoder::new_lossless(writer))?,
+            "avif" => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                writer,
+                8,
+                self.config.quality,
+            ))?,
+            "jpg" | "jpeg" => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(writer, self.config.quality))?,
+            "png" => image.write_with_encoder(PngEncoder::new(writer))?,
+            _ => return Err(anyhow!("unsupported image format `{format}`")),
+        }
+        Ok(())
+    }
+
+    /// file of an image url, absolute urls are looked up in the roots
+    pub fn resolve(&self, url: &str) -> Option<PathBuf> {
+        match url.strip_prefix('/') {
+            Some(path) => self
+                .roots
+                .iter()
+                .map(|root| root.join(path))
+                .find(|p| p.is_file()),
+            None => Some(PathBuf::from(url)).filter(|p| p.is_file()),
+        }
+    }
+
+    /// what `resize_image` needs to generate variants, stored in the template state
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        state.insert(
+            "roots".into(),
+            TinyLangType::Vec(
+                self.roots
+                    .iter()
+                    .map(|r| r.to_string_lossy().to_string().into())
+                    .collect(),
+            ),
+        );
+        state.insert(
+            "cache_folder".into(),
+            self.cache_folder.to_string_lossy().to_string().into(),
+        );
+        state.insert(
+            "output_folder".into(),
+            self.output_folder.to_string_lossy().to_string().into(),
+        );
+        state.insert("folder".into(), self.config.folder.clone().into());
+        state.insert(
+            "format".into(),
+            self.config
+                .formats
+                .first()
+                .cloned()
+                .unwrap_or_else(|| "avif".to_string())
+                .into(),
+        );
+        state.insert(
+            "quality".into(),
+            TinyLangType::Numeric(self.config.quality as f64),
+        );
+        state
+    }
+
+    fn from_tinylang_state(state: &State) -> Option<(Self, String)> {
+        let state = match state.get(STATE_KEY) {
+            Some(TinyLangType::Object(o)) => o,
+            _ => return None,
+        };
+        let string = |key: &str| match state.get(key) {
+            Some(TinyLangType::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let roots = match state.get("roots") {
+            Some(TinyLangType::Vec(roots)) => {
+                roots.iter().map(|r| PathBuf::from(r.to_string())).collect()
+            }
+            _ => Vec::new(),
+        };
+        let quality = match state.get("quality") {
+            Some(TinyLangType::Numeric(q)) => *q as u8,
+            _ => ImageProcessingConfiguration::default().quality,
+        };
+
+        let config = ImageProcessingConfiguration {
+            folder: string("folder")?,
+            quality,
+            ..Default::default()
+        };
+        let processor = Self::new(
+            config,
+            roots,
+            PathBuf::from(string("cache_folder")?),
+            PathBuf::from(string("output_folder")?),
+        );
+        Some((processor, string("format")?))
+    }
+
+    pub fn insert_into_state(&self, state: &mut State) {
+        state.insert(
+            STATE_KEY.into(),
+            TinyLangType::Object(self.as_tinylang_state()),
+        );
+    }
+}
+
+/// decodes an image, rotated as its EXIF orientation says
+pub fn decode(contents: &[u8]) -> Result<DynamicImage> {
+    let mut decoder = ImageReader::new(Cursor::new(contents))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// `resize_image(path, width)` returns the url of the image resized to `width`, in
+/// the first configured format. Falls back to the original path when it cannot.
+pub fn resize_image(arguments: FuncArguments, state: &State) -> TinyLangType {
+    let path = match arguments.first() {
+        Some(TinyLangType::String(path)) => path.clone(),
+        _ => return TinyLangType::Nil,
+    };
+    let width = match arguments.get(1) {
+        Some(TinyLangType::Numeric(width)) if *width >= 1.0 => *width as u32,
+        _ => return TinyLangType::String(path),
+    };
+
+    let (processor, format) = match ImageProcessor::from_tinylang_state(state) {
+        Some(p) => p,
+        None => return TinyLangType::String(path),
+    };
+    let file = match processor.resolve(&path) {
+        Some(f) if ImageProcessor::can_resize(&f) => f,
+        _ => {
+            eprintln!("resize_image: cannot resize {path}");
+            return TinyLangType::String(path);
+        }
+    };
+
+    let variant = Original::read(&file).and_then(|mut original| {
+        let width = width.min(original.width());
+        processor.variant(&mut original, width, &format)
+    });
+    match variant {
+        Ok(variant) => TinyLangType::String(variant.url),
+        Err(e) => {
+            eprintln!("resize_image: {e:?}");
+            TinyLangType::String(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+    use tempdir::TempDir;
+
+    fn processor(tempdir: &TempDir) -> ImageProcessor {
+        let root = tempdir.path().join("static");
+        fs::create_dir(&root).unwrap();
+        RgbImage::new(100, 50).save(root.join("cat.png")).unwrap();
+
+        let config = ImageProcessingConfiguration {
+            enabled: true,
+            widths: vec![40, 80, 200],
+            ..Default::default()
+        };
+        ImageProcessor::new(
+            config,
+            vec![root],
+            tempdir.path().join("cache"),
+            tempdir.path().join("output"),
+        )
+    }
+
+    #[test]
+    fn test_generates_cached_variants() {
+        let tempdir = TempDir::new("resize").unwrap();
+        let processor = processor(&tempdir);
+        let file = processor.resolve("/cat.png").unwrap();
+        let mut original = Original::read(&file).unwrap();
+
+        let variants = processor.variants(&mut original, "webp").unwrap();
+
+        let widths: Vec<u32> = variants.iter().map(|v| v.width).collect();
+        assert_eq!(vec![40, 80, 100], widths);
+        let output = tempdir.path().join("output").join(&variants[0].url[1..]);
+        assert_eq!((40, 20), image::image_dimensions(&output).unwrap());
+
+        // cached variants are copied again when the output is cleaned
+        fs::remove_dir_all(tempdir.path().join("output")).unwrap();
+        processor.variants(&mut original, "webp").unwrap();
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_resize_image_function() {
+        let tempdir = TempDir::new("resize").unwrap();
+        let mut state = State::new();
+        processor(&tempdir).insert_into_state(&mut state);
+
+        let url = resize_image(
+            vec!["/cat.png".to_string().into(), TinyLangType::Numeric(60.0)],
+            &state,
+        );
+        assert!(url.to_string().starts_with("/_images/cat-"));
+        assert!(url.to_string().ends_with("-60.avif"));
+
+        let missing = resize_image(
+            vec!["/nope.png".to_string().into(), TinyLangType::Numeric(60.0)],
+            &state,
+        );
+        assert_eq!("/nope.png", missing.to_string());
+    }
+}
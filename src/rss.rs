@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use rss::{Channel, Guid, Item};
+use rss::Item;
 use rss::{ChannelBuilder, GuidBuilder, ItemBuilder};
 
 pub struct FeedConfig {
@@ -29,7 +29,7 @@ pub fn generate_rss(
 ) -> std::io::Result<()> {
     // Sort posts by date (newest first)
     let mut sorted_posts = posts.to_vec();
-    sorted_posts.sort_by(|a, b| b.date.cmp(&a.date));
+    sorted_posts.sort_by_key(|post| std::cmp::Reverse(post.date));
 
     let items: Vec<Item> = sorted_posts
         .iter()
@@ -41,7 +41,11 @@ pub fn generate_rss(
                 .link(Some(post_url.clone()))
                 .description(Some(post.excerpt.clone()))
                 .content(Some(post.html_content.clone()))
-                .author(Some(post.author.clone()))
+                .author(Some(if post.author.is_empty() {
+                    config.author.clone()
+                } else {
+                    post.author.clone()
+                }))
                 .guid(Some(
                     GuidBuilder::default()
                         .value(post_url)
@@ -60,11 +64,18 @@ pub fn generate_rss(
         .link(config.feed_url.clone())
         .items(items)
         .language(Some(config.language.clone()))
-        .last_build_date(Some(Utc::now().to_rfc2822()))
+        // the newest post rather than now, so the feed only changes along with the posts
+        .last_build_date(Some(
+            sorted_posts
+                .first()
+                .map(|post| post.date)
+                .unwrap_or_else(Utc::now)
+                .to_rfc2822(),
+        ))
         .generator(Some("Squid".to_string()))
         .build();
 
     let rss_content = channel.to_string();
     let output_path = output_dir.join("rss.xml");
-    std::fs::write(output_path, rss_content)
+    crate::io::write_if_changed(&output_path, rss_content).map(|_| ())
 }
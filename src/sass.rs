@@ -0,0 +1,254 @@
+//! Sass stylesheets of the static folder.
+//!
+//! Every `.scss` and `.sass` file that is not a partial (its name does not start with
+//! `_`) is compiled to a `.css` file at the same place in the output. The files each
+//! stylesheet loads through `@use`, `@forward` and `@import` are recorded, so editing a
+//! partial only compiles the stylesheets using it again.
+//!
+//! Errors are reported as diagnostics, and the stylesheet is replaced by one showing
+//! the error on top of the page, so it is noticed while serving the website.
+
+use crate::diagnostic::Diagnostic;
+use crate::io::write_if_changed;
+use codemap::SpanLoc;
+use grass::{ErrorKind, Fs, Logger, Options, StdFs};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const SASS_EXTENSIONS: &[&str] = &["scss", "sass"];
+
+pub fn is_sass(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| SASS_EXTENSIONS.iter().any(|s| e.eq(*s)))
+}
+
+/// partials are only compiled as part of the stylesheets loading them
+pub fn is_entry_point(path: &Path) -> bool {
+    is_sass(path)
+        && !path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('_'))
+}
+
+/// reads from the disk, keeping track of every file read
+#[derive(Debug, Default)]
+struct RecordingFs {
+    read: Mutex<HashSet<PathBuf>>,
+}
+
+impl Fs for RecordingFs {
+    fn is_dir(&self, path: &Path) -> bool {
+        StdFs.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        StdFs.is_file(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+        let contents = fs::read(&path)?;
+        self.read.lock().unwrap().insert(path);
+        Ok(contents)
+    }
+}
+
+/// turns `@warn` into diagnostics
+#[derive(Debug, Default)]
+struct DiagnosticLogger {
+    warnings: Mutex<Vec<Diagnostic>>,
+}
+
+impl Logger for DiagnosticLogger {
+    fn debug(&self, _location: SpanLoc, _message: &str) {}
+
+    fn warn(&self, location: SpanLoc, message: &str) {
+        self.warnings.lock().unwrap().push(
+            Diagnostic::warning(location.file.name(), message)
+                .at(location.begin.line + 1, location.begin.column + 1),
+        );
+    }
+}
+
+pub struct Stylesheets {
+    static_folder: PathBuf,
+    output_folder: PathBuf,
+}
+
+/// result of compiling stylesheets
+#[derive(Debug, Default)]
+pub struct Compilation {
+    /// stylesheet -> files it loaded, itself included
+    pub imports: HashMap<PathBuf, HashSet<PathBuf>>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Stylesheets {
+    pub fn new(static_folder: PathBuf, output_folder: PathBuf) -> Self {
+        Self {
+            static_folder,
+            output_folder,
+        }
+    }
+
+    /// every stylesheet of the static folder
+    pub fn entry_points(&self) -> Vec<PathBuf> {
+        let mut entry_points = Vec::new();
+        let mut folders = vec![self.static_folder.clone()];
+        while let Some(folder) = folders.pop() {
+            for entry in fs::read_dir(&folder).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    folders.push(path);
+                } else if is_entry_point(&path) {
+                    entry_points.push(path);
+                }
+            }
+        }
+        entry_points.sort();
+        entry_points
+    }
+
+    pub fn compile(&self, entry_points: &[PathBuf]) -> Compilation {
+        let mut compilation = Compilation::default();
+
+        for entry_point in entry_points {
+            let (imports, diagnostics) = self.compile_one(entry_point);
+            let entry_point = entry_point
+                .canonicalize()
+                .unwrap_or(entry_point.to_path_buf());
+            compilation.imports.insert(entry_point, imports);
+            compilation.diagnostics.extend(diagnostics);
+        }
+
+        compilation
+    }
+
+    fn compile_one(&self, entry_point: &Path) -> (HashSet<PathBuf>, Vec<Diagnostic>) {
+        let fs = RecordingFs::default();
+        let logger = DiagnosticLogger::default();
+        let options = Options::default()
+            .fs(&fs)
+            .logger(&logger)
+            .load_path(&self.static_folder);
+
+        let (css, mut diagnostics) = match grass::from_path(entry_point, &options) {
+            Ok(css) => (css, Vec::new()),
+            Err(e) => {
+                let diagnostic = Self::diagnostic(entry_point, *e);
+                (error_stylesheet(&diagnostic), vec![diagnostic])
+            }
+        };
+        diagnostics.extend(logger.warnings.into_inner().unwrap());
+
+        let output = self.output_path(entry_point);
+        let written = output
+            .parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| write_if_changed(&output, css));
+        if let Err(e) = written {
+            diagnostics.push(Diagnostic::error(
+                entry_point,
+                format!("could not write {:?}: {e}", output),
+            ));
+        }
+
+        (fs.read.into_inner().unwrap(), diagnostics)
+    }
+
+    fn diagnostic(entry_point: &Path, error: grass::Error) -> Diagnostic {
+        match error.kind() {
+            ErrorKind::ParseError { message, loc, .. } => {
+                Diagnostic::error(loc.file.name(), message)
+                    .at(loc.begin.line + 1, loc.begin.column + 1)
+            }
+            ErrorKind::IoError(e) => Diagnostic::error(entry_point, e.to_string()),
+            ErrorKind::FromUtf8Error(e) => Diagnostic::error(entry_point, e),
+            _ => Diagnostic::error(entry_point, "could not compile the stylesheet"),
+        }
+    }
+
+    /// `static/css/main.scss` is written to `output/css/main.css`
+    pub fn output_path(&self, entry_point: &Path) -> PathBuf {
+        let relative = entry_point
+            .strip_prefix(&self.static_folder)
+            .unwrap_or(entry_point);
+        self.output_folder.join(relative).with_extension("css")
+    }
+}
+
+/// css showing the error on top of the page
+fn error_stylesheet(diagnostic: &Diagnostic) -> String {
+    let text = diagnostic
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\A ");
+    format!(
+        "body::before {{ content: \"{text}\"; display: block; white-space: pre-wrap; padding: 1em; font-family: monospace; color: #900; background: #fee; border-bottom: 2px solid #900; }}\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn stylesheets() -> (TempDir, Stylesheets) {
+        let tempdir = TempDir::new("sass").unwrap();
+        let folder = tempdir.path().join("static");
+        fs::create_dir_all(folder.join("css")).unwrap();
+        fs::write(folder.join("_colors.scss"), "$primary: #333;").unwrap();
+        fs::write(
+            folder.join("css").join("main.scss"),
+            "@use '../colors';\na { color: colors.$primary; }",
+        )
+        .unwrap();
+        fs::write(folder.join("css").join("print.sass"), "p\n  margin: 0").unwrap();
+
+        let stylesheets = Stylesheets::new(folder, tempdir.path().join("output"));
+        (tempdir, stylesheets)
+    }
+
+    #[test]
+    fn test_compiles_stylesheets_and_records_imports() {
+        let (tempdir, stylesheets) = stylesheets();
+
+        let entry_points = stylesheets.entry_points();
+        let compilation = stylesheets.compile(&entry_points);
+
+        assert_eq!(2, entry_points.len());
+        assert!(compilation.diagnostics.is_empty());
+        let css = fs::read_to_string(tempdir.path().join("output/css/main.css")).unwrap();
+        assert_eq!("a {\n  color: #333;\n}\n", css);
+        assert!(tempdir.path().join("output/css/print.css").exists());
+
+        let main = entry_points[0].canonicalize().unwrap();
+        let colors = tempdir
+            .path()
+            .join("static/_colors.scss")
+            .canonicalize()
+            .unwrap();
+        assert!(compilation.imports[&main].contains(&colors));
+    }
+
+    #[test]
+    fn test_errors_are_diagnostics() {
+        let (tempdir, stylesheets) = stylesheets();
+        let main = tempdir.path().join("static/css/main.scss");
+        fs::write(&main, "a {\n  color: $missing;\n}").unwrap();
+
+        let compilation = stylesheets.compile(&[main]);
+
+        let diagnostic = &compilation.diagnostics[0];
+        assert!(diagnostic.is_error());
+        assert_eq!("Undefined variable.", diagnostic.message);
+        assert_eq!(Some((2, 10)), diagnostic.location);
+        let css = fs::read_to_string(tempdir.path().join("output/css/main.css")).unwrap();
+        assert!(css.starts_with("body::before { content: \"error: Undefined variable."));
+    }
+}
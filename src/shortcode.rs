@@ -0,0 +1,241 @@
+//! Shortcodes let markdown documents call TinyLang templates.
+//!
+//! `{{< figure src="x.png" caption="A caption" >}}` renders `shortcodes/figure.template`
+//! (inside the template folder) with `args.src` and `args.caption` set, the document
+//! front matter is available as `page`. `{{</* figure */>}}` outputs the shortcode
+//! itself instead of rendering it, and shortcodes in code blocks and code spans are left
+//! as they are.
+//!
+//! Shortcodes are replaced by placeholders before the markdown is converted, so the
+//! markdown parser never sees (and escapes) the rendered html.
+
+use crate::fence::CodeBlocks;
+use crate::io::LazyFolderReader;
+use anyhow::{anyhow, Context, Result};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tinylang::eval;
+use tinylang::types::{State, TinyLangType};
+
+/// folder, inside the template folder, holding the shortcode templates
+pub const SHORTCODES_FOLDER: &str = "shortcodes";
+
+fn shortcode_regex() -> &'static Regex {
+    static SHORTCODE: OnceLock<Regex> = OnceLock::new();
+    SHORTCODE.get_or_init(|| {
+        Regex::new(
+            r#"\{\{<\s*(/\*)?\s*([A-Za-z0-9_\-]+)((?:\s+[A-Za-z0-9_\-]+\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>"']+))*)\s*(\*/)?\s*>\}\}"#,
+        )
+        .unwrap()
+    })
+}
+
+fn argument_regex() -> &'static Regex {
+    static ARGUMENT: OnceLock<Regex> = OnceLock::new();
+    ARGUMENT.get_or_init(|| {
+        Regex::new(r#"([A-Za-z0-9_\-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"']+))"#).unwrap()
+    })
+}
+
+fn placeholder(position: usize) -> String {
+    format!("SQUIDSHORTCODE{position}END")
+}
+
+/// a shortcode found in a document
+#[derive(Debug, Clone)]
+pub struct ShortcodeCall {
+    pub name: String,
+    pub arguments: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+pub struct Shortcodes {
+    /// shortcode name -> (template path, template contents)
+    templates: HashMap<String, (PathBuf, String)>,
+    /// state shared by every shortcode (website variables and functions)
+    state: State,
+}
+
+impl Shortcodes {
+    /// loads every `.template` inside the folder, a missing folder means no shortcodes
+    pub fn new(folder: &Path, state: State) -> Result<Self> {
+        let mut templates = HashMap::new();
+
+        if folder.is_dir() {
+            let reader = LazyFolderReader::new(folder, "template")
+                .context("could not create lazy folder reader for shortcodes folder")?;
+            for file in reader {
+                let file = file?;
+                let name = file.name.trim_end_matches(".template").to_string();
+                templates.insert(name, (file.path, file.contents));
+            }
+        }
+
+        Ok(Self { templates, state })
+    }
+
+    /// replaces the shortcodes by placeholders, returning the markdown and the calls
+    /// in the order of their placeholders
+    pub fn extract(&self, markdown: &str) -> (String, Vec<ShortcodeCall>) {
+        let mut calls = Vec::new();
+        let code = CodeBlocks::ranges_with_spans(markdown);
+
+        let markdown = shortcode_regex().replace_all(markdown, |caps: &Captures| {
+            let name = caps[2].to_string();
+            let arguments = caps.get(3).map(|a| a.as_str()).unwrap_or_default();
+
+            // escaped shortcode, output it without the comment markers
+            if caps.get(1).is_some() && caps.get(4).is_some() {
+                return format!("{{{{< {name}{arguments} >}}}}");
+            }
+            let start = caps.get(0).unwrap().start();
+            if code.iter().any(|range| range.contains(&start)) {
+                return caps[0].to_string();
+            }
+
+            let arguments = argument_regex()
+                .captures_iter(arguments)
+                .map(|arg| {
+                    let value = arg
+                        .get(2)
+                        .or(arg.get(3))
+                        .or(arg.get(4))
+                        .map(|v| v.as_str())
+                        .unwrap_or_default();
+                    (arg[1].to_string(), value.to_string())
+                })
+                .collect();
+
+            calls.push(ShortcodeCall { name, arguments });
+            placeholder(calls.len() - 1)
+        });
+
+        (markdown.to_string(), calls)
+    }
+
+    /// renders the shortcodes and puts them in place of their placeholders
+    pub fn render(&self, html: &str, calls: &[ShortcodeCall], page: &State) -> Result<String> {
+        let mut html = html.to_string();
+
+        for (position, call) in calls.iter().enumerate() {
+            let (_, template) = self
+                .templates
+                .get(&call.name)
+                .ok_or(anyhow!("unknown shortcode `{}`", call.name))?;
+
+            let mut state = self.state.clone();
+            state.insert("page".into(), TinyLangType::Object(page.clone()));
+            state.insert(
+                "args".into(),
+                TinyLangType::Object(
+                    call.arguments
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone().into()))
+                        .collect(),
+                ),
+            );
+
+            let rendered = eval(template, state)
+                .map_err(|e| anyhow!("could not render shortcode `{}`: {e}", call.name))?;
+
+            // a shortcode alone in its line is a block, it should not be wrapped in a paragraph
+            let placeholder = placeholder(position);
+            let block = format!("<p>{placeholder}</p>");
+            html = if html.contains(&block) {
+                html.replacen(&block, &rendered, 1)
+            } else {
+                html.replacen(&placeholder, &rendered, 1)
+            };
+        }
+
+        Ok(html)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir, File};
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    fn shortcodes() -> (TempDir, Shortcodes) {
+        let tempdir = TempDir::new("shortcodes").unwrap();
+        let folder = tempdir.path().join(SHORTCODES_FOLDER);
+        create_dir(&folder).unwrap();
+        let mut file = File::create(folder.join("figure.template")).unwrap();
+        write!(
+            file,
+            r#"<figure><img src="{{{{ args.src }}}}"><figcaption>{{{{ args.caption }}}} - {{{{ page.title }}}}</figcaption></figure>"#
+        )
+        .unwrap();
+
+        let shortcodes = Shortcodes::new(&folder, State::new()).unwrap();
+        (tempdir, shortcodes)
+    }
+
+    #[test]
+    fn test_renders_block_shortcode() {
+        let (_tempdir, shortcodes) = shortcodes();
+        let markdown = "# Title\n\n{{< figure src=\"x.png\" caption='A cat' >}}\n";
+
+        let (markdown, calls) = shortcodes.extract(markdown);
+        assert_eq!(1, calls.len());
+        assert_eq!("x.png", calls[0].arguments["src"]);
+
+        let html = markdown::to_html(&markdown);
+        let mut page = State::new();
+        page.insert("title".into(), "Post".into());
+        let html = shortcodes.render(&html, &calls, &page).unwrap();
+
+        assert_eq!(
+            r#"<h1>Title</h1>
+<figure><img src="x.png"><figcaption>A cat - Post</figcaption></figure>
+"#,
+            html
+        );
+    }
+
+    #[test]
+    fn test_escaped_and_unknown_shortcodes() {
+        let (_tempdir, shortcodes) = shortcodes();
+
+        let (markdown, calls) = shortcodes.extract("{{</* figure src=\"x.png\" */>}}");
+        assert!(calls.is_empty());
+        assert_eq!("{{< figure src=\"x.png\" >}}", markdown);
+
+        let (markdown, calls) = shortcodes.extract("{{< video >}}");
+        let html = markdown::to_html(&markdown);
+        assert!(shortcodes.render(&html, &calls, &State::new()).is_err());
+    }
+
+    #[test]
+    fn test_shortcodes_in_code_blocks_are_code() {
+        let (_tempdir, shortcodes) = shortcodes();
+        let markdown =
+            "```\n{{< figure >}}\n```\n\n    {{< video >}}\n\n{{< figure src=\"x.png\" >}}";
+
+        let (extracted, calls) = shortcodes.extract(markdown);
+
+        assert_eq!(1, calls.len());
+        assert!(extracted.starts_with("```\n{{< figure >}}\n```\n\n    {{< video >}}\n\n"));
+    }
+
+    #[test]
+    fn test_shortcodes_in_code_spans_are_code() {
+        let (_tempdir, shortcodes) = shortcodes();
+        let markdown = "Hugo uses `{{< figure >}}` for figures.";
+
+        let (extracted, calls) = shortcodes.extract(markdown);
+        assert!(calls.is_empty());
+
+        let html = markdown::to_html(&extracted);
+        let html = shortcodes.render(&html, &calls, &State::new()).unwrap();
+        assert_eq!(
+            "<p>Hugo uses <code>{{&lt; figure &gt;}}</code> for figures.</p>",
+            html
+        );
+    }
+}
@@ -1,14 +1,30 @@
-use crate::config::Configuration;
-use crate::deps::{DependencyGraph, FileChangeEvent};
+use crate::admonition::{Admonitions, ADMONITIONS_FOLDER};
+use crate::assets::{self, asset, asset_integrity, Assets};
+use crate::cache::{self, BuildCache, CacheKey, PageCache, CACHE_FOLDER};
+use crate::compress::Compressor;
+use crate::config::{Configuration, MarkdownConfiguration};
+use crate::data::{self, DataCollection, DataFile, DATA_EXTENSIONS};
+use crate::deps::{DependencyGraph, FileChangeEvent, FileChangeType};
+use crate::diagnostic::Diagnostic;
+use crate::gallery::{GalleryBuilder, GalleryCollection, GALLERY_TEMPLATE};
+use crate::html;
+use crate::images::ImageResolver;
 use crate::io;
-use crate::io::{LazyFolderReader, TemplateFile};
+use crate::io::{LazyFolderReader, OutputFile, TemplateFile};
+use crate::links::LinkResolver;
+use crate::outputs;
 use crate::rss::*;
+use crate::sass::{self, Stylesheets};
+use crate::shortcode::{Shortcodes, SHORTCODES_FOLDER};
 use anyhow::Context;
 use anyhow::Result;
 
 use crate::md::{MarkdownCollection, MarkdownDocument};
-use crate::tinylang::{render, reverse, sort_by_key};
-use std::collections::HashMap;
+use crate::placeholder::Placeholders;
+use crate::resize::{resize_image, ImageProcessor};
+use crate::tinylang::{render, reverse, sort_by_key, state_for_template};
+use crate::wikilink::{Backlink, WikiLinkResolver};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tinylang::eval;
@@ -19,23 +35,68 @@ use tokio::task::JoinSet;
 struct Builder {
     tinylang_state: Arc<State>,
     output_folder: PathBuf,
-    eval_tasks: Option<JoinSet<String>>,
+    eval_tasks: Option<JoinSet<OutputFile>>,
+    minify_html: bool,
+    pages: Option<PageCache>,
+}
+
+/// renders and writes a page, minified when asked to, unless the build cache knows the
+/// output is still what it would render
+async fn write_page(
+    output_folder: PathBuf,
+    file_name: String,
+    minify: bool,
+    pages: Option<PageCache>,
+    render: impl FnOnce() -> String,
+) -> OutputFile {
+    let output = output_folder.join(&file_name);
+    if pages.as_ref().is_some_and(|pages| pages.is_fresh(&output)) {
+        return OutputFile {
+            name: file_name,
+            written: false,
+        };
+    }
+
+    // e.g. the images resized while rendering, recorded again when the page is not
+    let (html, produced) = outputs::capture(render);
+    let html = match minify {
+        true => html::minify(&html),
+        false => html,
+    };
+    if let Some(pages) = pages {
+        pages.insert(output, html.as_bytes(), produced);
+    }
+    let written = io::write_to_disk(output_folder, &file_name, html).await;
+    OutputFile {
+        name: file_name,
+        written,
+    }
 }
 
 impl Builder {
-    fn new(state: State, output_folder: PathBuf) -> Self {
+    fn new(state: State, output_folder: PathBuf, minify_html: bool) -> Self {
         Self {
             tinylang_state: Arc::new(state),
             output_folder,
             eval_tasks: None,
+            minify_html,
+            pages: None,
         }
     }
 
+    /// pages the output already has are not rendered again
+    fn with_pages(mut self, pages: Option<PageCache>) -> Self {
+        self.pages = pages;
+        self
+    }
+
     async fn process_folder(
         &mut self,
-        eval_tasks: JoinSet<String>,
+        eval_tasks: JoinSet<OutputFile>,
         template_folder_reader: &mut LazyFolderReader,
         collections: &HashMap<String, MarkdownCollection>,
+        data_collections: &HashMap<String, DataCollection>,
+        galleries: &HashMap<String, GalleryCollection>,
     ) {
         self.eval_tasks = Some(eval_tasks);
 
@@ -52,6 +113,13 @@ impl Builder {
                 if let Some(collection) = collections.get(collection_name) {
                     self.eval_markdown_collection_to_output_file(collection.clone(), file)
                         .await;
+                } else if let Some(collection) = data_collections.get(collection_name) {
+                    self.eval_data_collection_to_output_file(collection.clone(), file)
+                        .await;
+                } else if file.name == GALLERY_TEMPLATE {
+                    for gallery in galleries.values() {
+                        self.eval_gallery_to_output_file(gallery.clone(), file.clone());
+                    }
                 }
                 continue;
             }
@@ -64,18 +132,16 @@ impl Builder {
     fn eval_template_to_output_file(&mut self, file: TemplateFile) {
         let output_folder = self.output_folder.to_path_buf();
         let state = self.tinylang_state.clone();
+        let minify = self.minify_html;
+        let pages = self.pages.clone();
 
         self.eval_tasks.as_mut().unwrap().spawn(async move {
             let file_name = file.name.replace(".template", ".html");
-            let html = {
-                let state = (*state).clone();
-
-                eval(&file.contents, state).unwrap()
-            };
+            let state = state_for_template(&file.contents, &state);
+            let pages = pages.and_then(|pages| pages.for_template(&file.contents, &state));
+            let render = move || eval(&file.contents, state).unwrap();
 
-            io::write_to_disk(output_folder, &file_name, html).await;
-
-            file_name
+            write_page(output_folder, file_name, minify, pages, render).await
         });
     }
 
@@ -103,53 +169,203 @@ impl Builder {
         template: TemplateFile,
     ) {
         let output_folder = self.mk_collection_dir(&collection).await;
+        let renderer = PageRenderer::new(template, &self.tinylang_state, self.pages.as_ref());
 
         // we need for each item in the collection
         // to evaluate the template using its header and content
         for item in collection.collection {
             let output_folder = output_folder.clone();
 
-            let state = self.tinylang_state.clone();
+            let renderer = renderer.clone();
+            let minify = self.minify_html;
 
-            let template = template.clone();
+            self.eval_tasks.as_mut().unwrap().spawn(async move {
+                // we need to save our file following the markdown file and not the template
+                let file_name = item.name.replace(".md", ".html");
+                let content = item.as_tinylang_state().into();
+                let pages = renderer.pages(&content);
+
+                let render = move || renderer.render(content);
+
+                write_page(output_folder, file_name, minify, pages, render).await
+            });
+        }
+    }
+
+    /// builds one page per record of a data file using the appropriate template
+    async fn eval_data_collection_to_output_file(
+        &mut self,
+        collection: DataCollection,
+        template: TemplateFile,
+    ) {
+        let output_folder = self.output_folder.join(&collection.name);
+
+        if !output_folder.exists() {
+            create_dir(&output_folder).await.unwrap();
+        }
+        let renderer = PageRenderer::new(template, &self.tinylang_state, self.pages.as_ref());
+
+        for record in collection.records {
+            let output_folder = output_folder.clone();
+
+            let renderer = renderer.clone();
+            let minify = self.minify_html;
+
+            let content = record.as_tinylang_state(&collection.name).into();
 
             self.eval_tasks.as_mut().unwrap().spawn(async move {
-                let html = {
-                    let mut state = (*state).clone();
+                let pages = renderer.pages(&content);
+                let render = move || renderer.render(content);
 
-                    state.insert("content".into(), item.as_tinylang_state().into());
+                let file_name = format!("{}.html", record.slug);
 
-                    eval(&template.contents, state).unwrap()
-                };
+                write_page(output_folder, file_name, minify, pages, render).await
+            });
+        }
+    }
 
-                // we need to save our file following the markdown file and not the template
-                let file_name = item.name.replace(".md", ".html");
+    /// builds a page per photo of the gallery, next to the published photos
+    fn eval_gallery_to_output_file(&mut self, gallery: GalleryCollection, template: TemplateFile) {
+        let output_folder = self.output_folder.join(&gallery.name);
+        let renderer = PageRenderer::new(template, &self.tinylang_state, self.pages.as_ref());
+
+        for photo in gallery.photos {
+            let output_folder = output_folder.clone();
 
-                io::write_to_disk(output_folder, &file_name, html).await;
+            let renderer = renderer.clone();
+            let minify = self.minify_html;
 
-                file_name
+            self.eval_tasks.as_mut().unwrap().spawn(async move {
+                let file_name = photo.page_name();
+                let content = photo.as_tinylang_state().into();
+                let pages = renderer.pages(&content);
+
+                let render = move || renderer.render(content);
+
+                write_page(output_folder, file_name, minify, pages, render).await
             });
         }
     }
 }
 
+/// renders the pages of a partial template, one per item it gets as `content`
+#[derive(Clone)]
+struct PageRenderer {
+    state: Arc<State>,
+    template: Arc<TemplateFile>,
+    /// pages of the template the build cache has, keyed on the state it reads
+    pages: Option<PageCache>,
+}
+
+impl PageRenderer {
+    /// only keeps the part of the state the template reads. `eval` takes the state by value,
+    /// so every page still gets its own copy of that part
+    fn new(template: TemplateFile, state: &State, pages: Option<&PageCache>) -> Self {
+        let state = state_for_template(&template.contents, state);
+        let pages = pages.and_then(|pages| pages.for_template(&template.contents, &state));
+        Self {
+            state: Arc::new(state),
+            template: Arc::new(template),
+            pages,
+        }
+    }
+
+    /// the cached page showing this content
+    fn pages(&self, content: &TinyLangType) -> Option<PageCache> {
+        self.pages.as_ref().map(|pages| pages.for_content(content))
+    }
+
+    /// parses the template again for every page, TinyLang cannot keep a parsed template
+    fn render(&self, content: TinyLangType) -> String {
+        let mut state = (*self.state).clone();
+        state.insert("content".into(), content);
+        eval(&self.template.contents, state).unwrap()
+    }
+}
+
 #[derive(Default)]
 struct WebsiteCachedState {
     collections: Option<HashMap<String, MarkdownCollection>>,
+    data_files: Option<Vec<DataFile>>,
+    data_collections: Option<HashMap<String, DataCollection>>,
+    galleries: HashMap<String, GalleryCollection>,
+    /// stylesheet -> files it loaded
+    stylesheet_imports: HashMap<PathBuf, HashSet<PathBuf>>,
+    /// source of the bundled assets -> their url
+    assets: HashMap<String, String>,
+    /// source of the assets -> their integrity hash
+    asset_integrity: HashMap<String, String>,
+    /// problems found since they were last taken
+    diagnostics: Vec<Diagnostic>,
     state: Option<State>,
     builder: Option<Builder>,
     deps: Option<DependencyGraph>,
+    /// documents and pages of the previous runs
+    build: Option<Arc<BuildCache>>,
+}
+
+/// converts the markdown documents, shared by the threads doing it
+struct DocumentConverter {
+    config: MarkdownConfiguration,
+    shortcodes: Shortcodes,
+    admonitions: Admonitions,
+    /// key of what the conversion reads besides the document, see `documents_key`
+    key: String,
+    cache: Option<Arc<BuildCache>>,
+}
+
+impl DocumentConverter {
+    fn convert(&self, path: &Path, partial_uri: String) -> Result<MarkdownDocument> {
+        let file = TemplateFile::new(&path.to_path_buf())
+            .with_context(|| format!("could not read {:?}", path))?;
+        let key = CacheKey::new()
+            .with(&self.key)
+            .with(file.path.to_string_lossy().as_bytes())
+            .with(&file.contents)
+            .finish();
+        if let Some(document) = self.cache.as_ref().and_then(|c| c.document(path, &key)) {
+            return Ok(document);
+        }
+
+        // shortcodes may resize images while the document is converted
+        let (document, produced) = outputs::capture(|| {
+            MarkdownDocument::with_configuration(
+                &file.contents,
+                file.name,
+                partial_uri,
+                &self.config,
+                &self.shortcodes,
+                &self.admonitions,
+            )
+        });
+        let document = document?;
+        if let Some(cache) = &self.cache {
+            cache.insert_document(file.path, key, document.clone(), produced);
+        }
+        Ok(document)
+    }
 }
 
 pub struct Website {
     template_folder: PathBuf,
     posts_folder: Option<PathBuf>,
+    data_folder: Option<PathBuf>,
+    static_folder: Option<PathBuf>,
+    gallery_folder: Option<PathBuf>,
+    /// folder of the build in progress, generated images are written there
+    output_folder: Option<PathBuf>,
+    /// assets are built without source maps
+    production: bool,
+    /// the website is previewed with `--serve`, pages are kept readable
+    serving: bool,
+    /// documents and pages are cached across runs in the cache folder
+    build_cache: bool,
     configuration: Option<Configuration>,
     cache: WebsiteCachedState,
 }
 
 impl Website {
-    pub(crate) fn new(
+    pub fn new(
         configuration: Option<Configuration>,
         template_folder: PathBuf,
         posts_folder: Option<PathBuf>,
@@ -157,13 +373,70 @@ impl Website {
         Self {
             template_folder,
             posts_folder,
+            data_folder: None,
+            static_folder: None,
+            gallery_folder: None,
+            output_folder: None,
+            production: false,
+            serving: false,
+            build_cache: false,
             configuration,
             cache: WebsiteCachedState::default(),
         }
     }
 
-    pub async fn build_from_scratch(&mut self, output: &Path) -> Result<JoinSet<String>> {
+    /// folder with JSON/YAML/TOML/CSV files exposed to the templates as `data.<file name>`
+    pub fn with_data_folder(mut self, data_folder: Option<PathBuf>) -> Self {
+        self.data_folder = data_folder;
+        self
+    }
+
+    /// folder copied to the output, markdown documents can use the images it holds
+    pub fn with_static_folder(mut self, static_folder: Option<PathBuf>) -> Self {
+        self.static_folder = static_folder;
+        self
+    }
+
+    /// folder of images, each of its folders becomes a photo gallery
+    pub fn with_gallery_folder(mut self, gallery_folder: Option<PathBuf>) -> Self {
+        self.gallery_folder = gallery_folder;
+        self
+    }
+
+    /// build for publishing rather than for working on the website
+    pub fn with_production(mut self, production: bool) -> Self {
+        self.production = production;
+        self
+    }
+
+    /// build for previewing the website while working on it
+    pub fn with_serving(mut self, serving: bool) -> Self {
+        self.serving = serving;
+        self
+    }
+
+    /// reuse the documents and pages of the previous runs, see `save_cache`
+    pub fn with_build_cache(mut self, build_cache: bool) -> Self {
+        self.build_cache = build_cache;
+        self
+    }
+
+    pub async fn build_from_scratch(&mut self, output: &Path) -> Result<JoinSet<OutputFile>> {
+        self.output_folder = Some(output.to_path_buf());
+        if self.build_cache {
+            self.cache
+                .build
+                .get_or_insert_with(|| Arc::new(BuildCache::load(&cache::cache_folder(output))));
+        }
+        // templates and shortcodes get the path of the bundled assets
+        if let Some(stylesheets) = self.stylesheets(output) {
+            let entry_points = stylesheets.entry_points();
+            self.compile_stylesheets(&stylesheets, &entry_points);
+        }
+        self.build_assets(output);
         let collections = self.build_markdown_collections().await?;
+        self.build_data_files().await?;
+        self.build_galleries(&collections, output)?;
         let c = self.configuration.clone().unwrap(); //fixme
         let feed_config = FeedConfig {
             title: c.website_name.clone(),
@@ -186,13 +459,11 @@ impl Website {
                 .unwrap_or_else(|| "en-us".to_string()),
         };
 
-        self.cache.builder = Some(Builder::new(
-            self.build_state(&collections),
-            output.to_path_buf(),
-        ));
+        self.cache.builder = Some(self.new_builder(&collections, output));
 
         self.generate_site_rss(&feed_config, &collections, output)
             .await?;
+        self.generate_highlight_stylesheet(output).await?;
 
         self.compile_templates().await
     }
@@ -220,9 +491,26 @@ impl Website {
         Ok(())
     }
 
+    /// Write the syntax highlighting theme when code blocks are highlighted with css classes
+    async fn generate_highlight_stylesheet(&self, output: &Path) -> Result<()> {
+        let highlight = match self.configuration.as_ref() {
+            Some(c) if c.markdown.highlight.enabled && c.markdown.highlight.css_classes => {
+                &c.markdown.highlight
+            }
+            _ => return Ok(()),
+        };
+
+        let css = crate::highlight::theme_stylesheet(highlight)
+            .context("Failed to generate highlight stylesheet")?;
+        io::write_to_disk(output.to_path_buf(), &highlight.stylesheet, css).await;
+
+        Ok(())
+    }
+
     /// Rebuild collections and RSS after markdown files change. Call compile_templates
     /// afterward to regenerate HTML.
     pub async fn rebuild_after_markdown_change(&mut self, output: &Path) -> Result<()> {
+        self.output_folder = Some(output.to_path_buf());
         let collections = self.build_markdown_collections().await?;
         let c = self
             .configuration
@@ -248,10 +536,7 @@ impl Website {
                 .cloned()
                 .unwrap_or_else(|| "en-us".to_string()),
         };
-        self.cache.builder = Some(Builder::new(
-            self.build_state(&collections),
-            output.to_path_buf(),
-        ));
+        self.cache.builder = Some(self.new_builder(&collections, output));
         let all_posts: Vec<_> = collections
             .values()
             .flat_map(|c| {
@@ -264,10 +549,11 @@ impl Website {
         Ok(())
     }
 
-    pub async fn compile_templates(&mut self) -> Result<JoinSet<String>> {
-        let mut template_folder_reader =
-            LazyFolderReader::new(&self.template_folder, "template")
-                .context("could not create lazy folder reader for template folder")?;
+    pub async fn compile_templates(&mut self) -> Result<JoinSet<OutputFile>> {
+        let mut template_folder_reader = LazyFolderReader::new(&self.template_folder, "template")
+            .context("could not create lazy folder reader for template folder")?
+            .without_folder(&self.shortcodes_folder())
+            .without_folder(&self.admonitions_folder());
         self.cache
             .builder
             .as_mut()
@@ -279,6 +565,11 @@ impl Website {
                     .collections
                     .as_ref()
                     .context("compile_templates called without caching collections")?,
+                self.cache
+                    .data_collections
+                    .as_ref()
+                    .context("compile_templates called without caching data collections")?,
+                &self.cache.galleries,
             )
             .await;
 
@@ -309,13 +600,20 @@ impl Website {
             .collections
             .as_ref()
             .context("build_dependency_graph called without collections")?;
+        let data_collections = self
+            .cache
+            .data_collections
+            .as_ref()
+            .context("build_dependency_graph called without data collections")?;
         let output_folder = output.to_path_buf();
         let base_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
 
         let mut deps = DependencyGraph::new(self.template_folder.clone(), output_folder.clone());
 
         let mut template_reader = LazyFolderReader::new(&self.template_folder, "template")
-            .context("could not create template reader for dependency graph")?;
+            .context("could not create template reader for dependency graph")?
+            .without_folder(&self.shortcodes_folder())
+            .without_folder(&self.admonitions_folder());
 
         while let Some(file) = template_reader.async_next().await {
             let file = file?;
@@ -323,8 +621,12 @@ impl Website {
 
             if file.name.starts_with('_') {
                 let collection_name = &file.name[1..file.name.len() - 9];
-                if collections.contains_key(collection_name) {
+                if collections.contains_key(collection_name)
+                    || data_collections.contains_key(collection_name)
+                {
                     deps.register_collection_partial(collection_name, file.path.clone());
+                } else if file.name == GALLERY_TEMPLATE {
+                    deps.register_gallery_template(file.path.clone());
                 }
             } else {
                 let output_name = file.name.replace(".template", ".html");
@@ -345,40 +647,233 @@ impl Website {
                 let md_path = collection.relative_path.join(&item.name);
                 let output_name = item.name.replace(".md", ".html");
                 let output_path = output_dir.join(&output_name);
+                for shortcode in &item.shortcodes {
+                    deps.register_shortcode_usage(
+                        md_path.clone(),
+                        self.shortcodes_folder()
+                            .join(format!("{shortcode}.template")),
+                    );
+                }
+                for kind in &item.admonitions {
+                    deps.register_admonition_usage(
+                        md_path.clone(),
+                        self.admonitions_folder().join(format!("{kind}.template")),
+                    );
+                }
                 deps.register_markdown_output(md_path, collection_name, output_path);
             }
         }
 
+        for (collection_name, collection) in data_collections {
+            // markdown collections take precedence when both share the same name
+            if collections.contains_key(collection_name) {
+                continue;
+            }
+            let output_dir = output_folder.join(collection_name);
+            for record in &collection.records {
+                let output_path = output_dir.join(format!("{}.html", record.slug));
+                deps.register_data_record_output(
+                    collection.path.clone(),
+                    collection_name,
+                    output_path,
+                );
+            }
+        }
+
+        for data_file in self.cache.data_files.iter().flatten() {
+            deps.register_data_file(data_file.path.clone(), data_file.top_level_name());
+        }
+
+        for (stylesheet, imports) in &self.cache.stylesheet_imports {
+            for import in imports {
+                deps.register_stylesheet_import(stylesheet.clone(), import.clone());
+            }
+        }
+
+        // pages of deleted posts, templates or records
+        if let Some(previous) = &self.cache.deps {
+            for stale in previous.outputs().difference(&deps.outputs()) {
+                if crate::outputs::remove(stale) {
+                    println!("removed {}", stale.display());
+                }
+            }
+        }
+
         self.cache.deps = Some(deps);
         Ok(())
     }
 
+    /// Compiles the stylesheets loading the changed static files, and the new ones.
+    pub async fn rebuild_stylesheets(
+        &mut self,
+        change: &FileChangeEvent,
+        output: &Path,
+    ) -> Result<()> {
+        let stylesheets = match self.stylesheets(output) {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let deps = self.cache.deps.as_ref().context("no dependency graph")?;
+
+        let mut entry_points = deps.affected_stylesheets(change);
+        entry_points.extend(
+            change
+                .paths
+                .iter()
+                .filter(|path| sass::is_entry_point(path) && path.is_file())
+                .cloned(),
+        );
+        if entry_points.is_empty() {
+            return Ok(());
+        }
+
+        let entry_points: Vec<PathBuf> = entry_points.into_iter().collect();
+        self.compile_stylesheets(&stylesheets, &entry_points);
+        // the files the stylesheets load may have changed
+        self.build_dependency_graph(output).await
+    }
+
+    fn stylesheets(&self, output: &Path) -> Option<Stylesheets> {
+        let static_folder = self.static_folder.as_ref()?;
+        Some(Stylesheets::new(
+            static_folder.clone(),
+            output.to_path_buf(),
+        ))
+    }
+
+    fn compile_stylesheets(&mut self, stylesheets: &Stylesheets, entry_points: &[PathBuf]) {
+        let compilation = stylesheets.compile(entry_points);
+        self.cache.stylesheet_imports.extend(compilation.imports);
+        self.cache.diagnostics.extend(compilation.diagnostics);
+    }
+
+    /// Bundles the assets again, the stylesheets they import may have changed. Returns
+    /// the pages using the assets whose url or integrity hash changed, built again.
+    pub async fn rebuild_assets(&mut self, output: &Path) -> Result<JoinSet<OutputFile>> {
+        let previous = (
+            std::mem::take(&mut self.cache.assets),
+            std::mem::take(&mut self.cache.asset_integrity),
+        );
+        self.build_assets(output);
+
+        let state = self.cache.state.as_mut().context("no state")?;
+        assets::insert_into_state(&self.cache.assets, &self.cache.asset_integrity, state);
+        let builder = self.cache.builder.as_mut().context("no builder")?;
+        builder.tinylang_state = Arc::new(state.clone());
+
+        let changed: HashSet<String> = [
+            (&previous.0, &self.cache.assets),
+            (&previous.1, &self.cache.asset_integrity),
+        ]
+        .into_iter()
+        .flat_map(|(before, after)| {
+            before
+                .keys()
+                .chain(after.keys())
+                .filter(|path| before.get(*path) != after.get(*path))
+        })
+        .cloned()
+        .collect();
+        let deps = self.cache.deps.as_ref().context("no dependency graph")?;
+        let affected = deps.affected_asset_outputs(&changed);
+        self.build_outputs(affected)
+    }
+
+    /// None unless `[assets]` bundles or fingerprints and there is a static folder
+    fn assets(&self, output: &Path) -> Option<Assets> {
+        let config = self.configuration.as_ref()?.assets.clone();
+        if !config.enabled && !config.fingerprint {
+            return None;
+        }
+        Some(Assets::new(
+            self.static_folder.clone()?,
+            output.to_path_buf(),
+            config,
+            self.production,
+        ))
+    }
+
+    fn build_assets(&mut self, output: &Path) {
+        let (assets, stylesheets) = match (self.assets(output), self.stylesheets(output)) {
+            (Some(assets), Some(stylesheets)) => (assets, stylesheets),
+            _ => return,
+        };
+        let mut sources = assets.sources();
+        // compiled sass stylesheets are bundled from the output
+        sources.extend(
+            stylesheets
+                .entry_points()
+                .iter()
+                .map(|entry_point| stylesheets.output_path(entry_point)),
+        );
+
+        let build = assets.build(&sources);
+        if let Err(diagnostic) = assets.write_manifest(&build.manifest) {
+            self.cache.diagnostics.push(diagnostic);
+        }
+        self.cache.assets = build.manifest;
+        self.cache.asset_integrity = build.integrity;
+        self.cache.diagnostics.extend(build.diagnostics);
+    }
+
+    /// Writes the gzip and brotli variants of the outputs that changed, once everything
+    /// was written to the output.
+    pub fn compress_outputs(&self, output: &Path) {
+        let config = match self.configuration.as_ref() {
+            Some(c) if c.compression.enabled => c.compression.clone(),
+            _ => return,
+        };
+        let compressed = Compressor::new(config).compress_folder(output);
+        if compressed > 0 {
+            println!("Compressed {compressed} files");
+        }
+    }
+
+    /// problems found by the builds since the last call
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.cache.diagnostics)
+    }
+
     /// Incrementally rebuild only the outputs affected by the given file change.
     /// Returns None if a full rebuild is required (e.g. config change).
+    ///
+    /// Markdown changes go through `rebuild_after_markdown_change` and `compile_templates`
+    /// instead: titles, wiki-links and backlinks of any document may change, and the build
+    /// cache skips the pages whose template, rendered templates and the part of the state
+    /// the template reads did not change.
     pub async fn build_incremental(
         &mut self,
         change: &FileChangeEvent,
-        _output: &Path,
-    ) -> Result<Option<JoinSet<String>>> {
+        output: &Path,
+    ) -> Result<Option<JoinSet<OutputFile>>> {
         let deps = self.cache.deps.as_ref().context("no dependency graph")?;
 
-        if deps.requires_full_rebuild(change) {
+        if change.change_type == FileChangeType::Markdown || deps.requires_full_rebuild(change) {
             return Ok(None);
         }
 
+        // shortcodes and admonitions are rendered when the markdown is converted
+        if deps.is_markdown_template_change(change) {
+            self.rebuild_after_markdown_change(output).await?;
+        }
+
+        let deps = self.cache.deps.as_ref().context("no dependency graph")?;
+
         if deps.is_static_change(change) {
             return Ok(Some(JoinSet::new()));
         }
 
         let affected = deps.affected_outputs(change);
 
-        if affected.is_empty() {
-            return Ok(Some(JoinSet::new()));
-        }
+        self.build_outputs(affected).map(Some)
+    }
 
+    /// Builds the given outputs again with the cached state.
+    fn build_outputs(&self, affected: HashSet<PathBuf>) -> Result<JoinSet<OutputFile>> {
+        let deps = self.cache.deps.as_ref().context("no dependency graph")?;
         let collections = self.cache.collections.as_ref().context("no collections")?;
         let state = self.cache.state.as_ref().context("no state")?;
-        let _builder = self.cache.builder.as_mut().context("no builder")?;
+        let minify = self.minify_html();
 
         let mut eval_tasks = JoinSet::new();
 
@@ -391,11 +886,10 @@ impl Website {
                     .unwrap()
                     .to_string_lossy()
                     .to_string();
-                let state = state.clone();
+                let state = state_for_template(&template.contents, state);
                 eval_tasks.spawn(async move {
-                    let html = eval(&template.contents, state).unwrap();
-                    io::write_to_disk(output_folder, &file_name, html).await;
-                    file_name
+                    let render = move || eval(&template.contents, state).unwrap();
+                    write_page(output_folder, file_name, minify, None, render).await
                 });
             } else if let Some((md_path, coll_name)) = deps.markdown_for_output(&output_path) {
                 let collection = collections
@@ -417,17 +911,161 @@ impl Website {
                     .unwrap()
                     .to_string_lossy()
                     .to_string();
-                let mut state = state.clone();
+                let mut state = state_for_template(&template.contents, state);
                 state.insert("content".into(), item.as_tinylang_state().into());
                 eval_tasks.spawn(async move {
-                    let html = eval(&template.contents, state).unwrap();
-                    io::write_to_disk(output_folder, &file_name, html).await;
-                    file_name
+                    let render = move || eval(&template.contents, state).unwrap();
+                    write_page(output_folder, file_name, minify, None, render).await
                 });
+            } else if let Some((_, coll_name)) = deps.data_record_for_output(&output_path) {
+                let collection = self
+                    .cache
+                    .data_collections
+                    .as_ref()
+                    .and_then(|c| c.get(&coll_name))
+                    .context("data collection not found")?;
+                let slug = output_path
+                    .file_stem()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or("");
+                let record = collection
+                    .records
+                    .iter()
+                    .find(|r| r.slug == slug)
+                    .context("data record not found")?;
+                let partial_path = deps
+                    .partial_for_collection(&coll_name)
+                    .context("partial not found")?;
+                let template = TemplateFile::new(&partial_path)?;
+                let output_folder = output_path.parent().unwrap().to_path_buf();
+                let file_name = output_path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string();
+                let mut state = state_for_template(&template.contents, state);
+                state.insert(
+                    "content".into(),
+                    record.as_tinylang_state(&coll_name).into(),
+                );
+                eval_tasks.spawn(async move {
+                    let render = move || eval(&template.contents, state).unwrap();
+                    write_page(output_folder, file_name, minify, None, render).await
+                });
+            }
+        }
+
+        Ok(eval_tasks)
+    }
+
+    pub async fn build_data_files(&mut self) -> Result<Vec<DataFile>> {
+        let mut data_files = Vec::new();
+        let data_folder = match &self.data_folder {
+            Some(d) => d,
+            None => {
+                self.cache.data_collections = Some(HashMap::new());
+                self.cache.data_files = Some(data_files.clone());
+                return Ok(data_files);
+            }
+        };
+
+        let mut data_folder_reader =
+            LazyFolderReader::with_extensions(data_folder, DATA_EXTENSIONS)
+                .context("could not create lazy folder reader for data folder")?;
+
+        // files that cannot be read are left out of the state, and fail the build
+        while let Some(file) = data_folder_reader.async_next().await {
+            let file = match file {
+                Ok(f) => f,
+                Err(e) => {
+                    let diagnostic = Diagnostic::error(data_folder, format!("{e:#}"));
+                    self.cache.diagnostics.push(diagnostic);
+                    continue;
+                }
+            };
+            match DataFile::new(&file.path, data_folder, &file.contents) {
+                Ok(d) => data_files.push(d),
+                Err(e) => {
+                    let diagnostic = Diagnostic::error(&file.path, format!("{e:#}"));
+                    self.cache.diagnostics.push(diagnostic);
+                }
             }
         }
+        let diagnostics = data::remove_clashes(&mut data_files);
+        self.cache.diagnostics.extend(diagnostics);
+
+        self.cache.data_collections = Some(self.build_data_collections(&data_files));
+        self.cache.data_files = Some(data_files.clone());
+        Ok(data_files)
+    }
 
-        Ok(Some(eval_tasks))
+    /// data files holding a list of records, used to generate one page per record
+    fn build_data_collections(
+        &mut self,
+        data_files: &[DataFile],
+    ) -> HashMap<String, DataCollection> {
+        let default_config = crate::config::DataConfiguration::default();
+        let data_config = self
+            .configuration
+            .as_ref()
+            .map(|c| &c.data)
+            .unwrap_or(&default_config);
+
+        let mut collections: HashMap<String, DataCollection> = HashMap::new();
+        for file in data_files {
+            let collection = match file
+                .key
+                .last()
+                .and_then(|name| DataCollection::new(file, data_config.slug_field_for(name)))
+            {
+                Some(c) => c,
+                None => continue,
+            };
+            self.cache
+                .diagnostics
+                .extend(collection.diagnostics.iter().cloned());
+
+            // collections are named after the file alone, their pages would overwrite
+            // each other
+            if let Some(existing) = collections.get(&collection.name) {
+                self.cache.diagnostics.push(Diagnostic::error(
+                    &collection.path,
+                    format!(
+                        "data collection `{}` is also defined by {}, skipping its pages",
+                        collection.name,
+                        existing.path.display()
+                    ),
+                ));
+                continue;
+            }
+            collections.insert(collection.name.clone(), collection);
+        }
+        collections
+    }
+
+    /// Re-read the data folder and refresh the cached state, call build_incremental
+    /// afterward to regenerate the templates reading the changed files.
+    pub async fn rebuild_after_data_change(&mut self) -> Result<()> {
+        let data_files = self.build_data_files().await?;
+
+        let data_state: TinyLangType = data::as_tinylang_state(&data_files).into();
+        let state = self
+            .cache
+            .state
+            .as_mut()
+            .context("rebuild_after_data_change called without caching state")?;
+        state.insert("data".into(), data_state);
+
+        let builder = self
+            .cache
+            .builder
+            .as_mut()
+            .context("rebuild_after_data_change called without caching builder")?;
+        builder.tinylang_state = Arc::new(state.clone());
+
+        // records may have been added or removed, so the outputs known by the graph change
+        let output_folder = builder.output_folder.clone();
+        self.build_dependency_graph(&output_folder).await
     }
 
     pub async fn build_markdown_collections(
@@ -436,34 +1074,78 @@ impl Website {
         let mut collections = HashMap::new();
         let posts_folder = match &self.posts_folder {
             Some(p) => p,
-            None => return Ok(collections),
+            None => {
+                self.cache.collections = Some(collections.clone());
+                return Ok(collections);
+            }
         };
 
-        let mut markdown_folder_reader = io::LazyFolderReader::new(posts_folder, "md")
+        let markdown_folder_reader = io::LazyFolderReader::new(posts_folder, "md")
             .context("could not create lazy folder reader for markdown folder")?;
 
-        while let Some(file) = markdown_folder_reader.async_next().await {
-            let file = match file {
-                Ok(f) => f,
-                Err(e) => {
-                    //todo log lib
-                    eprintln!("{}", e);
-                    continue;
-                }
-            };
+        let markdown_config = self
+            .configuration
+            .as_ref()
+            .map(|c| c.markdown.clone())
+            .unwrap_or_default();
+        let converter = Arc::new(DocumentConverter {
+            shortcodes: Shortcodes::new(&self.shortcodes_folder(), self.build_default_state())
+                .context("could not load shortcodes")?,
+            admonitions: Admonitions::new(&self.admonitions_folder(), self.build_default_state())
+                .context("could not load admonition templates")?,
+            key: self.documents_key(),
+            cache: self.cache.build.clone(),
+            config: markdown_config.clone(),
+        });
 
-            let markdown_content = match MarkdownDocument::new(
-                &file.contents,
-                file.name,
-                self.partial_uri(&file.path),
-            ) {
-                Ok(c) => c,
+        // sorted, so collections list their documents in the same order on every build
+        let mut files: Vec<(PathBuf, String)> = markdown_folder_reader
+            .into_paths()
+            .into_iter()
+            .map(|path| {
+                let partial_uri = self.partial_uri(&path);
+                (path, partial_uri)
+            })
+            .collect();
+        files.sort();
+
+        // documents are read and converted on the blocking threads, a chunk per worker
+        let workers = std::thread::available_parallelism().map_or(1, usize::from);
+        let chunk_size = files.len().div_ceil(workers).max(1);
+        let tasks: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let chunk = chunk.to_vec();
+                let converter = converter.clone();
+                tokio::task::spawn_blocking(move || {
+                    chunk
+                        .into_iter()
+                        .map(|(path, partial_uri)| {
+                            let document = converter.convert(&path, partial_uri);
+                            (path, document)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut documents = Vec::with_capacity(files.len());
+        for task in tasks {
+            documents.extend(task.await.context("could not convert markdown documents")?);
+        }
+
+        for (file_path, document) in documents {
+            // documents that cannot be converted are not built, and fail the build
+            let mut markdown_content = match document {
+                Ok(document) => document,
                 Err(e) => {
-                    eprintln!("{}", e);
+                    let diagnostic = Diagnostic::error(&file_path, format!("{e:#}"));
+                    self.cache.diagnostics.push(diagnostic);
                     continue;
                 }
             };
-            let mut path = file.path;
+            markdown_content.path = file_path.clone();
+            let mut path = file_path;
             // remove the filename
             path.pop();
 
@@ -477,24 +1159,278 @@ impl Website {
             collection.collection.push(markdown_content);
         }
 
+        let images = ImageResolver::new(self.image_roots())
+            .with_processor(self.image_processor())
+            .with_placeholders(self.placeholders());
+        let diagnostics = Self::process_images(&images, &markdown_config, &mut collections);
+        self.cache.diagnostics.extend(diagnostics);
+        if let Some(Err(e)) = images.placeholders().map(Placeholders::save) {
+            eprintln!("{e:?}");
+        }
+        let diagnostics = Self::resolve_links(posts_folder, &mut collections);
+        self.cache.diagnostics.extend(diagnostics);
+
         self.cache.collections = Some(collections.clone());
         Ok(collections)
     }
 
-    fn partial_uri(&self, path: &Path) -> String {
-        path.to_string_lossy()
-            .to_string()
-            // we leave only the relative path after the `posts_folder` to avoid
-            // creating a url with a local path (e.g. $HOME/my_site/posts)
-            .replace(
-                self.posts_folder
-                    .as_ref()
-                    .unwrap()
-                    .to_string_lossy()
-                    .as_ref(),
-                "",
+    /// documents using images that do not exist are not built, and fail the build, when
+    /// the images are read (dimensions, variants or placeholders)
+    fn process_images(
+        images: &ImageResolver,
+        config: &MarkdownConfiguration,
+        collections: &mut HashMap<String, MarkdownCollection>,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for collection in collections.values_mut() {
+            collection.collection.retain_mut(|doc| {
+                let (html_content, infos) =
+                    match images.process(&doc.html_content, &doc.path, &config.images) {
+                        Ok(processed) => processed,
+                        Err(e) => {
+                            diagnostics.push(Diagnostic::error(&doc.path, format!("{e:#}")));
+                            return false;
+                        }
+                    };
+                doc.cover = match doc
+                    .header
+                    .get("cover_image")
+                    .and_then(|src| images.info(src, &doc.path))
+                {
+                    Some(Ok(cover)) => Some(cover),
+                    Some(Err(e)) if images.requires_files(&config.images) => {
+                        diagnostics.push(Diagnostic::error(&doc.path, format!("{e:#}")));
+                        return false;
+                    }
+                    Some(Err(_)) | None => None,
+                };
+                doc.html_content = html_content;
+                doc.images = infos;
+                doc.summary = images
+                    .process(&doc.summary, &doc.path, &config.images)
+                    .map(|(summary, _)| summary)
+                    .unwrap_or_else(|_| doc.summary.clone());
+                true
+            });
+        }
+        diagnostics
+    }
+
+    /// links between documents can only be resolved once every document is known
+    fn resolve_links(
+        posts_folder: &Path,
+        collections: &mut HashMap<String, MarkdownCollection>,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let documents = || collections.values().flat_map(|c| &c.collection);
+        let resolver = LinkResolver::new(
+            posts_folder,
+            documents().map(|doc| (doc.path.clone(), doc.partial_uri.clone())),
+        );
+        let wikilink_resolver = WikiLinkResolver::new(documents().map(|doc| {
+            (
+                doc.path.as_path(),
+                doc.header.get("title").map(|t| t.as_str()),
+                doc.partial_uri.as_str(),
+                doc.html_content.as_str(),
             )
-            .replace("md", "html")
+        }));
+
+        // source path -> documents linking to it
+        let mut backlinks: HashMap<PathBuf, Vec<Backlink>> = HashMap::new();
+
+        for doc in collections.values_mut().flat_map(|c| &mut c.collection) {
+            let (html_content, missing) = resolver.rewrite(&doc.html_content, &doc.path);
+            for link in missing {
+                diagnostics.push(Diagnostic::warning(
+                    &doc.path,
+                    format!("broken link to {link}"),
+                ));
+            }
+
+            let (html_content, mut targets, missing) = wikilink_resolver.rewrite(&html_content);
+            for link in missing {
+                // targets cannot have a `#`, only headings do
+                let message = match link.contains('#') {
+                    true => format!(
+                        "wiki-link to {link} points to a heading without an id, heading ids are \
+                         added with `[markdown.headings] ids = true`"
+                    ),
+                    false => format!("broken wiki-link to {link}"),
+                };
+                diagnostics.push(Diagnostic::warning(&doc.path, message));
+            }
+            targets.sort();
+            targets.dedup();
+            targets.retain(|t| t != &doc.path);
+
+            let backlink = Backlink {
+                title: doc.header.get("title").cloned().unwrap_or_else(|| {
+                    doc.path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string()
+                }),
+                partial_uri: doc.partial_uri.clone(),
+            };
+            for target in &targets {
+                backlinks
+                    .entry(target.clone())
+                    .or_default()
+                    .push(backlink.clone());
+            }
+
+            doc.html_content = html_content;
+            let summary = resolver.rewrite(&doc.summary, &doc.path).0;
+            doc.summary = wikilink_resolver.rewrite(&summary).0;
+        }
+
+        for doc in collections.values_mut().flat_map(|c| &mut c.collection) {
+            let mut links = backlinks.remove(&doc.path).unwrap_or_default();
+            links.sort_by(|a, b| a.title.cmp(&b.title));
+            doc.backlinks = links;
+        }
+        diagnostics
+    }
+
+    /// publishes the photos of the gallery folder, along with their thumbnails, galleries
+    /// are listed under their folder name, which cannot hide anything else of the state
+    pub fn build_galleries(
+        &mut self,
+        collections: &HashMap<String, MarkdownCollection>,
+        output: &Path,
+    ) -> Result<()> {
+        let folder = match &self.gallery_folder {
+            Some(folder) => folder,
+            None => return Ok(()),
+        };
+        let (gallery, images) = self
+            .configuration
+            .as_ref()
+            .map(|c| (c.gallery.clone(), c.images.clone()))
+            .unwrap_or_default();
+        let placeholders = self.placeholders();
+        let mut reserved: Vec<String> = self.build_default_state().into_keys().collect();
+        reserved.extend(collections.keys().cloned());
+        reserved.push("content".into());
+        if self.cache.data_files.is_some() {
+            reserved.push("data".into());
+        }
+
+        let (galleries, diagnostics) =
+            GalleryBuilder::new(&gallery, &images, &self.cache_folder(), output)
+                .with_placeholders(placeholders.as_ref())
+                .with_reserved_names(reserved)
+                .build(folder)?;
+        self.cache.galleries = galleries;
+        self.cache.diagnostics.extend(diagnostics);
+
+        if let Some(Err(e)) = placeholders.as_ref().map(Placeholders::save) {
+            eprintln!("{e:?}");
+        }
+        Ok(())
+    }
+
+    /// folders absolute image urls are looked up in
+    fn image_roots(&self) -> Vec<PathBuf> {
+        [self.posts_folder.as_ref(), self.static_folder.as_ref()]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// cache folder of the output being built
+    fn cache_folder(&self) -> PathBuf {
+        match &self.output_folder {
+            Some(output) => cache::cache_folder(output),
+            None => PathBuf::from(CACHE_FOLDER),
+        }
+    }
+
+    fn placeholders(&self) -> Option<Placeholders> {
+        let enabled = self.configuration.as_ref()?.images.placeholders;
+        enabled.then(|| Placeholders::load(&self.cache_folder()))
+    }
+
+    /// None unless `[images]` is enabled and we know where the output goes
+    fn image_processor(&self) -> Option<ImageProcessor> {
+        let config = self.configuration.as_ref()?.images.clone();
+        if !config.enabled {
+            return None;
+        }
+        Some(ImageProcessor::new(
+            config,
+            self.image_roots(),
+            self.cache_folder(),
+            self.output_folder.clone()?,
+        ))
+    }
+
+    /// builder of the pages, which skips the ones the build cache has
+    fn new_builder(
+        &mut self,
+        collections: &HashMap<String, MarkdownCollection>,
+        output: &Path,
+    ) -> Builder {
+        let state = self.build_state(collections);
+        let minify = self.minify_html();
+        // each template adds itself and the state it reads to the key
+        let pages = self.cache.build.as_ref().map(|cache| {
+            let key = CacheKey::new().with([minify as u8]).finish();
+            cache.pages(key)
+        });
+        Builder::new(state, output.to_path_buf(), minify).with_pages(pages)
+    }
+
+    /// key of what converting a document reads besides the document itself
+    fn documents_key(&self) -> String {
+        CacheKey::new()
+            .with_json(&self.configuration)
+            .with_state(&self.build_default_state())
+            .with_folder(&self.shortcodes_folder())
+            .with_folder(&self.admonitions_folder())
+            .finish()
+    }
+
+    /// saves the build cache for the next run, once the pages are written
+    pub fn save_cache(&self) -> Result<()> {
+        match &self.cache.build {
+            Some(cache) => cache.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// `[html] minify`, which follows the production flag when unset, never while serving
+    fn minify_html(&self) -> bool {
+        let minify = self.configuration.as_ref().and_then(|c| c.html.minify);
+        !self.serving && minify.unwrap_or(self.production)
+    }
+
+    fn shortcodes_folder(&self) -> PathBuf {
+        self.template_folder.join(SHORTCODES_FOLDER)
+    }
+
+    fn admonitions_folder(&self) -> PathBuf {
+        self.template_folder.join(ADMONITIONS_FOLDER)
+    }
+
+    fn partial_uri(&self, path: &Path) -> String {
+        // we leave only the relative path after the `posts_folder` to avoid
+        // creating a url with a local path (e.g. $HOME/my_site/posts)
+        let relative = self
+            .posts_folder
+            .as_ref()
+            .and_then(|folder| path.strip_prefix(folder).ok())
+            .unwrap_or(path)
+            .with_extension("html");
+
+        let segments: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        format!("/{}", segments.join("/"))
     }
 
     /// We need to transform all the information we build about the collections to the
@@ -529,6 +1465,16 @@ impl Website {
         state.insert("render".into(), TinyLangType::Function(render));
         state.insert("sort_by_key".into(), TinyLangType::Function(sort_by_key));
         state.insert("reverse".into(), TinyLangType::Function(reverse));
+        state.insert("resize_image".into(), TinyLangType::Function(resize_image));
+        state.insert("asset".into(), TinyLangType::Function(asset));
+        state.insert(
+            "asset_integrity".into(),
+            TinyLangType::Function(asset_integrity),
+        );
+        assets::insert_into_state(&self.cache.assets, &self.cache.asset_integrity, &mut state);
+        if let Some(processor) = self.image_processor() {
+            processor.insert_into_state(&mut state);
+        }
         state
     }
 
@@ -536,7 +1482,14 @@ impl Website {
         let mut state = self.build_default_state();
         // passes all the collections state as well so users can use it for
         // things like pagination
-        state.extend(self.build_collection_state(collections).into_iter());
+        state.extend(self.build_collection_state(collections));
+        if let Some(data_files) = self.cache.data_files.as_ref() {
+            state.insert("data".into(), data::as_tinylang_state(data_files).into());
+        }
+        // galleries with a reserved name were left out by `build_galleries`
+        for (name, gallery) in &self.cache.galleries {
+            state.insert(name.clone(), gallery.as_tinylang_state().into());
+        }
         self.cache.state = Some(state.clone());
         state
     }
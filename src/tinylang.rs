@@ -1,7 +1,61 @@
+use regex::Regex;
+use std::collections::HashSet;
 use std::fs;
+use std::sync::OnceLock;
 use tinylang::eval;
 use tinylang::types::{FuncArguments, State, TinyLangType};
 
+fn identifier_regex() -> &'static Regex {
+    static IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+    IDENTIFIER.get_or_init(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap())
+}
+
+/// `render` calls, the path is captured when it is a string literal
+fn render_call_regex() -> &'static Regex {
+    static RENDER_CALL: OnceLock<Regex> = OnceLock::new();
+    RENDER_CALL.get_or_init(|| Regex::new(r#"\brender\s*\(\s*(?:'([^']*)'|"([^"]*)")?"#).unwrap())
+}
+
+/// The part of the state a template can read, which is what `eval` gets a copy of for
+/// each page: the names the template mentions, and the `_` prefixed keys functions read.
+/// `render` evaluates other templates with the same state, templates calling it get all
+/// of it.
+pub fn state_for_template(template: &str, state: &State) -> State {
+    let names: HashSet<&str> = identifier_regex()
+        .find_iter(template)
+        .map(|m| m.as_str())
+        .collect();
+    if names.contains("render") {
+        return state.clone();
+    }
+
+    state
+        .iter()
+        .filter(|(key, _)| key.starts_with('_') || names.contains(key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Paths and contents of the templates the template renders with `render`, and of the
+/// ones they render. None when a path is not a string literal, they cannot be known
+/// before rendering.
+pub fn rendered_templates(template: &str) -> Option<Vec<(String, String)>> {
+    let mut rendered: Vec<(String, String)> = Vec::new();
+    let mut pending = vec![template.to_string()];
+    while let Some(contents) = pending.pop() {
+        for call in render_call_regex().captures_iter(&contents) {
+            let path = call.get(1).or(call.get(2))?.as_str();
+            if rendered.iter().any(|(p, _)| p == path) {
+                continue;
+            }
+            let contents = fs::read_to_string(path).unwrap_or_default();
+            pending.push(contents.clone());
+            rendered.push((path.to_string(), contents));
+        }
+    }
+    Some(rendered)
+}
+
 /// exposes render as a function in the template itself.
 pub fn render(arguments: FuncArguments, state: &State) -> TinyLangType {
     if arguments.is_empty() {
@@ -65,3 +119,43 @@ pub fn reverse(arguments: FuncArguments, _state: &State) -> TinyLangType {
 
     TinyLangType::Vec(collection)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_for_template_keeps_what_it_can_read() {
+        let mut state = State::new();
+        state.insert("website_name".into(), "squid".into());
+        state.insert("posts".into(), TinyLangType::Vec(Vec::new()));
+        state.insert("_assets".into(), TinyLangType::Nil);
+        state.insert("render".into(), TinyLangType::Function(render));
+
+        let template = "<h1>{{ website_name }}</h1>{{ content.title }}";
+        let mut keys: Vec<String> = state_for_template(template, &state).into_keys().collect();
+        keys.sort();
+        assert_eq!(vec!["_assets", "website_name"], keys);
+
+        let template = "{{ render('tpl/_header.template') }}";
+        assert_eq!(state.len(), state_for_template(template, &state).len());
+    }
+
+    #[test]
+    fn test_rendered_templates_follow_render_calls() {
+        let tempdir = tempdir::TempDir::new("templates").unwrap();
+        let header = tempdir.path().join("_header.template");
+        let nav = tempdir.path().join("_nav.template");
+        fs::write(&header, format!("{{{{ render('{}') }}}}", nav.display())).unwrap();
+        fs::write(&nav, "<nav></nav>").unwrap();
+
+        let template = format!("{{{{ render(\"{}\") }}}}", header.display());
+        let rendered = rendered_templates(&template).unwrap();
+        let paths: Vec<&str> = rendered.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(vec![header.to_str().unwrap(), nav.to_str().unwrap()], paths);
+        assert_eq!("<nav></nav>", rendered[1].1);
+
+        assert!(rendered_templates("{{ render(page) }}").is_none());
+        assert!(rendered_templates("{{ title }}").unwrap().is_empty());
+    }
+}
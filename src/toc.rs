@@ -0,0 +1,174 @@
+//! Heading anchors and table of contents of markdown documents.
+
+use crate::config::HeadingsConfiguration;
+use crate::html::{decode_entities, strip_tags};
+use crate::slug::slugify;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use tinylang::types::{State, TinyLangType};
+
+/// an entry of the table of contents, headings deeper than this one are its children
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    /// heading content without any html tags
+    pub title: String,
+    /// id of the heading, only when ids are added to the html
+    pub id: Option<String>,
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        state.insert("level".into(), TinyLangType::Numeric(self.level as f64));
+        state.insert("title".into(), self.title.clone().into());
+        if let Some(id) = &self.id {
+            state.insert("id".into(), id.clone().into());
+        }
+        state.insert(
+            "children".into(),
+            TinyLangType::Vec(
+                self.children
+                    .iter()
+                    .map(|c| TinyLangType::Object(c.as_tinylang_state()))
+                    .collect(),
+            ),
+        );
+        state
+    }
+}
+
+fn heading_regex() -> &'static Regex {
+    static HEADING: OnceLock<Regex> = OnceLock::new();
+    HEADING.get_or_init(|| Regex::new(r"(?s)<h([1-6])>(.*?)</h[1-6]>").unwrap())
+}
+
+/// generates a unique id for every heading, adding them to the html when configured,
+/// and returns the table of contents
+pub fn process_headings(html: &str, config: &HeadingsConfiguration) -> (String, Vec<TocEntry>) {
+    let mut used_ids = HashSet::new();
+    let mut headings = Vec::new();
+
+    let html = heading_regex().replace_all(html, |caps: &Captures| {
+        let level: u8 = caps[1].parse().unwrap();
+        let content = &caps[2];
+        let title = strip_tags(content).trim().to_string();
+
+        let id = unique_id(&mut used_ids, &slugify(&decode_entities(&title)));
+        if level <= config.toc_depth {
+            headings.push((level, title, config.ids.then(|| id.clone())));
+        }
+
+        if !config.ids {
+            return caps[0].to_string();
+        }
+
+        if config.anchor_links {
+            format!(r##"<h{level} id="{id}">{content} <a class="heading-anchor" href="#{id}">#</a></h{level}>"##)
+        } else {
+            format!(r#"<h{level} id="{id}">{content}</h{level}>"#)
+        }
+    });
+
+    (html.to_string(), nest(headings))
+}
+
+/// same heading text gets a `-1`, `-2`... suffix
+fn unique_id(used_ids: &mut HashSet<String>, slug: &str) -> String {
+    let slug = if slug.is_empty() { "section" } else { slug };
+    let mut id = slug.to_string();
+    let mut suffix = 0;
+    while !used_ids.insert(id.clone()) {
+        suffix += 1;
+        id = format!("{slug}-{suffix}");
+    }
+    id
+}
+
+/// builds the tree of headings, a heading is a child of the closest previous
+/// heading with a lower level
+fn nest(headings: Vec<(u8, String, Option<String>)>) -> Vec<TocEntry> {
+    let mut roots: Vec<TocEntry> = Vec::new();
+    // path of the entries currently open, from the root to the deepest one
+    let mut open: Vec<TocEntry> = Vec::new();
+
+    for (level, title, id) in headings {
+        while open.last().map(|e| e.level >= level).unwrap_or(false) {
+            close_last(&mut open, &mut roots);
+        }
+        open.push(TocEntry {
+            level,
+            title,
+            id,
+            children: Vec::new(),
+        });
+    }
+
+    while !open.is_empty() {
+        close_last(&mut open, &mut roots);
+    }
+
+    roots
+}
+
+fn close_last(open: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>) {
+    let entry = open.pop().unwrap();
+    match open.last_mut() {
+        Some(parent) => parent.children.push(entry),
+        None => roots.push(entry),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adds_unique_ids_and_anchors() {
+        let config = HeadingsConfiguration {
+            ids: true,
+            anchor_links: true,
+            ..Default::default()
+        };
+        let html = "<h1>Intro &amp; more</h1><h2>Setup</h2><h2>Setup</h2><h2>Setup 1</h2>";
+
+        let (html, toc) = process_headings(html, &config);
+
+        assert_eq!(Some("intro-more".to_string()), toc[0].id);
+        assert!(html.contains(r#"<h1 id="intro-more">Intro &amp; more <a"#));
+        assert!(html.contains(
+            r##"<h2 id="setup">Setup <a class="heading-anchor" href="#setup">#</a></h2>"##
+        ));
+        assert!(html.contains(r#"<h2 id="setup-1">"#));
+        assert!(html.contains(r#"<h2 id="setup-1-1">"#));
+    }
+
+    #[test]
+    fn test_leaves_html_untouched_without_ids() {
+        let html = "<h1>Title</h1>";
+        let (output, toc) = process_headings(html, &HeadingsConfiguration::default());
+        assert_eq!(html, output);
+        // there is nothing to link to
+        assert_eq!(None, toc[0].id);
+    }
+
+    #[test]
+    fn test_nested_toc_respects_depth() {
+        let config = HeadingsConfiguration {
+            toc_depth: 2,
+            ..Default::default()
+        };
+        let html = "<h1>A</h1><h2><code>B</code></h2><h3>ignored</h3><h2>C</h2><h1>D</h1>";
+
+        let (_, toc) = process_headings(html, &config);
+
+        assert_eq!(2, toc.len());
+        assert_eq!(2, toc[0].children.len());
+        assert_eq!("B", toc[0].children[0].title);
+        assert!(toc[0].children[0].children.is_empty());
+        assert_eq!("D", toc[1].title);
+    }
+}
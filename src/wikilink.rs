@@ -0,0 +1,198 @@
+//! Obsidian style `[[Note Title]]` and `[[note|alias]]` links between documents.
+//!
+//! markdown leaves wiki-links as plain text, so they are resolved on the generated html
+//! once every document of every collection is known. A link targets a document by its
+//! `title` or by its file name without the extension, ignoring case, and may point to
+//! a heading with `[[Note#Heading]]`, which needs the heading ids.
+
+use crate::html::decode_entities;
+use crate::slug::slugify;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tinylang::types::State;
+
+/// code is skipped by matching it first and leaving it untouched
+fn wikilink_regex() -> &'static Regex {
+    static WIKILINK: OnceLock<Regex> = OnceLock::new();
+    WIKILINK.get_or_init(|| {
+        Regex::new(
+            r"(?s)<pre[^>]*>.*?</pre>|<code[^>]*>.*?</code>|\[\[([^\[\]|#<>]+)(?:#([^\[\]|<>]+))?(?:\|([^\[\]<>]+))?\]\]",
+        )
+        .unwrap()
+    })
+}
+
+fn id_regex() -> &'static Regex {
+    static ID: OnceLock<Regex> = OnceLock::new();
+    ID.get_or_init(|| Regex::new(r#"\sid="([^"]*)""#).unwrap())
+}
+
+/// a document linking to another one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backlink {
+    pub title: String,
+    pub partial_uri: String,
+}
+
+impl Backlink {
+    pub fn as_tinylang_state(&self) -> State {
+        let mut state = State::new();
+        state.insert("title".into(), self.title.clone().into());
+        state.insert("partial_uri".into(), self.partial_uri.clone().into());
+        state
+    }
+}
+
+pub struct WikiLinkResolver {
+    /// lowercase title or file stem -> (source path, url of the generated page)
+    documents: HashMap<String, (PathBuf, String)>,
+    /// source path -> ids of the html, headings can only be linked to when they have one
+    ids: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl WikiLinkResolver {
+    /// takes the (source path, title, url, html) of every document, titles win over file
+    /// names when both match
+    pub fn new<'a>(
+        documents: impl IntoIterator<Item = (&'a Path, Option<&'a str>, &'a str, &'a str)>,
+    ) -> Self {
+        let documents: Vec<_> = documents.into_iter().collect();
+        let mut by_name = HashMap::new();
+        let ids = documents
+            .iter()
+            .map(|(path, _, _, html)| {
+                let ids = id_regex()
+                    .captures_iter(html)
+                    .map(|id| id[1].to_string())
+                    .collect();
+                (path.to_path_buf(), ids)
+            })
+            .collect();
+
+        for (path, _, uri, _) in &documents {
+            if let Some(stem) = path.file_stem() {
+                by_name.insert(
+                    stem.to_string_lossy().to_lowercase(),
+                    (path.to_path_buf(), uri.to_string()),
+                );
+            }
+        }
+        for (path, title, uri, _) in &documents {
+            if let Some(title) = title {
+                by_name.insert(
+                    title.trim().to_lowercase(),
+                    (path.to_path_buf(), uri.to_string()),
+                );
+            }
+        }
+
+        Self {
+            documents: by_name,
+            ids,
+        }
+    }
+
+    /// replaces the wiki-links of the html by links to the documents, returning the new
+    /// html, the source path of the documents it links to and the links we could not
+    /// resolve, `Note#Heading` when only the heading has no id
+    pub fn rewrite(&self, html: &str) -> (String, Vec<PathBuf>, Vec<String>) {
+        let mut targets = Vec::new();
+        let mut missing = Vec::new();
+
+        let html = wikilink_regex().replace_all(html, |caps: &Captures| {
+            let target = match caps.get(1) {
+                Some(t) => t.as_str(),
+                None => return caps[0].to_string(),
+            };
+            let heading = caps.get(2).map(|h| h.as_str());
+            // the text is already escaped by markdown
+            let label = caps.get(3).map(|l| l.as_str()).unwrap_or(target).trim();
+
+            match self
+                .documents
+                .get(&decode_entities(target.trim()).to_lowercase())
+            {
+                Some((path, uri)) => {
+                    targets.push(path.clone());
+                    let mut anchor = String::new();
+                    if let Some(heading) = heading {
+                        let id = slugify(&decode_entities(heading));
+                        if !self.ids.get(path).is_some_and(|ids| ids.contains(&id)) {
+                            missing.push(format!("{}#{}", target.trim(), heading.trim()));
+                        }
+                        anchor = format!("#{id}");
+                    }
+                    format!(r#"<a class="wikilink" href="{uri}{anchor}">{label}</a>"#)
+                }
+                None => {
+                    missing.push(target.trim().to_string());
+                    format!(r#"<span class="wikilink broken">{label}</span>"#)
+                }
+            }
+        });
+
+        (html.to_string(), targets, missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> WikiLinkResolver {
+        WikiLinkResolver::new([
+            (
+                Path::new("notes/rust-tips.md"),
+                Some("Rust & Tips"),
+                "/notes/rust-tips.html",
+                "<h1>Tips</h1>",
+            ),
+            (
+                Path::new("notes/todo.md"),
+                None,
+                "/notes/todo.html",
+                r#"<h2 id="next-week">Next Week</h2>"#,
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_resolves_by_title_and_file_name() {
+        let html = "<p>[[rust &amp; tips]], [[Todo|my list]] and [[Todo#Next Week]]</p>";
+
+        let (html, targets, missing) = resolver().rewrite(html);
+
+        assert!(html
+            .contains(r#"<a class="wikilink" href="/notes/rust-tips.html">rust &amp; tips</a>"#));
+        assert!(html.contains(r#"<a class="wikilink" href="/notes/todo.html">my list</a>"#));
+        assert!(html.contains(r#"href="/notes/todo.html#next-week">Todo</a>"#));
+        assert_eq!(3, targets.len());
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn test_missing_targets_and_code() {
+        let html = "<p>[[Nope]]</p><pre><code>[[Todo]]</code></pre><p><code>[[Todo]]</code></p>";
+
+        let (output, targets, missing) = resolver().rewrite(html);
+
+        assert!(output.starts_with(r#"<p><span class="wikilink broken">Nope</span></p>"#));
+        assert!(output.ends_with("<pre><code>[[Todo]]</code></pre><p><code>[[Todo]]</code></p>"));
+        assert!(targets.is_empty());
+        assert_eq!(vec!["Nope"], missing);
+    }
+
+    #[test]
+    fn test_headings_without_ids_are_missing() {
+        let html = "<p>[[Todo#Next Week]] [[Rust &amp; Tips#Tips]]</p>";
+
+        let (output, targets, missing) = resolver().rewrite(html);
+
+        assert!(output.contains(r#"href="/notes/rust-tips.html#tips">"#));
+        assert_eq!(2, targets.len());
+        assert_eq!(vec!["Rust &amp; Tips#Tips"], missing);
+    }
+}
@@ -5,10 +5,9 @@ use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Child, Command};
 use std::time::Duration;
 use tempdir::TempDir;
-use tokio::signal;
 
 fn read_folder_contents(folder_path: &Path) -> HashMap<String, String> {
     let mut contents = HashMap::new();
@@ -37,8 +36,7 @@ fn read_folder_contents(folder_path: &Path) -> HashMap<String, String> {
 fn test_creates_basic_output() {
     let tempdir = TempDir::new("output").unwrap();
 
-    Command::cargo_bin("squid")
-        .unwrap()
+    Command::new(assert_cmd::cargo::cargo_bin!("squid"))
         .arg("--template-folder")
         .arg("tests/templates")
         .arg("--output-folder")
@@ -73,6 +71,73 @@ fn test_creates_basic_output() {
     }
 }
 
+#[test]
+fn test_cleans_only_generated_files() {
+    let tempdir = TempDir::new("output").unwrap();
+    fs::write(tempdir.path().join("CNAME"), "example.com").unwrap();
+
+    Command::new(assert_cmd::cargo::cargo_bin!("squid"))
+        .arg("--template-folder")
+        .arg("tests/templates")
+        .arg("--output-folder")
+        .arg(tempdir.path())
+        .arg("--markdown-folder")
+        .arg("tests/markdown")
+        .arg("--template-variables")
+        .arg("tests/config.toml")
+        .arg("--static-resources")
+        .arg("tests/static")
+        .assert()
+        .success();
+    assert!(tempdir.path().join("index.html").exists());
+
+    Command::new(assert_cmd::cargo::cargo_bin!("squid"))
+        .arg("clean")
+        .arg("--output-folder")
+        .arg(tempdir.path())
+        .assert()
+        .success();
+
+    let remaining: Vec<String> = read_folder_contents(tempdir.path()).into_keys().collect();
+    assert_eq!(vec!["CNAME".to_string()], remaining);
+}
+
+#[test]
+fn test_fails_on_missing_images() {
+    let tempdir = TempDir::new("output").unwrap();
+    let markdown = TempDir::new("markdown").unwrap();
+    let posts = markdown.path().join("posts");
+    fs::create_dir_all(&posts).unwrap();
+    fs::write(
+        posts.join("broken.md"),
+        "---\ntitle: Broken\n---\n![cat](missing-cat.png)",
+    )
+    .unwrap();
+    let config = markdown.path().join("config.toml");
+    let mut contents = fs::read_to_string("tests/config.toml").unwrap();
+    contents.push_str("[markdown.images]\ndimensions = true\n");
+    fs::write(&config, contents).unwrap();
+
+    let output = Command::new(assert_cmd::cargo::cargo_bin!("squid"))
+        .arg("--template-folder")
+        .arg("tests/templates")
+        .arg("--output-folder")
+        .arg(tempdir.path())
+        .arg("--markdown-folder")
+        .arg(markdown.path())
+        .arg("--template-variables")
+        .arg(&config)
+        .arg("--no-cache")
+        .assert()
+        .failure()
+        .get_output()
+        .clone();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("broken.md"));
+    assert!(stderr.contains("missing-cat.png"));
+}
+
 #[tokio::test]
 async fn test_watches() {
     let tempdir = TempDir::new("output").unwrap();
@@ -86,8 +151,7 @@ async fn test_watches() {
         .unwrap()
         .to_string();
 
-    let cargo_bin = Command::cargo_bin("squid")
-        .unwrap()
+    let cargo_bin = Command::new(assert_cmd::cargo::cargo_bin!("squid"))
         .arg("--template-folder")
         .arg("tests/templates")
         .arg("--output-folder")
@@ -114,15 +178,60 @@ async fn test_watches() {
     .unwrap();
 
     assert!(result);
-    kill_child(&cargo_bin.id().to_string())
+    kill_child(cargo_bin)
+}
+
+#[tokio::test]
+async fn test_watch_resolves_wikilinks_to_retitled_notes() {
+    let tempdir = TempDir::new("output").unwrap();
+    let markdown = TempDir::new("markdown").unwrap();
+    let posts = markdown.path().join("posts");
+    fs::create_dir_all(&posts).unwrap();
+    fs::write(posts.join("a.md"), "---\ntitle: A\n---\nsee [[Foo]]\n").unwrap();
+    fs::write(posts.join("b.md"), "---\ntitle: Bar\n---\nbar\n").unwrap();
+
+    let cargo_bin = Command::new(assert_cmd::cargo::cargo_bin!("squid"))
+        .arg("--template-folder")
+        .arg("tests/templates")
+        .arg("--output-folder")
+        .arg(tempdir.path())
+        .arg("--markdown-folder")
+        .arg(markdown.path())
+        .arg("--template-variables")
+        .arg("tests/config.toml")
+        .arg("--no-cache")
+        .arg("--watch")
+        .spawn()
+        .unwrap();
+
+    let page = tempdir.path().join("posts/a.html");
+    let contains = |text: &str| fs::read_to_string(&page).is_ok_and(|c| c.contains(text));
+    let drafts = TempDir::new("drafts").unwrap();
+    let draft = drafts.path().join("b.md");
+
+    let resolved = tokio::time::timeout(Duration::from_secs(20), async {
+        while !contains(r#"<span class="wikilink broken">Foo</span>"#) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        // retitled until the watcher, which starts after the first build, sees it
+        while !contains(r#"<a class="wikilink" href="/posts/b.html">Foo</a>"#) {
+            fs::write(&draft, "---\ntitle: Foo\n---\nbar\n").unwrap();
+            fs::rename(&draft, posts.join("b.md")).unwrap();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    })
+    .await
+    .context("a.html did not link to the retitled note before timeout of 20s");
+
+    kill_child(cargo_bin);
+    resolved.unwrap();
 }
 
 #[tokio::test]
 async fn test_webserver() {
     let output_folder = TempDir::new("output").unwrap();
 
-    let cargo_bin = Command::cargo_bin("squid")
-        .unwrap()
+    let cargo_bin = Command::new(assert_cmd::cargo::cargo_bin!("squid"))
         .arg("--template-folder")
         .arg("tests/templates")
         .arg("--output-folder")
@@ -155,13 +264,14 @@ async fn test_webserver() {
 
     assert_eq!(200, resp.status());
 
-    kill_child(&cargo_bin.id().to_string())
+    kill_child(cargo_bin)
 }
 
-fn kill_child(child_id: &str) {
+fn kill_child(mut child: Child) {
     let mut kill = Command::new("kill")
-        .args(["-s", "INT", child_id])
+        .args(["-s", "INT", &child.id().to_string()])
         .spawn()
         .unwrap();
     kill.wait().unwrap();
+    child.wait().unwrap();
 }